@@ -11,6 +11,9 @@ use walkdir::WalkDir;
 use std::fs::File;
 use std::io::Read;
 
+mod schema;
+use schema::parse_segment_size;
+
 struct FnVisitor {
     pub functions: Vec<(syn::ItemFn, String)>
 }
@@ -113,3 +116,47 @@ fn impl_register_devices_macro(ast: &syn::ItemFn, attr_ast: &syn::ExprLit) -> To
 pub fn device(_attr: TokenStream, item: TokenStream) -> TokenStream {
     item
 }
+
+/// Memory schema read at build time from the file in the `THEK_MEM_SCHEMA` environment variable.
+///
+/// Each line of the file is a block: segment size and % of the memory, separated by a comma. Sizes accept `K`, `M` and `G` suffixes, and `MAX` or `MAX-N` for blocks of one single segment. Empty lines and `#` comments are ignored.
+///
+/// Expands to a `&[(usize, u8)]` expression. The schema is not validated here, use it with `check_schema` to validate it at compile time.
+#[proc_macro]
+pub fn mem_schema_file(_item: TokenStream) -> TokenStream {
+    let path = std::env::var("THEK_MEM_SCHEMA").expect("THEK_MEM_SCHEMA environment variable must point to the memory schema file");
+    let path = std::fs::canonicalize(&path).expect("Memory schema file not found");
+    let mut content = String::new();
+    File::open(&path).expect("Failed opening memory schema file")
+        .read_to_string(&mut content).expect("Failed reading memory schema file");
+
+    let mut blocks = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if fields.len() != 2 {
+            panic!("Memory schema line {}: expected segment size and percentage", i + 1);
+        }
+        let size = parse_segment_size(fields[0])
+            .unwrap_or_else(|| panic!("Memory schema line {}: bad segment size '{}'", i + 1, fields[0]));
+        let percentage: u8 = fields[1].parse()
+            .unwrap_or_else(|_| panic!("Memory schema line {}: bad percentage '{}'", i + 1, fields[1]));
+        blocks.push(format!("({}, {}u8)", size, percentage));
+    }
+
+    // Including the file makes cargo rebuild when it changes
+    let path = path.display().to_string();
+    let blocks = blocks.join(", ");
+    let blocks_tokens: proc_macro2::TokenStream = format!("&[{}]", blocks).parse().unwrap();
+    let gen = quote! {
+        {
+            const _: &[u8] = include_bytes!(#path);
+            #blocks_tokens
+        }
+    };
+
+    gen.into()
+}
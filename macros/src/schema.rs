@@ -0,0 +1,19 @@
+//! Memory schema file parsing, kept apart from the proc macros to be tested on the host.
+
+/// Parse a segment size of a memory schema file, and return it as a Rust expression.
+pub fn parse_segment_size(size: &str) -> Option<String> {
+    let size = size.replace(' ', "").to_uppercase();
+    if size == "MAX" {
+        return Some("usize::MAX".to_string());
+    }
+    if let Some(n) = size.strip_prefix("MAX-") {
+        return n.parse::<usize>().ok().map(|n| format!("(usize::MAX - {})", n));
+    }
+    let (number, multiplier) = match size.chars().last()? {
+        'K' => (&size[..size.len() - 1], 1024),
+        'M' => (&size[..size.len() - 1], 1024 * 1024),
+        'G' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (&size[..], 1)
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier).map(|n| format!("{}usize", n))
+}
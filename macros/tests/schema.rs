@@ -0,0 +1,43 @@
+//! Memory schema file parsing tests.
+//!
+//! Proc macro crates can only export macros, so the parser module is included directly.
+
+#[path = "../src/schema.rs"]
+mod schema;
+
+use schema::parse_segment_size;
+
+#[test]
+fn parses_plain_sizes() {
+    assert_eq!(parse_segment_size("256").as_deref(), Some("256usize"));
+    assert_eq!(parse_segment_size(" 1 024 ").as_deref(), Some("1024usize"));
+}
+
+#[test]
+fn parses_suffixes() {
+    assert_eq!(parse_segment_size("4K").as_deref(), Some("4096usize"));
+    assert_eq!(parse_segment_size("128k").as_deref(), Some("131072usize"));
+    assert_eq!(parse_segment_size("2M").as_deref(), Some("2097152usize"));
+    assert_eq!(parse_segment_size("1G").as_deref(), Some("1073741824usize"));
+}
+
+#[test]
+fn parses_max() {
+    assert_eq!(parse_segment_size("MAX").as_deref(), Some("usize::MAX"));
+    assert_eq!(parse_segment_size("max").as_deref(), Some("usize::MAX"));
+    assert_eq!(parse_segment_size("MAX-1").as_deref(), Some("(usize::MAX - 1)"));
+    assert_eq!(parse_segment_size("MAX - 2").as_deref(), Some("(usize::MAX - 2)"));
+}
+
+#[test]
+fn rejects_bad_sizes() {
+    for size in ["", "K", "12X", "-1", "1.5K", "MAX-", "MAX-K", "MAX1", "K4"] {
+        assert_eq!(parse_segment_size(size), None, "Accepted '{}'", size);
+    }
+}
+
+#[test]
+fn rejects_overflow() {
+    assert_eq!(parse_segment_size("99999999999999999999"), None);
+    assert_eq!(parse_segment_size("999999999999G"), None);
+}
@@ -0,0 +1,11 @@
+#!/bin/bash
+# Run the host tests: memory allocator, frame allocator, memory schema and ANSI parser.
+# The project cargo config builds for the kernel target, so cargo runs from outside the project to use the host target instead.
+DIR=$(cd "$(dirname "$0")" && pwd)
+cd / && RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test --test mem "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test --test ansi "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test --test paging "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test,heap-debug --test heap_debug --test mem "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test,alloc-trace --test alloc_trace "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/thek/Cargo.toml" --features test,alloc-profile --test alloc_profile "$@" &&
+    RUSTUP_TOOLCHAIN=nightly cargo test --manifest-path "$DIR/macros/Cargo.toml" "$@"
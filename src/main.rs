@@ -1,20 +1,31 @@
 #![no_main]
 #![no_std]
+#![feature(custom_test_frameworks)]
+#![test_runner(thek::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 use thek::{controllers::{
         stdout::StdoutController,
         port::PortController,
         text::TextController
-    }, devices::{
+    }, logger::LevelFilter, devices::{
         self
     }, devices::text::ansi::AnsiColor, mem::{
-        arch::raw_mem,
+        arch::{
+            raw_mem, add_raw_mem
+        },
+        paging,
         layout::{
             MemBlockSet,
         }
     }
 };
 
+use bootloader::{
+    BootInfo, entry_point,
+    bootinfo::MemoryRegionType
+};
+
 use core::default::Default;
 use std::{
     prelude::v1::*,
@@ -25,26 +36,68 @@ use std::{
     mem::size_of
 };
 
-#[no_mangle]
-extern "C"
-fn _start() -> ! {
+#[cfg(test)]
+mod tests;
+
+entry_point!(kernel_start);
+
+fn kernel_start(boot_info: &'static BootInfo) -> ! {
     thek::cpu::init_cpu();
-    thek::mem::init_small_schema();
+    init_raw_mem(boot_info);
+    thek::mem::init_schema();
     thek::devices::init_devices();
+    thek::logger::init_logger(LevelFilter::Info);
+    thek::logger::add_sink(Box::new(PortController::default()));
     thek::task::init_task();
     thek::cpu::start_cpu();
 
+    #[cfg(test)]
+    test_main();
+
     main();
     print!(".END.");
     loop {}
 }
 
-/*
-TODO: create tests for mem
-- Check used mem after every alloc/dealloc
-- Check if segments are correctly selected depending on requiered size.
-- Check that blocks are not overlaping.
-*/
+/// Fraction of the usable memory reserved for the frame allocator (page tables, DMA buffers, task stacks, etc.), the rest goes to the heap.
+const FRAME_POOL_FRACTION: u64 = 32;
+
+/// Limits of the memory reserved for the frame allocator.
+const MIN_FRAME_POOL_SIZE: u64 = 1024 * 1024;
+const MAX_FRAME_POOL_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Give the usable memory regions to the frame allocator and the heap.
+///
+/// Physical memory is mapped by the bootloader at `physical_memory_offset`, so the heap size follows the RAM given to the machine.
+fn init_raw_mem(boot_info: &'static BootInfo) {
+    unsafe {
+        paging::init_paging(boot_info.physical_memory_offset as usize);
+    }
+    let usable_regions = || boot_info.memory_map.iter().filter(|region| region.region_type == MemoryRegionType::Usable);
+    let usable_size: u64 = usable_regions().map(|region| region.range.end_addr() - region.range.start_addr()).sum();
+    let mut found = false;
+    let mut pending_frames = (usable_size / FRAME_POOL_FRACTION).clamp(MIN_FRAME_POOL_SIZE, MAX_FRAME_POOL_SIZE);
+    for region in usable_regions() {
+        let mut start = region.range.start_addr();
+        let end = region.range.end_addr();
+        // Frames are taken from the beginning of the first regions, they are already page aligned
+        let frames_size = pending_frames.min(end - start);
+        if frames_size > 0 && unsafe { paging::add_frames(start, frames_size) } {
+            pending_frames -= frames_size;
+            start += frames_size;
+        }
+        if start == end {
+            continue;
+        }
+        // Regions that don't fit in the list are just ignored
+        found |= unsafe {
+            add_raw_mem((boot_info.physical_memory_offset + start) as *mut u8, (end - start) as usize)
+        };
+    }
+    if !found {
+        panic!("No usable memory region found");
+    }
+}
 
 fn main() {
     println!("Hola!");
@@ -94,7 +147,7 @@ fn main() {
     }
     println!();
 
-    let s = String::from("Aix`o mola molt! e's guay!");
+    let s = String::from("Això mola molt! És guai!");
     println!("string = {}", s);
 
     let b = Box::new(1200);
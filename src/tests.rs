@@ -0,0 +1,272 @@
+//! Kernel tests, run inside QEMU with `cargo test`.
+
+use std::{
+    prelude::v1::*,
+    collections::HashMap,
+    fmt::Write
+};
+
+use core::sync::atomic::{
+    AtomicBool, Ordering
+};
+
+use thek::mem::{
+    self,
+    paging::{
+        self, PageFlags, PAGE_SIZE
+    }
+};
+use thek::task::{
+    self, TaskStack
+};
+use thek::logger::{
+    self, LevelFilter, LOG_RING_LEN, LOG_MSG_LEN
+};
+use thek::controllers::port::PortController;
+use thek::sys::KMutex;
+use thek::devices::{
+    self,
+    gfx::{
+        PixelFormat, Rgb
+    },
+    text::{
+        vcon, fbcon,
+        ansi::AnsiColor,
+        psf::{
+            PsfFont, DEFAULT_FONT
+        }
+    }
+};
+
+#[test_case]
+fn box_alloc() {
+    let b = Box::new(1200);
+    assert_eq!(*b, 1200);
+}
+
+#[test_case]
+fn vec_grows() {
+    let mut v = Vec::new();
+    for i in 0..1000usize {
+        v.push(i);
+    }
+    assert_eq!(v.iter().sum::<usize>(), 999 * 1000 / 2);
+}
+
+#[test_case]
+fn string_push() {
+    let mut s = String::from("Això mola molt!");
+    s.push_str(" És guai!");
+    assert_eq!(s, "Això mola molt! És guai!");
+}
+
+#[test_case]
+fn hash_map() {
+    let mut map = HashMap::new();
+    map.insert("nom", "Mar");
+    map.insert("edat", "2");
+    assert_eq!(map.get("nom"), Some(&"Mar"));
+    assert_eq!(map.len(), 2);
+}
+
+#[test_case]
+fn dealloc_frees_segments() {
+    let used = mem::stats().used_segments();
+    let boxes: Vec<Box<[u8; 100]>> = (0..10).map(|_| Box::new([0u8; 100])).collect();
+    assert!(mem::stats().used_segments() > used);
+    drop(boxes);
+    assert_eq!(mem::stats().used_segments(), used);
+}
+
+#[test_case]
+fn ticks_advance() {
+    let t = thek::task::ticks();
+    thek::task::sleep(50);
+    assert!(thek::task::ticks() > t);
+}
+
+#[test_case]
+fn map_and_unmap_page() {
+    let frame = paging::alloc_frame().expect("No frames");
+    let page = paging::reserve_virt(PAGE_SIZE).expect("No virtual window");
+    unsafe {
+        paging::map_page(page, frame, PageFlags::DATA).unwrap();
+        // Mapping may take frames for new page tables
+        let frames = paging::free_frames();
+        assert_eq!(paging::translate(page + 8), Some(frame + 8));
+        // Same frame seen through the physical memory mapping
+        *(page as *mut u64) = 0x1234_5678;
+        assert_eq!(*(paging::phys_to_virt(frame) as *const u64), 0x1234_5678);
+        paging::set_page_flags(page, PageFlags::READ_ONLY).unwrap();
+        assert_eq!(paging::unmap_page(page).unwrap(), frame);
+        assert_eq!(paging::translate(page), None);
+        paging::free_frame(frame);
+        assert_eq!(paging::free_frames(), frames + 1);
+    }
+}
+
+#[test_case]
+fn task_stack_has_guard() {
+    let stack = TaskStack::new("guarded", 2 * PAGE_SIZE).expect("No stack");
+    assert_eq!(stack.size(), 2 * PAGE_SIZE);
+    let bottom = stack.bottom() as usize;
+    unsafe {
+        // The whole stack is writable
+        *(bottom as *mut u64) = 1;
+        *((stack.top() as usize - 8) as *mut u64) = 2;
+    }
+    assert!(task::stack_guard_owner(bottom).is_none());
+    assert_eq!(task::stack_guard_owner(bottom - 1).map(|owner| owner.name_str() == "guarded"), Some(true));
+    drop(stack);
+    assert!(task::stack_guard_owner(bottom - 1).is_none());
+}
+
+/// Log sink that keeps what is written to it, and logs once from inside the sink when `NESTED_LOG` is set.
+struct CaptureSink;
+
+static CAPTURED: KMutex<String> = KMutex::new(String::new());
+static NESTED_LOG: AtomicBool = AtomicBool::new(false);
+
+impl Write for CaptureSink {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        CAPTURED.acquire().push_str(s);
+        if NESTED_LOG.swap(false, Ordering::SeqCst) {
+            log::warn!(target: "ltest", "nested");
+        }
+        Ok(())
+    }
+}
+
+/// Run `func` with the capture sink as the only sink, then restore the serial port sink.
+fn with_capture_sink(func: impl FnOnce()) {
+    logger::clear_sinks();
+    CAPTURED.acquire().clear();
+    logger::add_sink(Box::new(CaptureSink));
+    func();
+    logger::clear_sinks();
+    logger::add_sink(Box::new(PortController::default()));
+}
+
+/// Messages in the log ring buffer.
+fn recent_messages() -> Vec<String> {
+    let mut messages = Vec::new();
+    logger::recent_records(|record| messages.push(record.message().to_owned()));
+    messages
+}
+
+#[test_case]
+fn logger_filters_by_module() {
+    with_capture_sink(|| {
+        logger::set_module_level("ltest", LevelFilter::Warn);
+        logger::set_module_level("ltest::net", LevelFilter::Debug);
+        logger::clear_records();
+        log::info!(target: "ltest", "dropped");
+        log::warn!(target: "ltest::disk", "kept 1");
+        log::debug!(target: "ltest::net::tcp", "kept 2");
+        log::trace!(target: "ltest::net", "dropped");
+        // Prefixes only match whole module names
+        log::debug!(target: "ltest::network", "dropped");
+        assert_eq!(recent_messages(), ["kept 1", "kept 2"]);
+    });
+}
+
+#[test_case]
+fn logger_ring_keeps_recent_records() {
+    with_capture_sink(|| {
+        logger::set_module_level("ltest", LevelFilter::Warn);
+        logger::clear_records();
+        for i in 0..(LOG_RING_LEN + 5) {
+            log::warn!(target: "ltest", "{}", i);
+        }
+        let messages = recent_messages();
+        assert_eq!(messages.len(), LOG_RING_LEN);
+        assert_eq!(messages[0], "5");
+        assert_eq!(messages[LOG_RING_LEN - 1], format!("{}", LOG_RING_LEN + 4));
+        // Long messages are truncated
+        logger::clear_records();
+        log::warn!(target: "ltest", "{:1$}", "x", LOG_MSG_LEN + 10);
+        assert_eq!(recent_messages()[0].len(), LOG_MSG_LEN);
+    });
+}
+
+#[test_case]
+fn logger_writes_nested_records_to_sinks() {
+    with_capture_sink(|| {
+        logger::set_module_level("ltest", LevelFilter::Warn);
+        // The sink logs while it's being written, the nested record is written after the current one
+        NESTED_LOG.store(true, Ordering::SeqCst);
+        log::warn!(target: "ltest", "outer");
+        let captured = CAPTURED.acquire().clone();
+        let outer = captured.find("ltest: outer").expect("No outer record");
+        let nested = captured.find("ltest: nested").expect("No nested record");
+        assert!(outer < nested);
+    });
+}
+
+#[test_case]
+fn vcon_key_combos() {
+    let con2 = *vcon::vcon_device(1).unwrap().acquire();
+    let (_, rows) = con2.size().unwrap();
+    // Fill the history with two screens
+    for _ in 0..2 * rows {
+        con2.scroll_up(0, rows - 1, AnsiColor::White, AnsiColor::Black).unwrap();
+    }
+    // Alt+F2
+    assert!(!vcon::handle_scancode(0x38));
+    assert!(vcon::handle_scancode(0x3C));
+    assert!(!vcon::handle_scancode(0xB8));
+    assert_eq!(vcon::active_vcon(), 1);
+    // Shift+PgUp twice, then Shift+PgDn
+    assert!(!vcon::handle_scancode(0x2A));
+    assert!(!vcon::handle_scancode(0xE0));
+    assert!(vcon::handle_scancode(0x49));
+    assert_eq!(vcon::scroll_offset(1), rows);
+    assert!(!vcon::handle_scancode(0xE0));
+    assert!(vcon::handle_scancode(0x49));
+    assert_eq!(vcon::scroll_offset(1), 2 * rows);
+    assert!(!vcon::handle_scancode(0xE0));
+    assert!(vcon::handle_scancode(0x51));
+    assert_eq!(vcon::scroll_offset(1), rows);
+    assert!(!vcon::handle_scancode(0xAA));
+    // Alt+F1
+    assert!(!vcon::handle_scancode(0x38));
+    assert!(vcon::handle_scancode(0x3B));
+    assert!(!vcon::handle_scancode(0xB8));
+    assert_eq!(vcon::active_vcon(), 0);
+}
+
+#[test_case]
+fn psf_rejects_malformed_fonts() {
+    // PSF2 header: magic, version, header size, flags, num glyphs, glyph size, height, width
+    static NO_GLYPHS: [u32; 8] = [0x864A_B572, 0, 32, 0, 0, 16, 16, 8];
+    static OVERFLOW: [u32; 8] = [0x864A_B572, 0, 32, 0, 0xFFFF_FFFF, 0xFFFF_FFFF, 16, 8];
+    for header in [&NO_GLYPHS, &OVERFLOW] {
+        let data = unsafe { core::slice::from_raw_parts(header.as_ptr() as *const u8, 32) };
+        assert!(PsfFont::parse(data).is_err());
+    }
+}
+
+// Switches the screen to graphic mode and back to text mode
+#[test_case]
+fn fbcon_draws_glyphs() {
+    let gfx = devices::get_gfx_device("GFX1").expect("No GFX1");
+    let mode = gfx.unwrap_gfx().set_mode(640, 480, PixelFormat::Xrgb8888).unwrap();
+    assert_eq!((mode.width, mode.height), (640, 480));
+    let con = fbcon::init_fbcon("GFX1", None).unwrap();
+    con.unwrap_text().write(1, 1, AnsiColor::White, AnsiColor::Blue, 'A').unwrap();
+    assert_eq!(con.unwrap_text().read(1, 1).unwrap().0, 'A');
+    // Every pixel of the cell has the text or the background color, as the glyph says
+    let font = PsfFont::parse(DEFAULT_FONT).unwrap();
+    let (width, height) = font.size();
+    let glyph = font.glyph('A');
+    let gfx = gfx.unwrap_gfx();
+    for y in 0..height {
+        for x in 0..width {
+            let color = if glyph.is_set(x, y) { AnsiColor::White } else { AnsiColor::Blue };
+            assert_eq!(gfx.get_pixel(width + x, height + y).unwrap(), Rgb::from(color));
+        }
+    }
+    // Leave the screen as the other tests expect it
+    gfx.text_mode().unwrap();
+    assert!(gfx.mode().is_err());
+}
\ No newline at end of file
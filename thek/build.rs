@@ -0,0 +1,6 @@
+//! Build script.
+
+fn main() {
+    // The memory schema file is read by a proc macro (see `macros::mem_schema_file`), and cargo doesn't track the environment variables read by proc macros
+    println!("cargo:rerun-if-env-changed=THEK_MEM_SCHEMA");
+}
\ No newline at end of file
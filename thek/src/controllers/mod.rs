@@ -14,4 +14,6 @@ pub mod text;
 
 pub mod port;
 
+pub mod term;
+
 pub mod stdout;
\ No newline at end of file
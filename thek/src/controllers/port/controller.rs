@@ -13,7 +13,9 @@ use crate::devices::{
     }
 };
 
-use crate::sys::KError;
+use crate::sys::{
+    KError, Encoding, port_encoding
+};
 
 use alloc::{
     borrow::ToOwned,
@@ -22,7 +24,8 @@ use alloc::{
 
 /// Port controller.
 pub struct PortController {
-    device_id: String
+    device_id: String,
+    encoding: Encoding
 }
 
 impl PortController {
@@ -38,7 +41,8 @@ impl PortController {
     /// We assume the `device_id` is a port device and is already configured.
     pub fn new(device_id: String) -> Self {
         Self {
-            device_id
+            device_id,
+            encoding: port_encoding()
         }
     }
 
@@ -56,7 +60,8 @@ impl PortController {
                 port_dev.config(parity, data_bits, stop_bits, speed)?;
                 return Ok(
                     Self {
-                        device_id
+                        device_id,
+                        encoding: port_encoding()
                     }
                 );
             }
@@ -65,6 +70,16 @@ impl PortController {
     }
 
     //TODO: create "from" constructors for other port types.
+
+    /// Encoding used to write chars to the port.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Set encoding used to write chars to the port.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
 }
 
 impl Default for PortController {
@@ -77,9 +92,11 @@ impl Write for PortController {
     fn write_str(&mut self, s: &str) -> Result<(), Error> {
         if let Ok(device) = Self::get_device(&self.device_id) {
             let port_dev = device.unwrap_port();
-            //TODO: iter as chars and convert into appropiate encoding (configured in the kernel). Otherwise we are always using UTF-8, the str type encoding.
-            for ch in s.as_bytes() {
-                port_dev.write(*ch).unwrap_or_default();
+            let mut buf = [0u8; 4];
+            for ch in s.chars() {
+                for b in self.encoding.encode(ch, &mut buf) {
+                    port_dev.write(*b).unwrap_or_default();
+                }
             }
             Ok(())
         }
@@ -0,0 +1,361 @@
+use core::{
+    fmt::{
+        Write,
+        Error
+    },
+    default::Default
+};
+
+use crate::devices::{
+    self, Device,
+    text::ansi::{
+        AnsiColor, AnsiParser, AnsiAction, AnsiCommand
+    }
+};
+
+use crate::sys::{
+    KError, KMutex, Encoding, port_encoding
+};
+
+use crate::cpu::check_ints;
+
+use crate::task::{
+    ticks, TIMER_PERIOD_SEC
+};
+
+use alloc::{
+    borrow::ToOwned,
+    string::String,
+    collections::BTreeMap
+};
+
+/// Default terminal size, used when the terminal doesn't answer the size query.
+const DEFAULT_TERM_SIZE: (usize, usize) = (80, 25);
+
+/// Time to wait for a terminal reply, in milliseconds.
+const REPLY_TIMEOUT_MS: usize = 200;
+
+/// Size reported by the terminal of each port device, `None` if it didn't answer.
+static TERM_SIZES: KMutex<BTreeMap<String, Option<(usize, usize)>>> = KMutex::new(BTreeMap::new());
+
+/// Terminal controller.
+///
+/// Drives a VT100/ANSI terminal connected to a port device (usually a serial port), offering the same API as [`TextController`](crate::controllers::text::TextController).
+pub struct TermController {
+    cols: usize,
+    rows: usize,
+    x: usize,
+    y: usize,
+    device_id: String,
+    text_color: AnsiColor,
+    bg_color: AnsiColor,
+    saved_xy: (usize, usize),
+    parser: AnsiParser,
+    encoding: Encoding
+}
+
+impl TermController {
+    fn get_device(id: &str) -> Result<Device, KError> {
+        if let Some(d) = devices::get_port_device(id) {
+            Ok(d)
+        }
+        else {
+            Err(KError::Other)
+        }
+    }
+
+    /// We assume the `device_id` is a port device and is already configured.
+    pub fn new(text_color: AnsiColor, bg_color: AnsiColor, device_id: String) -> Result<Self, KError> {
+        Self::get_device(&device_id)?;
+        let mut term = Self::unchecked(text_color, bg_color, device_id);
+        term.set_colors(text_color, bg_color)?;
+        term.set_xy(0, 0)?;
+        Ok(term)
+    }
+
+    /// Create the controller without checking the device nor sending anything. The size is the one reported by the terminal, or the default one.
+    fn unchecked(text_color: AnsiColor, bg_color: AnsiColor, device_id: String) -> Self {
+        let mut term = Self {
+            cols: DEFAULT_TERM_SIZE.0,
+            rows: DEFAULT_TERM_SIZE.1,
+            x: 0, y: 0,
+            device_id,
+            text_color, bg_color,
+            saved_xy: (0, 0),
+            parser: AnsiParser::new(),
+            encoding: port_encoding()
+        };
+        if let Some((cols, rows)) = term.cached_size() {
+            term.cols = cols;
+            term.rows = rows;
+        }
+        term
+    }
+
+    pub fn get_xy(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn set_xy(&mut self, x: usize, y: usize) -> Result<(), KError> {
+        self.x = x.min(self.cols - 1);
+        self.y = y.min(self.rows - 1);
+        let (x, y) = (self.x, self.y);
+        self.send_fmt(format_args!("\x1b[{};{}H", y + 1, x + 1))
+    }
+
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Set text and background colors for the next writes.
+    pub fn set_colors(&mut self, text_color: AnsiColor, bg_color: AnsiColor) -> Result<(), KError> {
+        self.text_color = text_color;
+        self.bg_color = bg_color;
+        self.send_fmt(format_args!("\x1b[0;{};{}m", SgrColor(text_color, false), SgrColor(bg_color, true)))
+    }
+
+    pub fn clear(&mut self) -> Result<(), KError> {
+        self.set_colors(self.text_color, self.bg_color)?;
+        self.send_str("\x1b[2J")?;
+        self.set_xy(0, 0)
+    }
+
+    /// Encoding used to write chars to the terminal.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Set encoding used to write chars to the terminal.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Terminal size, queried only the first time for each device.
+    fn cached_size(&mut self) -> Option<(usize, usize)> {
+        if let Some(size) = TERM_SIZES.acquire().get(&self.device_id) {
+            return *size;
+        }
+        // Ticks don't advance with interrupts disabled, the reply can't be waited for. Query it next time
+        if !check_ints() {
+            return None;
+        }
+        let size = self.query_size();
+        TERM_SIZES.acquire().insert(self.device_id.clone(), size);
+        size
+    }
+
+    /// Ask the terminal for its size, waiting up to [`REPLY_TIMEOUT_MS`] for the reply.
+    ///
+    /// Moves the cursor to the bottom right corner and requests a cursor position report.
+    fn query_size(&mut self) -> Option<(usize, usize)> {
+        self.send_str("\x1b7\x1b[999;999H\x1b[6n\x1b8").ok()?;
+        // Expected reply: ESC [ rows ; cols R
+        let device = Self::get_device(&self.device_id).ok()?;
+        let port_dev = device.unwrap_port();
+        let mut values = [0usize; 2];
+        let mut index = 0;
+        let mut started = false;
+        let timeout_ticks = (REPLY_TIMEOUT_MS as f64 / 1000.0 / TIMER_PERIOD_SEC) as u32 + 1;
+        let start = ticks();
+        loop {
+            while !port_dev.is_ready() {
+                if ticks().wrapping_sub(start) >= timeout_ticks {
+                    return None;
+                }
+                core::hint::spin_loop();
+            }
+            match port_dev.read().ok()? {
+                b'[' => started = true,
+                b';' if started && index == 0 => index = 1,
+                b @ b'0'..=b'9' if started => {
+                    values[index] = values[index] * 10 + (b - b'0') as usize;
+                },
+                b'R' if started && index == 1 => break,
+                0x1b => {},
+                _ => return None
+            }
+        }
+        let (rows, cols) = (values[0], values[1]);
+        if rows > 0 && cols > 0 {
+            Some((cols, rows))
+        }
+        else {
+            None
+        }
+    }
+
+    fn send_str(&self, s: &str) -> Result<(), KError> {
+        let device = Self::get_device(&self.device_id)?;
+        let port_dev = device.unwrap_port();
+        let mut buf = [0u8; 4];
+        for ch in s.chars() {
+            for b in self.encoding.encode(ch, &mut buf) {
+                port_dev.write(*b)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_fmt(&self, args: core::fmt::Arguments) -> Result<(), KError> {
+        let mut writer = PortWriter {
+            term: self,
+            result: Ok(())
+        };
+        core::fmt::write(&mut writer, args).unwrap_or_default();
+        writer.result
+    }
+
+    /// Update the tracked cursor position after sending a char.
+    fn track(&mut self, ch: char) {
+        match ch {
+            '\n' => {
+                self.x = 0;
+                if self.y + 1 < self.rows {
+                    self.y += 1;
+                }
+            },
+            '\r' => {
+                self.x = 0;
+            },
+            '\t' => {
+                self.x = ((self.x / 8 + 1) * 8).min(self.cols - 1);
+            },
+            '\x08' => {
+                if self.x > 0 {
+                    self.x -= 1;
+                }
+            },
+            _ => {
+                self.x += 1;
+                if self.x >= self.cols {
+                    self.x = 0;
+                    if self.y + 1 < self.rows {
+                        self.y += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Update the tracked cursor position after sending an ANSI command.
+    fn track_command(&mut self, cmd: AnsiCommand) {
+        let max_x = self.cols - 1;
+        let max_y = self.rows - 1;
+        match cmd {
+            AnsiCommand::CursorUp(n) => self.y = self.y.saturating_sub(n),
+            AnsiCommand::CursorDown(n) => self.y = (self.y + n).min(max_y),
+            AnsiCommand::CursorForward(n) => self.x = (self.x + n).min(max_x),
+            AnsiCommand::CursorBack(n) => self.x = self.x.saturating_sub(n),
+            AnsiCommand::CursorNextLine(n) => {
+                self.x = 0;
+                self.y = (self.y + n).min(max_y);
+            },
+            AnsiCommand::CursorPrevLine(n) => {
+                self.x = 0;
+                self.y = self.y.saturating_sub(n);
+            },
+            AnsiCommand::CursorColumn(x) => self.x = x.min(max_x),
+            AnsiCommand::CursorRow(y) => self.y = y.min(max_y),
+            AnsiCommand::CursorPosition(x, y) => {
+                self.x = x.min(max_x);
+                self.y = y.min(max_y);
+            },
+            AnsiCommand::ScrollRegion(_, _) => {
+                self.x = 0;
+                self.y = 0;
+            },
+            AnsiCommand::SaveCursor => self.saved_xy = (self.x, self.y),
+            AnsiCommand::RestoreCursor => {
+                self.x = self.saved_xy.0;
+                self.y = self.saved_xy.1;
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Default for TermController {
+    /// Terminal on device SER1. If the device is not available, the controller has the default size and writes fail.
+    fn default() -> Self {
+        let mut term = Self::unchecked(AnsiColor::White, AnsiColor::Black, "SER1".to_owned());
+        term.set_colors(AnsiColor::White, AnsiColor::Black)
+            .and_then(|_| term.set_xy(0, 0))
+            .unwrap_or_default();
+        term
+    }
+}
+
+impl Write for TermController {
+    fn write_str(&mut self, s: &str) -> Result<(), Error> {
+        let mut buf = [0u8; 4];
+        for ch in s.chars() {
+            match self.parser.feed(ch) {
+                AnsiAction::Print(ch) => {
+                    // Text consoles move to the line beginning on newline, do the same here
+                    let out = if ch == '\n' { "\r\n" } else { ch.encode_utf8(&mut buf) };
+                    if self.send_str(out).is_err() {
+                        return Err(Error);
+                    }
+                    self.track(ch);
+                },
+                AnsiAction::Command(cmd) => {
+                    if self.send_str(ch.encode_utf8(&mut buf)).is_err() {
+                        return Err(Error);
+                    }
+                    self.track_command(cmd);
+                },
+                AnsiAction::None => {
+                    if self.send_str(ch.encode_utf8(&mut buf)).is_err() {
+                        return Err(Error);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adapter to send formatted strings to the terminal port.
+struct PortWriter<'a> {
+    term: &'a TermController,
+    result: Result<(), KError>
+}
+
+impl<'a> Write for PortWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result<(), Error> {
+        self.result = self.term.send_str(s);
+        self.result.map_err(|_| Error)
+    }
+}
+
+/// SGR parameters for a color, text (false) or background (true).
+struct SgrColor(AnsiColor, bool);
+
+impl core::fmt::Display for SgrColor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let SgrColor(color, background) = *self;
+        let offset = if background { 10 } else { 0 };
+        let code = match color {
+            AnsiColor::Black => 30,
+            AnsiColor::Red => 31,
+            AnsiColor::Green => 32,
+            AnsiColor::Yellow => 33,
+            AnsiColor::Blue => 34,
+            AnsiColor::Magenta => 35,
+            AnsiColor::Cyan => 36,
+            AnsiColor::White => 37,
+            AnsiColor::BrightBlack => 90,
+            AnsiColor::BrightRed => 91,
+            AnsiColor::BrightGreen => 92,
+            AnsiColor::BrightYellow => 93,
+            AnsiColor::BrightBlue => 94,
+            AnsiColor::BrightMagenta => 95,
+            AnsiColor::BrightCyan => 96,
+            AnsiColor::BrightWhite => 97,
+            AnsiColor::Color256(c) => {
+                return write!(f, "{};5;{}", 38 + offset, c);
+            }
+        };
+        write!(f, "{}", code + offset)
+    }
+}
@@ -0,0 +1,4 @@
+//! ANSI terminal controllers.
+
+mod controller;
+pub use self::controller::*;
\ No newline at end of file
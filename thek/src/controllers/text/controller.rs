@@ -9,32 +9,62 @@ use core::{
 use crate::devices::{
     self, Device,
     text::{
+        Text,
         CursorBlink,
         CursorShape,
         ansi::{
-            AnsiColor, IntoAscii, IntoChar
+            AnsiColor, IntoChar, AnsiParser, AnsiAction, AnsiCommand, EraseMode, SgrAttr
         }
     }
 };
 
 use crate::sys::{
-    KError
+    KError, KMutex
 };
 
 use alloc::{
     borrow::ToOwned,
-    string::String
+    string::String,
+    sync::Arc,
+    vec::Vec
 };
 
+/// Copy of a screen cell.
+#[derive(Copy, Clone)]
+struct Cell {
+    ch: char,
+    text_color: AnsiColor,
+    bg_color: AnsiColor
+}
+
+/// Shadow copy of the screen of a device, shared by all the controllers of the device.
+type Shadow = Arc<KMutex<Vec<Cell>>>;
+
 /// Text controller.
+/// 
+/// Keeps a shadow copy of the screen, so it never has to read back from the device, and writes printable chars in spans to reduce device accesses.
+/// The shadow belongs to the device, so controllers writing to the same device see each other's changes. It's always locked after the device.
 pub struct TextController {
     cols: usize,
     rows: usize,
     x: usize,
     y: usize,
-    device_id: String,
+    device: Device,
+    shadow: Shadow,
+    /// Printable chars pending to be written, starting at `span_x`.
+    span: Vec<char>,
+    span_x: usize,
     text_color: AnsiColor,
-    bg_color: AnsiColor
+    bg_color: AnsiColor,
+    default_text_color: AnsiColor,
+    default_bg_color: AnsiColor,
+    inverse: bool,
+    /// Increased intensity, the text color is drawn bright.
+    bold: bool,
+    saved_xy: (usize, usize),
+    scroll_top: usize,
+    scroll_bottom: usize,
+    parser: AnsiParser
 }
 
 impl TextController {
@@ -53,12 +83,25 @@ impl TextController {
         text_dev.config_cursor(true, CursorShape::Default, CursorBlink::Default)?;
         let (cols, rows) = text_dev.size()?;
         let (x, y) = text_dev.get_position()?;
+        let shadow = device_shadow(&device_id, *text_dev, cols, rows)?;
+        drop(text_dev);
         Ok(
             Self {
                 cols, rows,
                 x, y,
-                device_id,
-                text_color, bg_color
+                device,
+                shadow,
+                span: Vec::with_capacity(cols),
+                span_x: 0,
+                text_color, bg_color,
+                default_text_color: text_color,
+                default_bg_color: bg_color,
+                inverse: false,
+                bold: false,
+                saved_xy: (x, y),
+                scroll_top: 0,
+                scroll_bottom: rows - 1,
+                parser: AnsiParser::new()
             }
         )
     }
@@ -68,12 +111,18 @@ impl TextController {
     }
 
     pub fn set_xy(&mut self, x: usize, y: usize) -> Result<(), KError> {
-        let device = Self::get_device(&self.device_id)?;
-        let text_dev = device.unwrap_text();
+        if x >= self.cols || y >= self.rows {
+            return Err(KError::OutBounds);
+        }
+        self.flush_span()?;
+        let text_dev = self.device.unwrap_text();
         self.x = x;
         self.y = y;
-        let (_, text_color, bg_color) = text_dev.read(x, y)?;
-        if let (AnsiColor::Black, AnsiColor::Black) = (text_color, bg_color) {
+        let mut shadow = self.shadow.acquire();
+        let cell = &mut shadow[y * self.cols + x];
+        if let (AnsiColor::Black, AnsiColor::Black) = (cell.text_color, cell.bg_color) {
+            cell.text_color = self.text_color;
+            cell.bg_color = self.bg_color;
             text_dev.put_color(x, y, self.text_color, self.bg_color)?;
         }
         text_dev.set_position(x, y)?;
@@ -84,60 +133,264 @@ impl TextController {
         (self.cols, self.rows)
     }
 
-    pub fn clear(&mut self) -> Result<(), KError> {
-        let device = Self::get_device(&self.device_id)?;
-        let text_dev = device.unwrap_text();
-        for y in 0..self.rows {
-            for x in 0..self.cols {
-                text_dev.write(x, y, self.text_color, self.bg_color, ' ')?;
-            }
+    /// Read char and colors at a position, from the shadow screen.
+    pub fn read(&mut self, x: usize, y: usize) -> Result<(char, AnsiColor, AnsiColor), KError> {
+        if x >= self.cols || y >= self.rows {
+            return Err(KError::OutBounds);
         }
-        Ok(())
+        self.flush_span()?;
+        let cell = self.shadow.acquire()[y * self.cols + x];
+        Ok((cell.ch, cell.text_color, cell.bg_color))
     }
 
-    fn inc_pos(&mut self) {
+    pub fn clear(&mut self) -> Result<(), KError> {
+        self.flush_span()?;
+        self.fill(0, 0, self.cols, self.rows, Cell { ch: ' ', text_color: self.text_color, bg_color: self.bg_color })
+    }
+
+    /// Queue a printable char and advance cursor.
+    fn push_char(&mut self, ch: char) -> Result<(), KError> {
+        if self.span.is_empty() {
+            self.span_x = self.x;
+        }
+        self.span.push(ch);
         self.x += 1;
         if self.x >= self.cols {
+            self.flush_span()?;
             self.x = 0;
             self.y += 1;
         }
         if self.y >= self.rows {
             self.y = 0;
         }
+        Ok(())
     }
 
-    fn scroll_up(&self) -> Result<(), KError> {
-        let device = Self::get_device(&self.device_id)?;
-        let text_dev = device.unwrap_text();
-        // Copy all lines one line up (from 1 to rows-1)
-        for line_num in 1..self.rows {
-            for char_num in 0..self.cols {
-                if let Ok((ch, text_color, bg_color)) = text_dev.read(char_num, line_num) {
-                    text_dev.write(char_num, line_num - 1, text_color, bg_color, ch)?;
-                }
-            }
+    /// Write pending printable chars to the device.
+    fn flush_span(&mut self) -> Result<(), KError> {
+        if self.span.is_empty() {
+            return Ok(());
+        }
+        let (text_color, bg_color) = self.colors();
+        let pos = self.y * self.cols + self.span_x;
+        let text_dev = self.device.unwrap_text();
+        for (cell, ch) in self.shadow.acquire()[pos..].iter_mut().zip(self.span.iter()) {
+            *cell = Cell { ch: *ch, text_color, bg_color };
+        }
+        let result = text_dev.write_span(self.span_x, self.y, text_color, bg_color, &self.span);
+        drop(text_dev);
+        self.span.clear();
+        result
+    }
+
+    /// Fill a region with a char and colors, on the device and the shadow screen.
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, cell: Cell) -> Result<(), KError> {
+        let text_dev = self.device.unwrap_text();
+        for line in self.shadow.acquire().chunks_mut(self.cols).skip(y).take(height) {
+            line[x..(x + width)].fill(cell);
+        }
+        text_dev.fill_region(x, y, width, height, cell.text_color, cell.bg_color, cell.ch)
+    }
+
+    fn scroll_up(&mut self) -> Result<(), KError> {
+        let (top, bottom, cols) = (self.scroll_top, self.scroll_bottom, self.cols);
+        let blank = Cell { ch: 0u8.into_char()?, text_color: self.text_color, bg_color: self.bg_color };
+        let text_dev = self.device.unwrap_text();
+        let mut shadow = self.shadow.acquire();
+        shadow.copy_within(((top + 1) * cols)..((bottom + 1) * cols), top * cols);
+        shadow[(bottom * cols)..((bottom + 1) * cols)].fill(blank);
+        text_dev.scroll_up(top, bottom, self.text_color, self.bg_color)
+    }
+
+    fn scroll_down(&mut self) -> Result<(), KError> {
+        let (top, bottom, cols) = (self.scroll_top, self.scroll_bottom, self.cols);
+        {
+            let text_dev = self.device.unwrap_text();
+            self.shadow.acquire().copy_within((top * cols)..(bottom * cols), (top + 1) * cols);
+            text_dev.copy_region(0, top, 0, top + 1, cols, bottom - top)?;
+        }
+        // Set first line empty
+        self.fill(0, top, cols, 1, Cell { ch: 0u8.into_char()?, text_color: self.text_color, bg_color: self.bg_color })
+    }
+
+    /// Fill with spaces from (x0, y0) to (x1, y1), both included, using current colors.
+    fn erase(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) -> Result<(), KError> {
+        let (text_color, bg_color) = self.colors();
+        let blank = Cell { ch: ' ', text_color, bg_color };
+        if y0 == y1 {
+            return self.fill(x0, y0, x1 + 1 - x0, 1, blank);
+        }
+        // First line, full lines in the middle and last line
+        self.fill(x0, y0, self.cols - x0, 1, blank)?;
+        if y1 > y0 + 1 {
+            self.fill(0, y0 + 1, self.cols, y1 - y0 - 1, blank)?;
+        }
+        self.fill(0, y1, x1 + 1, 1, blank)
+    }
+
+    /// Current text and background colors, with the intensity and inverse attributes applied.
+    fn colors(&self) -> (AnsiColor, AnsiColor) {
+        let text_color = if self.bold { self.text_color.bright() } else { self.text_color };
+        if self.inverse {
+            (self.bg_color, text_color)
         }
-        // Set last line empty
-        for char_num in 0..self.cols {
-            text_dev.write(char_num, self.rows - 1, self.text_color, self.bg_color, 0u8.into_char()?)?;
+        else {
+            (text_color, self.bg_color)
         }
+    }
 
+    fn new_line(&mut self) -> Result<(), KError> {
+        if self.y == self.scroll_bottom {
+            self.scroll_up()?;
+        }
+        else if self.y + 1 < self.rows {
+            self.y += 1;
+        }
         Ok(())
     }
 
-    // TODO: convert from unicode to any encoding we have configured in the kernel
-    // Default for VGA console is ASCII extended:
-    // https://upload.wikimedia.org/wikipedia/commons/2/26/Ascii-codes-table.png
-    fn internal_print(&mut self, ch: u8) -> Result<(), KError> {
-        let device = Self::get_device(&self.device_id)?;
-        let text_dev = device.unwrap_text();
-        if let Err(_) = text_dev.write(self.x, self.y, self.text_color, self.bg_color, ch.into_char()?) {
-            return Err(KError::Other);
+    /// Execute an ANSI command.
+    fn ansi_command(&mut self, cmd: AnsiCommand) -> Result<(), KError> {
+        self.flush_span()?;
+        let max_x = self.cols - 1;
+        let max_y = self.rows - 1;
+        match cmd {
+            AnsiCommand::CursorUp(n) => {
+                self.y = self.y.saturating_sub(n);
+            },
+            AnsiCommand::CursorDown(n) => {
+                self.y = (self.y + n).min(max_y);
+            },
+            AnsiCommand::CursorForward(n) => {
+                self.x = (self.x + n).min(max_x);
+            },
+            AnsiCommand::CursorBack(n) => {
+                self.x = self.x.saturating_sub(n);
+            },
+            AnsiCommand::CursorNextLine(n) => {
+                self.x = 0;
+                self.y = (self.y + n).min(max_y);
+            },
+            AnsiCommand::CursorPrevLine(n) => {
+                self.x = 0;
+                self.y = self.y.saturating_sub(n);
+            },
+            AnsiCommand::CursorColumn(x) => {
+                self.x = x.min(max_x);
+            },
+            AnsiCommand::CursorRow(y) => {
+                self.y = y.min(max_y);
+            },
+            AnsiCommand::CursorPosition(x, y) => {
+                self.x = x.min(max_x);
+                self.y = y.min(max_y);
+            },
+            AnsiCommand::EraseDisplay(mode) => {
+                match mode {
+                    EraseMode::ToEnd => self.erase(self.x, self.y, max_x, max_y)?,
+                    EraseMode::ToCursor => self.erase(0, 0, self.x, self.y)?,
+                    EraseMode::All => self.erase(0, 0, max_x, max_y)?
+                }
+            },
+            AnsiCommand::EraseLine(mode) => {
+                match mode {
+                    EraseMode::ToEnd => self.erase(self.x, self.y, max_x, self.y)?,
+                    EraseMode::ToCursor => self.erase(0, self.y, self.x, self.y)?,
+                    EraseMode::All => self.erase(0, self.y, max_x, self.y)?
+                }
+            },
+            AnsiCommand::ScrollUp(n) => {
+                for _ in 0..n.min(self.rows) {
+                    self.scroll_up()?;
+                }
+            },
+            AnsiCommand::ScrollDown(n) => {
+                for _ in 0..n.min(self.rows) {
+                    self.scroll_down()?;
+                }
+            },
+            AnsiCommand::ScrollRegion(top, bottom) => {
+                let bottom = bottom.unwrap_or(max_y).min(max_y);
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.x = 0;
+                    self.y = 0;
+                }
+            },
+            AnsiCommand::SaveCursor => {
+                self.saved_xy = (self.x, self.y);
+            },
+            AnsiCommand::RestoreCursor => {
+                let (x, y) = self.saved_xy;
+                self.x = x.min(max_x);
+                self.y = y.min(max_y);
+            },
+            AnsiCommand::Sgr(params) => {
+                for attr in params.sgr_iter() {
+                    match attr {
+                        SgrAttr::Reset => {
+                            self.text_color = self.default_text_color;
+                            self.bg_color = self.default_bg_color;
+                            self.inverse = false;
+                            self.bold = false;
+                        },
+                        SgrAttr::Bold => self.bold = true,
+                        SgrAttr::Normal => self.bold = false,
+                        SgrAttr::Inverse => self.inverse = true,
+                        SgrAttr::NotInverse => self.inverse = false,
+                        SgrAttr::TextColor(color) => self.text_color = color,
+                        SgrAttr::BgColor(color) => self.bg_color = color,
+                        SgrAttr::DefaultTextColor => self.text_color = self.default_text_color,
+                        SgrAttr::DefaultBgColor => self.bg_color = self.default_bg_color,
+                        SgrAttr::Unsupported => {}
+                    }
+                }
+            },
+            AnsiCommand::Unsupported => {}
         }
         Ok(())
     }
+
+}
+
+/// Get the shadow screen of a device, reading it back from the device the first time or if its size changed.
+fn device_shadow(device_id: &str, text_dev: &dyn Text, cols: usize, rows: usize) -> Result<Shadow, KError> {
+    let mut shadows = SHADOWS.acquire();
+    let shadow = if let Some((_, shadow)) = shadows.iter().find(|(id, _)| id == device_id) {
+        shadow.clone()
+    }
+    else {
+        let shadow = Arc::new(KMutex::new(Vec::new()));
+        shadows.push((device_id.to_owned(), shadow.clone()));
+        shadow
+    };
+    let mut cells = shadow.acquire();
+    if cells.len() != cols * rows {
+        cells.clear();
+        for y in 0..rows {
+            for x in 0..cols {
+                let (ch, text_color, bg_color) = text_dev.read(x, y)?;
+                cells.push(Cell { ch, text_color, bg_color });
+            }
+        }
+    }
+    drop(cells);
+    Ok(shadow)
+}
+
+/// Reset the locks of all shadow screens.
+///
+/// `WARNING`: Don't call it unless you know very well what you are doing! Only intended to be used by the panic handler.
+pub fn reset_shadows() {
+    for (_, shadow) in SHADOWS.reset().acquire().iter() {
+        shadow.reset();
+    }
 }
 
+/// Shadow screens of the devices, by device id.
+static SHADOWS: KMutex<Vec<(String, Shadow)>> = KMutex::new(Vec::new());
+
 impl Default for TextController {
     fn default() -> Self {
         Self::new(AnsiColor::White, AnsiColor::Black, "CON1".to_owned())
@@ -148,19 +401,32 @@ impl Default for TextController {
 impl Write for TextController {
     fn write_str(&mut self, s: &str) -> Result<(), Error> {
         for ch in s.chars() {
+            let ch = match self.parser.feed(ch) {
+                AnsiAction::Print(ch) => ch,
+                AnsiAction::Command(cmd) => {
+                    // ANSI Escape Sequence
+                    if self.ansi_command(cmd).is_err() {
+                        return Err(Error);
+                    }
+                    continue;
+                },
+                AnsiAction::None => continue
+            };
+            // Control chars break the current span
+            if (ch as u32) < 0x20 && self.flush_span().is_err() {
+                return Err(Error);
+            }
             match ch as u32 {
                 0x0a => {
                     // Newline
-                    if self.y + 1 >= self.rows {
-                        if self.scroll_up().is_err() {
-                            return Err(Error);
-                        }
-                        self.x = 0;
-                    }
-                    else {
-                        self.y += 1;
-                        self.x = 0;
+                    if self.new_line().is_err() {
+                        return Err(Error);
                     }
+                    self.x = 0;
+                },
+                0x0d => {
+                    // Carriage return
+                    self.x = 0;
                 },
                 0x09 => {
                     // Tab
@@ -185,20 +451,17 @@ impl Write for TextController {
                         }
                     }
                     // Print space to actually remove char
-                    if self.internal_print(' '.into_ascii().unwrap()).is_err() {
+                    let (text_color, bg_color) = self.colors();
+                    if self.fill(self.x, self.y, 1, 1, Cell { ch: ' ', text_color, bg_color }).is_err() {
                         return Err(Error);
                     }
                 },
-                0x1b => {
-                    // ANSI Escape Sequence
-                    // TODO: parse ANSI commands and perform action
-                },
                 _ => {
-                    // Everything else is considered a printable char (even if it's not)
-                    if self.internal_print(ch.into_ascii().unwrap_or_default()).is_err() {
+                    // Everything else is considered a printable char (even if it's not).
+                    // The device is responsible for converting the char into its own encoding.
+                    if self.push_char(ch).is_err() {
                         return Err(Error);
                     }
-                    self.inc_pos();
                 }
             }
         }
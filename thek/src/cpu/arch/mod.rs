@@ -8,7 +8,10 @@
 //! - `fn disable_ints()` 
 //! - `fn enable_ints()`
 //! - `fn check_ints() -> bool`
+//! - `fn exit_vm(code: u32) -> !`
 //! - `fn set_timer_handler(func: fn())`
+//! - `fn set_irq_handler(irq: u8, func: fn()) -> bool`
+//! - `fn set_page_fault_handler(func: fn(usize))`
 //! - `const TIMER_FREQ_HZ: u64`
 //! - `struct StackFrame`
 
@@ -4,20 +4,28 @@ use x86_64::{
     VirtAddr,
     structures::{
         idt::{
-            InterruptDescriptorTable, InterruptStackFrame
+            InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode
         },
         gdt::{
             GlobalDescriptorTable, Descriptor
-        }
+        },
+        tss::TaskStateSegment
     },
     instructions::{
-        //tables::load_tss
-        interrupts::are_enabled
+        tables::load_tss,
+        interrupts::{
+            are_enabled, without_interrupts
+        }
     },
-    registers::segmentation::{
-        Segment, CS
+    registers::{
+        control::Cr2,
+        segmentation::{
+            Segment, CS
+        }
     }
 };
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use pic8259::ChainedPics;
 use crate::sys::KMutex;
 
@@ -34,28 +42,49 @@ pub fn start_arch() {
     enable_ints();
 }
 
-// Init GDT.
+// Init GDT and TSS.
 fn init_gdt() {
-    //TODO: create TSS
     let mut gdt = GDT.acquire();
     let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-    // let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+    let tss_selector = unsafe {
+        // Faults that may come from a broken stack run on their own stacks
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = fault_stack_top(addr_of!(DOUBLE_FAULT_STACK));
+        TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = fault_stack_top(addr_of!(PAGE_FAULT_STACK));
+        gdt.add_entry(Descriptor::tss_segment(&*addr_of!(TSS)))
+    };
     // Load GDT
     unsafe {
         gdt.load_unsafe();
         CS::set_reg(code_selector);
-        //load_tss(tss_selector);
+        load_tss(tss_selector);
     }
 }
 
 static GDT: KMutex<GlobalDescriptorTable> = KMutex::new(GlobalDescriptorTable::new());
 
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+const FAULT_STACK_SIZE: usize = 16 * 1024;
+
+static mut DOUBLE_FAULT_STACK: [u8; FAULT_STACK_SIZE] = [0; FAULT_STACK_SIZE];
+static mut PAGE_FAULT_STACK: [u8; FAULT_STACK_SIZE] = [0; FAULT_STACK_SIZE];
+
+/// Top of a fault stack, 16 bytes aligned.
+fn fault_stack_top(stack: *const [u8; FAULT_STACK_SIZE]) -> VirtAddr {
+    (VirtAddr::from_ptr(stack) + FAULT_STACK_SIZE as u64).align_down(16u64)
+}
+
 // Init essential interrupts.
 fn init_idt() {
     let mut idt = IDT.acquire();
-    // Set double fault interrupt handler
-    //TODO: set a different stack for this handler
-    idt.double_fault.set_handler_fn(double_fault_int_handler);
+    // Set fault interrupt handlers
+    unsafe {
+        idt.double_fault.set_handler_fn(double_fault_int_handler).set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        idt.page_fault.set_handler_fn(page_fault_int_handler).set_stack_index(PAGE_FAULT_IST_INDEX);
+    }
     // Load IDT
     unsafe {
         idt.load_unsafe();
@@ -67,6 +96,26 @@ fn double_fault_int_handler(stack_frame: InterruptStackFrame, error_code: u64) -
     panic!("DOUBLE FAULT = {} , {:#?}", error_code, stack_frame);
 }
 
+extern "x86-interrupt"
+fn page_fault_int_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    let addr = Cr2::read_raw() as usize;
+    // The fault may happen with any lock held, so the handler is read without locking
+    let handler = PAGE_FAULT_HANDLER.load(Ordering::SeqCst);
+    if handler != 0 {
+        let func: fn(usize) = unsafe { core::mem::transmute(handler) };
+        func(addr);
+    }
+    panic!("PAGE FAULT at {:#x} = {:?} , {:#?}", addr, error_code, stack_frame);
+}
+
+/// Set a function to be executed on page faults, with the faulting address, before the generic panic.
+pub fn set_page_fault_handler(func: fn(usize)) {
+    PAGE_FAULT_HANDLER.store(func as usize, Ordering::SeqCst);
+}
+
+/// Address of the page fault handler, 0 if not set.
+static PAGE_FAULT_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
 static IDT: KMutex<InterruptDescriptorTable> = KMutex::new(InterruptDescriptorTable::new());
 
 #[repr(u8)]
@@ -81,13 +130,116 @@ fn init_pic() {
     let mut idt = IDT.acquire();
     unsafe {
         idt[PicInt::Timer as usize].set_handler_addr(VirtAddr::new(timer_int_handler as u64));
+    }
+    // IRQ 0 is the timer, that has its own handler
+    let irq_int_handlers = [
+        irq_1_int_handler, irq_2_int_handler, irq_3_int_handler, irq_4_int_handler,
+        irq_5_int_handler, irq_6_int_handler, irq_7_int_handler, irq_8_int_handler,
+        irq_9_int_handler, irq_10_int_handler, irq_11_int_handler, irq_12_int_handler,
+        irq_13_int_handler, irq_14_int_handler, irq_15_int_handler
+    ];
+    for (i, handler) in irq_int_handlers.iter().enumerate() {
+        idt[PIC_1_OFFSET as usize + 1 + i].set_handler_fn(*handler);
+    }
+    unsafe {
         idt.load_unsafe();
     }
 }
 
+/// Set a function to be executed on each interrupt of hardware line `irq`, and unmask it.
+///
+/// IRQ 0 (timer) and 2 (PIC cascade) can't be set.
+/// * Return: could be set or not.
+pub fn set_irq_handler(irq: u8, func: fn()) -> bool {
+    if irq == 0 || irq == 2 || irq >= 16 {
+        return false;
+    }
+    // The handler lock is also acquired by the ISR, don't let it interrupt us while we hold it
+    without_interrupts(|| {
+        IRQ_HANDLERS.acquire()[irq as usize] = Some(func);
+        let mut pics = PICS.acquire();
+        unsafe {
+            let [mut mask1, mut mask2] = pics.read_masks();
+            if irq < 8 {
+                mask1 &= !(1 << irq);
+            }
+            else {
+                // Unmask cascade line too
+                mask1 &= !(1 << 2);
+                mask2 &= !(1 << (irq - 8));
+            }
+            pics.write_masks(mask1, mask2);
+        }
+    });
+    true
+}
+
+/// Handler for each IRQ line.
+type IrqHandlers = [Option<fn()>; 16];
+
+static IRQ_HANDLERS: KMutex<IrqHandlers> = KMutex::new([None; 16]);
+
+fn irq_isr(irq: u8) {
+    // The lowest priority line of each PIC gets spurious IRQs, that are not marked in service
+    if (irq == 7 || irq == 15) && !irq_in_service(irq) {
+        if irq == 15 {
+            // The master PIC did see a real IRQ from the slave in the cascade line
+            unsafe {
+                PICS.acquire().notify_end_of_interrupt(PIC_1_OFFSET + 2);
+            }
+        }
+        return;
+    }
+    let handler = IRQ_HANDLERS.acquire()[irq as usize];
+    if let Some(func) = handler {
+        func();
+    }
+    unsafe {
+        PICS.acquire().notify_end_of_interrupt(PIC_1_OFFSET + irq);
+    }
+}
+
+/// Check the in-service register of the PIC that handles `irq`.
+fn irq_in_service(irq: u8) -> bool {
+    let _pics = PICS.acquire();
+    let (port, bit) = if irq < 8 { (PIC_1_COMMAND, irq) } else { (PIC_2_COMMAND, irq - 8) };
+    outb(port, PIC_READ_ISR);
+    inb(port) & (1 << bit) != 0
+}
+
+macro_rules! irq_int_handler {
+    ($name:ident, $irq:expr) => {
+        extern "x86-interrupt"
+        fn $name(_stack_frame: InterruptStackFrame) {
+            irq_isr($irq);
+        }
+    };
+}
+
+irq_int_handler!(irq_1_int_handler, 1);
+irq_int_handler!(irq_2_int_handler, 2);
+irq_int_handler!(irq_3_int_handler, 3);
+irq_int_handler!(irq_4_int_handler, 4);
+irq_int_handler!(irq_5_int_handler, 5);
+irq_int_handler!(irq_6_int_handler, 6);
+irq_int_handler!(irq_7_int_handler, 7);
+irq_int_handler!(irq_8_int_handler, 8);
+irq_int_handler!(irq_9_int_handler, 9);
+irq_int_handler!(irq_10_int_handler, 10);
+irq_int_handler!(irq_11_int_handler, 11);
+irq_int_handler!(irq_12_int_handler, 12);
+irq_int_handler!(irq_13_int_handler, 13);
+irq_int_handler!(irq_14_int_handler, 14);
+irq_int_handler!(irq_15_int_handler, 15);
+
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xA0;
+/// OCW3 command to read the in-service register.
+const PIC_READ_ISR: u8 = 0x0B;
+
 static PICS: KMutex<ChainedPics> = KMutex::new(
     unsafe {
         ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
@@ -220,6 +372,42 @@ pub fn outb(port: u16, data: u8) {
     }
 }
 
+#[inline]
+/// Input word from port
+pub fn inw(port: u16) -> u16 {
+    let r: u16;
+    unsafe {
+        asm!("in ax, dx", out("ax") r, in("dx") port);
+    }
+    r
+}
+
+#[inline]
+/// Output word to port
+pub fn outw(port: u16, data: u16) {
+    unsafe {
+        asm!("out dx, ax", in("dx") port, in("ax") data);
+    }
+}
+
+#[inline]
+/// Input double word from port
+pub fn inl(port: u16) -> u32 {
+    let r: u32;
+    unsafe {
+        asm!("in eax, dx", out("eax") r, in("dx") port);
+    }
+    r
+}
+
+#[inline]
+/// Output double word to port
+pub fn outl(port: u16, data: u32) {
+    unsafe {
+        asm!("out dx, eax", in("dx") port, in("eax") data);
+    }
+}
+
 #[inline]
 /// Halt the system.
 pub fn halt() {
@@ -231,6 +419,18 @@ pub fn halt() {
     }
 }
 
+/// Exit QEMU through the `isa-debug-exit` device (iobase 0xf4, iosize 4).
+/// 
+/// QEMU terminates with status `(code << 1) | 1`. If the device is not present, just halt.
+pub fn exit_vm(code: u32) -> ! {
+    outl(ISA_DEBUG_EXIT_PORT, code);
+    loop {
+        halt();
+    }
+}
+
+const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+
 #[inline]
 /// Disable interrupts.
 pub fn disable_ints() {
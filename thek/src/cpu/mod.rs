@@ -3,10 +3,23 @@
 pub mod arch;
 
 pub use arch::{
-    start_arch as start_cpu, halt, disable_ints, enable_ints, check_ints
+    start_arch as start_cpu, halt, disable_ints, enable_ints, check_ints, exit_vm
 };
 
 /// Initialize ints, cpu structures, timers, etc.
 pub fn init_cpu() {
     arch::init_arch();
 }
+
+/// Run `func` with interrupts disabled, and restore them as they were.
+///
+/// Locks shared with interrupt handlers must be taken this way, otherwise an interrupt arriving while the lock is held deadlocks.
+pub fn without_ints<T>(func: impl FnOnce() -> T) -> T {
+    let ints = check_ints();
+    disable_ints();
+    let res = func();
+    if ints {
+        enable_ints();
+    }
+    res
+}
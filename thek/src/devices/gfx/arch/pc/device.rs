@@ -0,0 +1,230 @@
+//! PC framebuffer device.
+//!
+//! Uses the Bochs VBE extensions, available in QEMU and Bochs. The whole video memory is mapped uncached when the first mode is set (see [`paging::map_mmio`]), all modes fit in it.
+
+use crate::devices::{
+    register_device,
+    gfx::{
+        Gfx, GfxMode, PixelFormat, Rgb, Framebuffer
+    },
+    Id, Interrupt, Device
+};
+
+use macros::device;
+
+use crate::cpu::arch::{
+    inw, outw, inl, outl
+};
+
+use crate::mem::paging;
+
+use crate::sys::{
+    KMutex, KError
+};
+
+#[device(crate::devices::gfx::arch)]
+pub fn register_devices() {
+    register_device(Device::Gfx(&PC_GFX_DEVICE_1_MUTEX));
+}
+
+static PC_GFX_DEVICE_1 : PcGfxDevice = PcGfxDevice::new();
+static PC_GFX_DEVICE_1_MUTEX : KMutex<&'static dyn Gfx> = KMutex::new(&PC_GFX_DEVICE_1);
+
+/// Current framebuffer and the mapping of the video memory.
+struct GfxState {
+    framebuffer: Option<Framebuffer>,
+    /// Virtual address and size of the mapping.
+    lfb: Option<(*mut u8, usize)>
+}
+
+static GFX_STATE : KMutex<GfxState> = KMutex::new(GfxState { framebuffer: None, lfb: None });
+
+const VBE_DISPI_IOPORT_INDEX : u16 = 0x01CE;
+const VBE_DISPI_IOPORT_DATA : u16 = 0x01CF;
+
+const VBE_DISPI_INDEX_ID : u16 = 0;
+const VBE_DISPI_INDEX_XRES : u16 = 1;
+const VBE_DISPI_INDEX_YRES : u16 = 2;
+const VBE_DISPI_INDEX_BPP : u16 = 3;
+const VBE_DISPI_INDEX_ENABLE : u16 = 4;
+const VBE_DISPI_INDEX_VIRT_WIDTH : u16 = 6;
+const VBE_DISPI_INDEX_VIDEO_MEMORY_64K : u16 = 0xA;
+
+/// First version supporting 24 and 32 bits per pixel.
+const VBE_DISPI_ID2 : u16 = 0xB0C2;
+const VBE_DISPI_ID_MAX : u16 = 0xB0CF;
+
+const VBE_DISPI_DISABLED : u16 = 0x00;
+const VBE_DISPI_ENABLED : u16 = 0x01;
+const VBE_DISPI_LFB_ENABLED : u16 = 0x40;
+
+/// PCI vendor and device of the Bochs/QEMU standard VGA.
+const BOCHS_VGA_PCI_ID : (u16, u16) = (0x1234, 0x1111);
+/// Linear framebuffer address used when the PCI device is not found.
+const VBE_DISPI_LFB_PHYSICAL_ADDRESS : usize = 0xE0000000;
+
+const PCI_CONFIG_ADDRESS : u16 = 0xCF8;
+const PCI_CONFIG_DATA : u16 = 0xCFC;
+
+/// PC graphic device.
+pub struct PcGfxDevice {
+}
+
+impl PcGfxDevice {
+    const fn new() -> Self {
+        Self {}
+    }
+
+    fn vbe_read(index: u16) -> u16 {
+        outw(VBE_DISPI_IOPORT_INDEX, index);
+        inw(VBE_DISPI_IOPORT_DATA)
+    }
+
+    fn vbe_write(index: u16, value: u16) {
+        outw(VBE_DISPI_IOPORT_INDEX, index);
+        outw(VBE_DISPI_IOPORT_DATA, value);
+    }
+
+    fn vbe_available() -> bool {
+        let id = Self::vbe_read(VBE_DISPI_INDEX_ID);
+        (VBE_DISPI_ID2..=VBE_DISPI_ID_MAX).contains(&id)
+    }
+
+    fn pci_read(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+        let address = 0x8000_0000u32
+            | (bus as u32) << 16
+            | (slot as u32) << 11
+            | (func as u32) << 8
+            | (offset as u32 & 0xFC);
+        outl(PCI_CONFIG_ADDRESS, address);
+        inl(PCI_CONFIG_DATA)
+    }
+
+    /// Find the linear framebuffer address in the BAR0 of the Bochs VGA PCI device.
+    fn lfb_address() -> usize {
+        for slot in 0..32 {
+            let id = Self::pci_read(0, slot, 0, 0);
+            if (id & 0xFFFF) as u16 == BOCHS_VGA_PCI_ID.0 && (id >> 16) as u16 == BOCHS_VGA_PCI_ID.1 {
+                return (Self::pci_read(0, slot, 0, 0x10) & 0xFFFF_FFF0) as usize;
+            }
+        }
+        VBE_DISPI_LFB_PHYSICAL_ADDRESS
+    }
+
+    /// Back to text mode.
+    fn disable(state: &mut GfxState) {
+        Self::vbe_write(VBE_DISPI_INDEX_ENABLE, VBE_DISPI_DISABLED);
+        state.framebuffer = None;
+    }
+
+    fn with_framebuffer<T>(func: impl FnOnce(&Framebuffer) -> Result<T, KError>) -> Result<T, KError> {
+        if let Some(fb) = &GFX_STATE.acquire().framebuffer {
+            func(fb)
+        }
+        else {
+            // No graphic mode set yet, we are still in text mode
+            Err(KError::Unsupported)
+        }
+    }
+}
+
+impl Gfx for PcGfxDevice {
+    fn mode(&self) -> Result<GfxMode, KError> {
+        Self::with_framebuffer(|fb| Ok(fb.mode()))
+    }
+
+    fn set_mode(&self, width: usize, height: usize, format: PixelFormat) -> Result<GfxMode, KError> {
+        let mut state = GFX_STATE.acquire();
+        let bpp = match format {
+            PixelFormat::Xrgb8888 => 32,
+            PixelFormat::Rgb888 => 24,
+            PixelFormat::Rgb565 => 16,
+            PixelFormat::Rgb555 => 15,
+            _ => return Err(KError::Unsupported)
+        };
+        // VBE registers are 16 bits, a truncated size would set another mode
+        if width == 0 || height == 0 || width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(KError::OutBounds);
+        }
+        if !Self::vbe_available() {
+            return Err(KError::Unsupported);
+        }
+        Self::vbe_write(VBE_DISPI_INDEX_ENABLE, VBE_DISPI_DISABLED);
+        Self::vbe_write(VBE_DISPI_INDEX_XRES, width as u16);
+        Self::vbe_write(VBE_DISPI_INDEX_YRES, height as u16);
+        Self::vbe_write(VBE_DISPI_INDEX_BPP, bpp);
+        Self::vbe_write(VBE_DISPI_INDEX_ENABLE, VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED);
+        // Read back, the device may have rejected or adjusted the mode
+        let mode = GfxMode {
+            width: Self::vbe_read(VBE_DISPI_INDEX_XRES) as usize,
+            height: Self::vbe_read(VBE_DISPI_INDEX_YRES) as usize,
+            pitch: Self::vbe_read(VBE_DISPI_INDEX_VIRT_WIDTH) as usize * format.bytes_per_pixel(),
+            format
+        };
+        if Self::vbe_read(VBE_DISPI_INDEX_BPP) != bpp {
+            Self::disable(&mut state);
+            return Err(KError::Unsupported);
+        }
+        let size = mode.pitch * mode.height;
+        if state.lfb.is_none() {
+            // Older versions don't report the video memory size, then map just this mode
+            let lfb_size = (Self::vbe_read(VBE_DISPI_INDEX_VIDEO_MEMORY_64K) as usize * 64 * 1024).max(size);
+            match unsafe { paging::map_mmio(Self::lfb_address() as u64, lfb_size) } {
+                Ok(base) => state.lfb = Some((base, lfb_size)),
+                Err(e) => {
+                    Self::disable(&mut state);
+                    return Err(e);
+                }
+            }
+        }
+        match state.lfb {
+            Some((base, lfb_size)) if size <= lfb_size => {
+                state.framebuffer = Some(
+                    unsafe {
+                        Framebuffer::new(base, mode)
+                    }
+                );
+                Ok(mode)
+            },
+            _ => {
+                Self::disable(&mut state);
+                Err(KError::Unsupported)
+            }
+        }
+    }
+
+    fn text_mode(&self) -> Result<(), KError> {
+        Self::disable(&mut GFX_STATE.acquire());
+        Ok(())
+    }
+
+    fn put_pixel(&self, x: usize, y: usize, color: Rgb) -> Result<(), KError> {
+        Self::with_framebuffer(|fb| fb.put_pixel(x, y, color))
+    }
+
+    fn get_pixel(&self, x: usize, y: usize) -> Result<Rgb, KError> {
+        Self::with_framebuffer(|fb| fb.get_pixel(x, y))
+    }
+
+    fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Rgb) -> Result<(), KError> {
+        Self::with_framebuffer(|fb| fb.fill_rect(x, y, width, height, color))
+    }
+
+    fn blit(&self, x: usize, y: usize, width: usize, height: usize, pixels: &[Rgb]) -> Result<(), KError> {
+        Self::with_framebuffer(|fb| fb.blit(x, y, width, height, pixels))
+    }
+
+    fn copy_rect(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        Self::with_framebuffer(|fb| fb.copy_rect(src_x, src_y, dst_x, dst_y, width, height))
+    }
+}
+
+impl Id for PcGfxDevice {
+    fn id(&self) -> &str {
+        "GFX1"
+    }
+}
+
+impl Interrupt for PcGfxDevice {
+    fn handler(&self, _: fn(Device)) -> bool { false }
+}
\ No newline at end of file
@@ -0,0 +1,4 @@
+//! PC graphic devices.
+
+pub mod device;
+pub use self::device::*;
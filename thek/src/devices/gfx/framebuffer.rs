@@ -0,0 +1,149 @@
+use core::ptr;
+
+use crate::sys::KError;
+
+use super::{
+    Rgb, GfxMode
+};
+
+/// Linear framebuffer.
+///
+/// Implements the drawing operations over a memory mapped frame buffer, arch dependent devices only have to provide the buffer address and mode.
+#[derive(Copy, Clone)]
+pub struct Framebuffer {
+    base: *mut u8,
+    mode: GfxMode
+}
+
+impl Framebuffer {
+    /// Create a framebuffer.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to a mapped and writable buffer of at least `mode.pitch * mode.height` bytes.
+    pub const unsafe fn new(base: *mut u8, mode: GfxMode) -> Self {
+        Self { base, mode }
+    }
+
+    /// Framebuffer mode.
+    pub fn mode(&self) -> GfxMode {
+        self.mode
+    }
+
+    /// Buffer address.
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub fn put_pixel(&self, x: usize, y: usize, color: Rgb) -> Result<(), KError> {
+        self.check_rect(x, y, 1, 1)?;
+        unsafe {
+            self.write_raw(self.pixel_ptr(x, y), self.mode.format.encode(color));
+        }
+        Ok(())
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Result<Rgb, KError> {
+        self.check_rect(x, y, 1, 1)?;
+        let raw = unsafe {
+            self.read_raw(self.pixel_ptr(x, y))
+        };
+        Ok(self.mode.format.decode(raw))
+    }
+
+    pub fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Rgb) -> Result<(), KError> {
+        self.check_rect(x, y, width, height)?;
+        let raw = self.mode.format.encode(color);
+        let bytes = self.mode.format.bytes_per_pixel();
+        for dy in 0..height {
+            let line_ptr = self.pixel_ptr(x, y + dy);
+            for dx in 0..width {
+                unsafe {
+                    self.write_raw(line_ptr.add(dx * bytes), raw);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn blit(&self, x: usize, y: usize, width: usize, height: usize, pixels: &[Rgb]) -> Result<(), KError> {
+        self.check_rect(x, y, width, height)?;
+        if pixels.len() < width * height {
+            return Err(KError::OutBounds);
+        }
+        let bytes = self.mode.format.bytes_per_pixel();
+        for (dy, line) in pixels.chunks(width).take(height).enumerate() {
+            let line_ptr = self.pixel_ptr(x, y + dy);
+            for (dx, color) in line.iter().enumerate() {
+                unsafe {
+                    self.write_raw(line_ptr.add(dx * bytes), self.mode.format.encode(*color));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn copy_rect(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        self.check_rect(src_x, src_y, width, height)?;
+        self.check_rect(dst_x, dst_y, width, height)?;
+        let line_len = width * self.mode.format.bytes_per_pixel();
+        unsafe {
+            if width == self.mode.width && self.mode.pitch == line_len {
+                // Full lines without padding are contiguous in memory, copy all at once
+                ptr::copy(self.pixel_ptr(0, src_y), self.pixel_ptr(0, dst_y), line_len * height);
+            }
+            else if dst_y > src_y {
+                // Copy lines backwards to not overwrite source lines before they are copied
+                for dy in (0..height).rev() {
+                    ptr::copy(self.pixel_ptr(src_x, src_y + dy), self.pixel_ptr(dst_x, dst_y + dy), line_len);
+                }
+            }
+            else {
+                for dy in 0..height {
+                    ptr::copy(self.pixel_ptr(src_x, src_y + dy), self.pixel_ptr(dst_x, dst_y + dy), line_len);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_rect(&self, x: usize, y: usize, width: usize, height: usize) -> Result<(), KError> {
+        if x + width > self.mode.width || y + height > self.mode.height {
+            Err(KError::OutBounds)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Pointer to the pixel at X,Y position.
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u8 {
+        unsafe {
+            self.base.add(y * self.mode.pitch + x * self.mode.format.bytes_per_pixel())
+        }
+    }
+
+    unsafe fn write_raw(&self, ptr: *mut u8, raw: u32) {
+        match self.mode.format.bytes_per_pixel() {
+            4 => ptr::write_volatile(ptr as *mut u32, raw),
+            3 => {
+                ptr::write_volatile(ptr, raw as u8);
+                ptr::write_volatile(ptr.add(1), (raw >> 8) as u8);
+                ptr::write_volatile(ptr.add(2), (raw >> 16) as u8);
+            },
+            _ => ptr::write_volatile(ptr as *mut u16, raw as u16)
+        }
+    }
+
+    unsafe fn read_raw(&self, ptr: *mut u8) -> u32 {
+        match self.mode.format.bytes_per_pixel() {
+            4 => ptr::read_volatile(ptr as *mut u32),
+            3 => {
+                ptr::read_volatile(ptr) as u32
+                    | (ptr::read_volatile(ptr.add(1)) as u32) << 8
+                    | (ptr::read_volatile(ptr.add(2)) as u32) << 16
+            },
+            _ => ptr::read_volatile(ptr as *mut u16) as u32
+        }
+    }
+}
\ No newline at end of file
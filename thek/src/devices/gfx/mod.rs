@@ -0,0 +1,165 @@
+//! Graphic devices.
+
+use super::{
+    Id, Interrupt
+};
+
+use crate::sys::KError;
+
+use super::text::ansi::AnsiColor;
+
+pub mod arch;
+
+mod framebuffer;
+pub use self::framebuffer::*;
+
+/// RGB color, 8 bits per component.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// Convert an ANSI color to RGB, using the VGA palette for the 16 basic colors and the xterm palette for the rest.
+impl From<AnsiColor> for Rgb {
+    fn from(value: AnsiColor) -> Self {
+        match value {
+            AnsiColor::Black => Rgb::new(0, 0, 0),
+            AnsiColor::Red => Rgb::new(170, 0, 0),
+            AnsiColor::Green => Rgb::new(0, 170, 0),
+            AnsiColor::Yellow => Rgb::new(170, 85, 0),
+            AnsiColor::Blue => Rgb::new(0, 0, 170),
+            AnsiColor::Magenta => Rgb::new(170, 0, 170),
+            AnsiColor::Cyan => Rgb::new(0, 170, 170),
+            AnsiColor::White => Rgb::new(170, 170, 170),
+            AnsiColor::BrightBlack => Rgb::new(85, 85, 85),
+            AnsiColor::BrightRed => Rgb::new(255, 85, 85),
+            AnsiColor::BrightGreen => Rgb::new(85, 255, 85),
+            AnsiColor::BrightYellow => Rgb::new(255, 255, 85),
+            AnsiColor::BrightBlue => Rgb::new(85, 85, 255),
+            AnsiColor::BrightMagenta => Rgb::new(255, 85, 255),
+            AnsiColor::BrightCyan => Rgb::new(85, 255, 255),
+            AnsiColor::BrightWhite => Rgb::new(255, 255, 255),
+            AnsiColor::Color256(c) => {
+                if c < 16 {
+                    let basic = AnsiColor::from_index((c % 8) as u16);
+                    Rgb::from(if c < 8 { basic } else { basic.bright() })
+                }
+                else if c < 232 {
+                    // 6x6x6 color cube
+                    let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                    let c = c - 16;
+                    Rgb::new(level(c / 36), level((c / 6) % 6), level(c % 6))
+                }
+                else {
+                    // Grayscale ramp
+                    let v = 8 + (c - 232) * 10;
+                    Rgb::new(v, v, v)
+                }
+            }
+        }
+    }
+}
+
+/// Pixel format. Components are named from the most significant to the least significant bit.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PixelFormat {
+    /// 32 bits, 8 bits per component, upper byte unused.
+    Xrgb8888,
+    /// 32 bits, 8 bits per component, upper byte unused.
+    Xbgr8888,
+    /// 24 bits, 8 bits per component.
+    Rgb888,
+    /// 24 bits, 8 bits per component.
+    Bgr888,
+    /// 16 bits, 5 bits red, 6 bits green and 5 bits blue.
+    Rgb565,
+    /// 15 bits (stored in 16), 5 bits per component.
+    Rgb555
+}
+
+impl PixelFormat {
+    /// Bits per pixel, as stored in memory.
+    pub fn bpp(&self) -> usize {
+        match self {
+            PixelFormat::Xrgb8888 | PixelFormat::Xbgr8888 => 32,
+            PixelFormat::Rgb888 | PixelFormat::Bgr888 => 24,
+            PixelFormat::Rgb565 | PixelFormat::Rgb555 => 16
+        }
+    }
+
+    /// Bytes per pixel.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bpp() / 8
+    }
+
+    /// Convert a color into a raw pixel value.
+    pub fn encode(&self, color: Rgb) -> u32 {
+        let (r, g, b) = (color.r as u32, color.g as u32, color.b as u32);
+        match self {
+            PixelFormat::Xrgb8888 | PixelFormat::Rgb888 => (r << 16) | (g << 8) | b,
+            PixelFormat::Xbgr8888 | PixelFormat::Bgr888 => (b << 16) | (g << 8) | r,
+            PixelFormat::Rgb565 => ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3),
+            PixelFormat::Rgb555 => ((r >> 3) << 10) | ((g >> 3) << 5) | (b >> 3)
+        }
+    }
+
+    /// Convert a raw pixel value into a color.
+    pub fn decode(&self, raw: u32) -> Rgb {
+        match self {
+            PixelFormat::Xrgb8888 | PixelFormat::Rgb888 => {
+                Rgb::new((raw >> 16) as u8, (raw >> 8) as u8, raw as u8)
+            },
+            PixelFormat::Xbgr8888 | PixelFormat::Bgr888 => {
+                Rgb::new(raw as u8, (raw >> 8) as u8, (raw >> 16) as u8)
+            },
+            PixelFormat::Rgb565 => {
+                Rgb::new((((raw >> 11) & 0x1F) << 3) as u8, (((raw >> 5) & 0x3F) << 2) as u8, ((raw & 0x1F) << 3) as u8)
+            },
+            PixelFormat::Rgb555 => {
+                Rgb::new((((raw >> 10) & 0x1F) << 3) as u8, (((raw >> 5) & 0x1F) << 3) as u8, ((raw & 0x1F) << 3) as u8)
+            }
+        }
+    }
+}
+
+/// Graphic mode.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GfxMode {
+    /// Width in pixels.
+    pub width: usize,
+    /// Height in pixels.
+    pub height: usize,
+    /// Bytes per line, may be bigger than `width` times the pixel size.
+    pub pitch: usize,
+    /// Pixel format.
+    pub format: PixelFormat
+}
+
+/// Graphic device interface.
+pub trait Gfx : Id + Interrupt {
+    /// Current graphic mode.
+    fn mode(&self) -> Result<GfxMode, KError>;
+    /// Set a graphic mode.
+    /// * Return: actual mode set.
+    fn set_mode(&self, width: usize, height: usize, format: PixelFormat) -> Result<GfxMode, KError>;
+    /// Leave the graphic mode and go back to text mode. Drawing fails until a graphic mode is set again.
+    fn text_mode(&self) -> Result<(), KError>;
+    /// Set pixel color at X,Y position.
+    fn put_pixel(&self, x: usize, y: usize, color: Rgb) -> Result<(), KError>;
+    /// Get pixel color at X,Y position.
+    fn get_pixel(&self, x: usize, y: usize) -> Result<Rgb, KError>;
+    /// Fill a rectangle with a color.
+    fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Rgb) -> Result<(), KError>;
+    /// Copy `pixels` into a rectangle. Pixels are ordered by lines and must contain at least `width` times `height` colors.
+    fn blit(&self, x: usize, y: usize, width: usize, height: usize, pixels: &[Rgb]) -> Result<(), KError>;
+    /// Copy a rectangle to another position in the screen. Regions can overlap.
+    fn copy_rect(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError>;
+}
\ No newline at end of file
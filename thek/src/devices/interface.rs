@@ -18,7 +18,9 @@ use super::{
     keyset::Keyset,
     network::Network,
     text::Text,
-    port::Port
+    port::Port,
+    gfx::Gfx,
+    tracker::Tracker
 };
 
 /// Device store.
@@ -28,6 +30,8 @@ struct DeviceStore {
     keyset: HashMap<&'static str, Device>,
     network: HashMap<&'static str, Device>,
     port: HashMap<&'static str, Device>,
+    gfx: HashMap<&'static str, Device>,
+    tracker: HashMap<&'static str, Device>,
     generic: HashMap<&'static str, Device>
 }
 
@@ -53,6 +57,8 @@ impl DeviceStore {
             keyset: def_device_map!(),
             network: def_device_map!(),
             port: def_device_map!(),
+            gfx: def_device_map!(),
+            tracker: def_device_map!(),
             generic: def_device_map!()
         }
     }
@@ -116,6 +122,26 @@ impl DeviceStore {
         self.port.remove(id).is_some()
     }
 
+    /// Get a Gfx device by ID.
+    pub fn get_gfx(&self, id: &str) -> Option<Device> {
+        self.get(&self.gfx, id)
+    }
+    
+    /// Remove a Gfx device by ID.
+    pub fn remove_gfx(&mut self, id: &str) -> bool {
+        self.gfx.remove(id).is_some()
+    }
+
+    /// Get a Tracker device by ID.
+    pub fn get_tracker(&self, id: &str) -> Option<Device> {
+        self.get(&self.tracker, id)
+    }
+    
+    /// Remove a Tracker device by ID.
+    pub fn remove_tracker(&mut self, id: &str) -> bool {
+        self.tracker.remove(id).is_some()
+    }
+
     /// Get a Generic device by ID.
     pub fn get_generic(&self, id: &str) -> Option<Device> {
         self.get(&self.generic, id)
@@ -149,6 +175,14 @@ impl DeviceStore {
                 self.port.insert(m.acquire().id(), device);
                 true
             },
+            Device::Gfx(m) => {
+                self.gfx.insert(m.acquire().id(), device);
+                true
+            },
+            Device::Tracker(m) => {
+                self.tracker.insert(m.acquire().id(), device);
+                true
+            },
             Device::Generic(m) => {
                 self.generic.insert(m.acquire().id(), device);
                 true
@@ -197,6 +231,22 @@ pub fn get_port_device(id: &str) -> Option<Device> {
     }
 }
 
+/// Get a Gfx device by ID.
+pub fn get_gfx_device(id: &str) -> Option<Device> {
+    let _lock = DEVICE_STORE_MUTEX.acquire();
+    unsafe {
+        DEVICE_STORE.get_gfx(id)
+    }
+}
+
+/// Get a Tracker device by ID.
+pub fn get_tracker_device(id: &str) -> Option<Device> {
+    let _lock = DEVICE_STORE_MUTEX.acquire();
+    unsafe {
+        DEVICE_STORE.get_tracker(id)
+    }
+}
+
 /// Get a Generic device by ID.
 pub fn get_generic_device(id: &str) -> Option<Device> {
     let _lock = DEVICE_STORE_MUTEX.acquire();
@@ -225,6 +275,12 @@ pub fn unregister_device(device: Device) -> bool {
             Device::Port(m) => {
                 DEVICE_STORE.remove_port(m.acquire().id())
             },
+            Device::Gfx(m) => {
+                DEVICE_STORE.remove_gfx(m.acquire().id())
+            },
+            Device::Tracker(m) => {
+                DEVICE_STORE.remove_tracker(m.acquire().id())
+            },
             Device::Generic(m) => {
                 DEVICE_STORE.remove_generic(m.acquire().id())
             }
@@ -259,6 +315,10 @@ pub enum Device {
     Network(&'static KMutex<&'static dyn Network>),
     /// Port devices (UART, USB, SPI, ...)
     Port(&'static KMutex<&'static dyn Port>),
+    /// Graphic devices (framebuffers, GPUs, ...)
+    Gfx(&'static KMutex<&'static dyn Gfx>),
+    /// Pointing input devices (mouse, touchpad, touch screen, ...)
+    Tracker(&'static KMutex<&'static dyn Tracker>),
     /// Generic devices. Whatever that is not covered by the other types.
     Generic(&'static KMutex<&'static dyn Generic>)
 }
@@ -314,6 +374,26 @@ impl Device {
         }
     }
 
+    /// Force gfx unwrap and lock device.
+    pub fn unwrap_gfx(&self) -> KLock<'_, &'static dyn Gfx> {
+        if let Device::Gfx(m) = self {
+            m.acquire()
+        }
+        else {
+            panic!("Not a Gfx device");
+        }
+    }
+
+    /// Force tracker unwrap and lock device.
+    pub fn unwrap_tracker(&self) -> KLock<'_, &'static dyn Tracker> {
+        if let Device::Tracker(m) = self {
+            m.acquire()
+        }
+        else {
+            panic!("Not a Tracker device");
+        }
+    }
+
     /// Force generic unwrap and lock device.
     pub fn unwrap_generic(&self) -> KLock<'_, &'static dyn Generic> {
         if let Device::Generic(m) = self {
@@ -335,7 +415,10 @@ pub trait Id {
     /// - `HDD` for hard disks (e.g., HDD1 is the primary hard disk)
     /// - `KBD` for keyboards (e.g., KBD7)
     /// - `CON` for text consoles (e.g., CON1 is the default text output, usually the screen)
+    /// - `FBC` for text consoles on a framebuffer (e.g., FBC1)
     /// - `ETH` for ethernet cards (e.g., ETH2)
+    /// - `GFX` for graphic devices (e.g., GFX1 is the primary screen in graphic mode)
+    /// - `MOU` for mice (e.g., MOU1)
     fn id(&self) -> &str;
 }
 
@@ -348,7 +431,6 @@ pub trait Interrupt {
 
 /*
 Other device types we could define:
-  - Gfx (2D and 3D)
+  - 3D acceleration for Gfx
   - Printer
-  - Tracker (mouse, touchpad, touch screen, etc)
 */
\ No newline at end of file
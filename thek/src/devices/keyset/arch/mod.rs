@@ -0,0 +1,6 @@
+//! Architecture dependent keyset devices.
+
+#[cfg(feature = "pc64")]
+mod pc;
+#[cfg(feature = "pc64")]
+pub use self::pc::*;
@@ -0,0 +1,211 @@
+//! PC PS/2 keyboard device.
+//!
+//! Scancodes (set 1) are received on IRQ 1. Each one is passed first to the virtual consoles to handle the console key combos (see [`vcon::handle_scancode`]), the rest are queued along with their character in the US layout.
+
+use crate::devices::{
+    register_device,
+    keyset::{
+        Keyset, KeyChar
+    },
+    text::vcon,
+    Id, Interrupt, Device
+};
+
+use macros::device;
+
+use crate::cpu::{
+    without_ints,
+    arch::{
+        inb, set_irq_handler
+    }
+};
+
+use crate::sys::KMutex;
+
+#[device(crate::devices::keyset::arch)]
+pub fn register_devices() {
+    if set_irq_handler(KEYBOARD_IRQ, PcKeyboardDevice::isr) {
+        register_device(Device::Keyset(&PC_KEYBOARD_DEVICE_1_MUTEX));
+    }
+}
+
+static PC_KEYBOARD_DEVICE_1 : PcKeyboardDevice = PcKeyboardDevice::new();
+static PC_KEYBOARD_DEVICE_1_MUTEX : KMutex<&'static dyn Keyset> = KMutex::new(&PC_KEYBOARD_DEVICE_1);
+
+const KEYBOARD_IRQ : u8 = 1;
+
+const PS2_DATA : u16 = 0x60;
+
+/// Prefix of the extended scancodes.
+const SCANCODE_EXTENDED : u8 = 0xE0;
+/// Bit set in the scancodes of key releases.
+const SCANCODE_RELEASE : u8 = 0x80;
+
+const SCANCODE_LEFT_SHIFT : u8 = 0x2A;
+const SCANCODE_RIGHT_SHIFT : u8 = 0x36;
+const SCANCODE_CAPS_LOCK : u8 = 0x3A;
+
+/// Characters of the US layout for scancodes 0x00 to 0x39, 0 if the key has no character.
+const US_LAYOUT : &[u8; 0x3A] = b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+/// Same, with shift pressed.
+const US_LAYOUT_SHIFT : &[u8; 0x3A] = b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+const QUEUE_SIZE : usize = 64;
+
+/// Modifiers and key queue, shared with the interrupt handler.
+struct KeyboardState {
+    shift: bool,
+    caps_lock: bool,
+    extended: bool,
+    /// Scancodes received and their characters.
+    queue: [(u8, Option<KeyChar>); QUEUE_SIZE],
+    head: usize,
+    count: usize,
+    handler: Option<fn(Device)>
+}
+
+impl KeyboardState {
+    fn push(&mut self, key: (u8, Option<KeyChar>)) {
+        if self.count == QUEUE_SIZE {
+            // Queue full, drop the oldest key
+            self.head = (self.head + 1) % QUEUE_SIZE;
+            self.count -= 1;
+        }
+        self.queue[(self.head + self.count) % QUEUE_SIZE] = key;
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<(u8, Option<KeyChar>)> {
+        if self.count == 0 {
+            None
+        }
+        else {
+            let key = self.queue[self.head];
+            self.head = (self.head + 1) % QUEUE_SIZE;
+            self.count -= 1;
+            Some(key)
+        }
+    }
+
+    /// Process a scancode, updating the modifiers.
+    /// * Return: character of the key, if it has one.
+    fn receive(&mut self, code: u8) -> Option<KeyChar> {
+        if code == SCANCODE_EXTENDED {
+            self.extended = true;
+            return None;
+        }
+        let extended = core::mem::replace(&mut self.extended, false);
+        let release = code & SCANCODE_RELEASE != 0;
+        let key = code & !SCANCODE_RELEASE;
+        if extended {
+            // Arrows, right control, etc. Extended shifts are fake, sent along with other keys
+            return None;
+        }
+        match key {
+            SCANCODE_LEFT_SHIFT | SCANCODE_RIGHT_SHIFT => {
+                self.shift = !release;
+                None
+            },
+            SCANCODE_CAPS_LOCK => {
+                if !release {
+                    self.caps_lock = !self.caps_lock;
+                }
+                None
+            },
+            _ => {
+                let lower = *US_LAYOUT.get(key as usize)?;
+                // Caps lock only affects letters
+                let shift = if lower.is_ascii_alphabetic() { self.shift != self.caps_lock } else { self.shift };
+                let c = if shift { US_LAYOUT_SHIFT[key as usize] } else { lower };
+                match c {
+                    0 => None,
+                    _ if release => Some(KeyChar::Release(c as char)),
+                    _ => Some(KeyChar::Press(c as char))
+                }
+            }
+        }
+    }
+}
+
+static KEYBOARD_STATE : KMutex<KeyboardState> = KMutex::new(KeyboardState {
+    shift: false,
+    caps_lock: false,
+    extended: false,
+    queue: [(0, None); QUEUE_SIZE],
+    head: 0,
+    count: 0,
+    handler: None
+});
+
+/// PC PS/2 keyboard device.
+pub struct PcKeyboardDevice {
+}
+
+impl PcKeyboardDevice {
+    const fn new() -> Self {
+        Self {}
+    }
+
+    /// Run `func` with the keyboard state locked. The state is also locked by the interrupt handler, so interrupts are disabled meanwhile.
+    fn with_state<T>(func: impl FnOnce(&mut KeyboardState) -> T) -> T {
+        without_ints(|| func(&mut KEYBOARD_STATE.acquire()))
+    }
+
+    /// IRQ 1 handler. Runs with interrupts disabled.
+    fn isr() {
+        let code = inb(PS2_DATA);
+        // Console key combos are not seen by the keyboard users
+        if vcon::handle_scancode(code) {
+            return;
+        }
+        let mut state = KEYBOARD_STATE.acquire();
+        let key = state.receive(code);
+        state.push((code, key));
+        let handler = state.handler;
+        drop(state);
+        if let Some(func) = handler {
+            func(Device::Keyset(&PC_KEYBOARD_DEVICE_1_MUTEX));
+        }
+    }
+}
+
+impl Keyset for PcKeyboardDevice {
+    fn is_ready(&self) -> bool {
+        Self::with_state(|state| state.count > 0)
+    }
+
+    fn read(&self) -> u8 {
+        loop {
+            if let Some((code, _)) = Self::with_state(|state| state.pop()) {
+                return code;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn char_read(&self) -> KeyChar {
+        loop {
+            // Keys without character are discarded
+            if let Some((_, Some(key))) = Self::with_state(|state| state.pop()) {
+                return key;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl Id for PcKeyboardDevice {
+    fn id(&self) -> &str {
+        "KBD1"
+    }
+}
+
+impl Interrupt for PcKeyboardDevice {
+    /// Set a function to be called from the interrupt handler each time a key is queued.
+    ///
+    /// The device lock may be held by the interrupted code, so the handler must not block on it. Use [`Keyset::is_ready`] and [`Keyset::read`] from regular code instead.
+    fn handler(&self, func: fn(Device)) -> bool {
+        Self::with_state(|state| state.handler = Some(func));
+        true
+    }
+}
\ No newline at end of file
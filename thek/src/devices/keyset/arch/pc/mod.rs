@@ -0,0 +1,4 @@
+//! PC keyset devices.
+
+pub mod device;
+pub use self::device::*;
@@ -4,7 +4,10 @@ use super::{
     Id, Interrupt
 };
 
+pub mod arch;
+
 /// Processed char.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum KeyChar {
     Press(char),
     Release(char)
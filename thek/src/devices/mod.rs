@@ -12,5 +12,9 @@ pub mod generic;
 
 pub mod keyset;
 
+pub mod gfx;
+
+pub mod tracker;
+
 mod interface;
 pub use self::interface::*;
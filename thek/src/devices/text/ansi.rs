@@ -1,11 +1,13 @@
 //! Character and ANSI utils.
 
-use crate::sys::KError;
+use crate::sys::{
+    KError, Encoding, REPLACEMENT_BYTE
+};
 
 /// Define an ANSI color
 /// 
 /// More info about ANSI colors: <https://en.wikipedia.org/wiki/ANSI_escape_code#Colors>
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum AnsiColor {
     // Basic terminals
     Black,
@@ -41,20 +43,405 @@ pub trait IntoChar {
     fn into_char(&self) -> Result<char, Self::Error>;
 }
 
-// TODO: use different encodings
 /// Convert char into ASCII character (8 bits, assuming Latin-1 encoding).
+/// 
+/// Chars outside Latin-1 are converted into [`REPLACEMENT_BYTE`]. For other encodings use [`Encoding`].
 impl IntoAscii for char {
     type Error = KError;
     fn into_ascii(&self) -> Result<u8, Self::Error> {
-        Ok(*self as u8)
+        Ok(Encoding::Latin1.encode_byte(*self).unwrap_or(REPLACEMENT_BYTE))
     }
 }
 
-// TODO: use different encodings
 /// Convert u8 ASCII into character (8 bits, assuming Latin-1 encoding).
 impl IntoChar for u8 {
     type Error = KError;
     fn into_char(&self) -> Result<char, Self::Error> {
-        Ok(*self as char)
+        Ok(Encoding::Latin1.decode_byte(*self))
+    }
+}
+
+impl AnsiColor {
+    /// Get the bright version of a basic color. Other colors are returned unchanged.
+    pub fn bright(self) -> Self {
+        match self {
+            AnsiColor::Black => AnsiColor::BrightBlack,
+            AnsiColor::Red => AnsiColor::BrightRed,
+            AnsiColor::Green => AnsiColor::BrightGreen,
+            AnsiColor::Yellow => AnsiColor::BrightYellow,
+            AnsiColor::Blue => AnsiColor::BrightBlue,
+            AnsiColor::Magenta => AnsiColor::BrightMagenta,
+            AnsiColor::Cyan => AnsiColor::BrightCyan,
+            AnsiColor::White => AnsiColor::BrightWhite,
+            _ => self
+        }
+    }
+
+    /// Get a basic color from its index (0 to 7) in the ANSI color table.
+    pub(crate) fn from_index(index: u16) -> Self {
+        match index {
+            0 => AnsiColor::Black,
+            1 => AnsiColor::Red,
+            2 => AnsiColor::Green,
+            3 => AnsiColor::Yellow,
+            4 => AnsiColor::Blue,
+            5 => AnsiColor::Magenta,
+            6 => AnsiColor::Cyan,
+            _ => AnsiColor::White
+        }
+    }
+}
+
+/// Maximum number of parameters in a control sequence.
+const MAX_ANSI_PARAMS: usize = 16;
+
+/// Erase mode for erase commands.
+#[derive(Copy, Clone)]
+pub enum EraseMode {
+    /// From cursor to the end.
+    ToEnd,
+    /// From the beginning to cursor.
+    ToCursor,
+    /// Everything.
+    All
+}
+
+impl From<u16> for EraseMode {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => EraseMode::ToCursor,
+            2 | 3 => EraseMode::All,
+            _ => EraseMode::ToEnd
+        }
+    }
+}
+
+/// Parameters of a control sequence.
+#[derive(Copy, Clone)]
+pub struct AnsiParams {
+    params: [u16; MAX_ANSI_PARAMS],
+    len: usize
+}
+
+impl AnsiParams {
+    const fn new() -> Self {
+        Self {
+            params: [0; MAX_ANSI_PARAMS],
+            len: 0
+        }
+    }
+
+    /// Get parameter at index, or `default` if it's not present or zero.
+    pub fn get(&self, index: usize, default: u16) -> u16 {
+        if index < self.len && self.params[index] != 0 {
+            self.params[index]
+        }
+        else {
+            default
+        }
+    }
+
+    /// Iterate SGR (Select Graphic Rendition) attributes defined by the parameters.
+    pub fn sgr_iter(&self) -> SgrIter<'_> {
+        SgrIter {
+            params: self,
+            index: 0,
+            first: true
+        }
+    }
+}
+
+/// Graphic rendition attribute.
+#[derive(Copy, Clone)]
+pub enum SgrAttr {
+    /// Reset all attributes.
+    Reset,
+    /// Bold or increased intensity.
+    Bold,
+    /// Normal intensity.
+    Normal,
+    /// Swap text and background colors.
+    Inverse,
+    /// Disable inverse.
+    NotInverse,
+    /// Set text color.
+    TextColor(AnsiColor),
+    /// Set background color.
+    BgColor(AnsiColor),
+    /// Default text color.
+    DefaultTextColor,
+    /// Default background color.
+    DefaultBgColor,
+    /// Unsupported attribute.
+    Unsupported
+}
+
+/// Iterator over SGR attributes, returned by [`AnsiParams::sgr_iter()`].
+pub struct SgrIter<'a> {
+    params: &'a AnsiParams,
+    index: usize,
+    first: bool
+}
+
+impl<'a> Iterator for SgrIter<'a> {
+    type Item = SgrAttr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // "CSI m" without params is equivalent to "CSI 0 m"
+        if self.params.len == 0 && self.first {
+            self.first = false;
+            return Some(SgrAttr::Reset);
+        }
+        if self.index >= self.params.len {
+            return None;
+        }
+        let code = self.params.params[self.index];
+        self.index += 1;
+        let attr = match code {
+            0 => SgrAttr::Reset,
+            1 => SgrAttr::Bold,
+            22 => SgrAttr::Normal,
+            7 => SgrAttr::Inverse,
+            27 => SgrAttr::NotInverse,
+            30..=37 => SgrAttr::TextColor(AnsiColor::from_index(code - 30)),
+            39 => SgrAttr::DefaultTextColor,
+            40..=47 => SgrAttr::BgColor(AnsiColor::from_index(code - 40)),
+            49 => SgrAttr::DefaultBgColor,
+            90..=97 => SgrAttr::TextColor(AnsiColor::from_index(code - 90).bright()),
+            100..=107 => SgrAttr::BgColor(AnsiColor::from_index(code - 100).bright()),
+            38 | 48 => {
+                // Extended color: "38;5;N" or "38;2;R;G;B". Only 256 color mode is supported.
+                let mode = self.params.get(self.index, 0);
+                match mode {
+                    5 => {
+                        let color = AnsiColor::Color256(self.params.get(self.index + 1, 0) as u8);
+                        self.index += 2;
+                        if code == 38 {
+                            SgrAttr::TextColor(color)
+                        }
+                        else {
+                            SgrAttr::BgColor(color)
+                        }
+                    },
+                    2 => {
+                        self.index += 4;
+                        SgrAttr::Unsupported
+                    },
+                    _ => {
+                        self.index += 1;
+                        SgrAttr::Unsupported
+                    }
+                }
+            },
+            _ => SgrAttr::Unsupported
+        };
+        Some(attr)
+    }
+}
+
+/// ANSI command, produced by [`AnsiParser`].
+#[derive(Copy, Clone)]
+pub enum AnsiCommand {
+    /// Move cursor up N lines.
+    CursorUp(usize),
+    /// Move cursor down N lines.
+    CursorDown(usize),
+    /// Move cursor forward N columns.
+    CursorForward(usize),
+    /// Move cursor back N columns.
+    CursorBack(usize),
+    /// Move cursor to the beginning of the line N lines down.
+    CursorNextLine(usize),
+    /// Move cursor to the beginning of the line N lines up.
+    CursorPrevLine(usize),
+    /// Move cursor to column (0-based).
+    CursorColumn(usize),
+    /// Move cursor to row (0-based).
+    CursorRow(usize),
+    /// Move cursor to column and row (0-based).
+    CursorPosition(usize, usize),
+    /// Erase part of the screen.
+    EraseDisplay(EraseMode),
+    /// Erase part of the current line.
+    EraseLine(EraseMode),
+    /// Scroll region up N lines.
+    ScrollUp(usize),
+    /// Scroll region down N lines.
+    ScrollDown(usize),
+    /// Set scroll region, top and bottom rows (0-based, inclusive). Bottom `None` means the last row.
+    ScrollRegion(usize, Option<usize>),
+    /// Save cursor position.
+    SaveCursor,
+    /// Restore cursor position.
+    RestoreCursor,
+    /// Select graphic rendition.
+    Sgr(AnsiParams),
+    /// Sequence not supported, ignored.
+    Unsupported
+}
+
+/// Result of feeding a char to [`AnsiParser`].
+pub enum AnsiAction {
+    /// Char is not part of an escape sequence.
+    Print(char),
+    /// Char was consumed by the parser, sequence not yet finished.
+    None,
+    /// A complete sequence was parsed.
+    Command(AnsiCommand)
+}
+
+#[derive(Copy, Clone)]
+enum ParserState {
+    Ground,
+    Escape,
+    Csi
+}
+
+/// ANSI escape sequence parser.
+/// 
+/// It's a state machine fed char by char, so sequences can be splitted between different writes.
+/// Supports CSI sequences and the ESC 7 / ESC 8 cursor save and restore.
+/// 
+/// More info about ANSI escape sequences: <https://en.wikipedia.org/wiki/ANSI_escape_code>
+pub struct AnsiParser {
+    state: ParserState,
+    params: AnsiParams,
+    /// A digit was found for the current parameter.
+    in_param: bool,
+    /// Private sequence (starting with `?`), like DEC private modes.
+    private: bool
+}
+
+impl AnsiParser {
+    /// Create new parser.
+    pub const fn new() -> Self {
+        Self {
+            state: ParserState::Ground,
+            params: AnsiParams::new(),
+            in_param: false,
+            private: false
+        }
+    }
+
+    /// Feed one char into the parser.
+    pub fn feed(&mut self, ch: char) -> AnsiAction {
+        match self.state {
+            ParserState::Ground => {
+                if ch == '\x1b' {
+                    self.state = ParserState::Escape;
+                    AnsiAction::None
+                }
+                else {
+                    AnsiAction::Print(ch)
+                }
+            },
+            ParserState::Escape => {
+                match ch {
+                    '[' => {
+                        self.params = AnsiParams::new();
+                        self.in_param = false;
+                        self.private = false;
+                        self.state = ParserState::Csi;
+                        AnsiAction::None
+                    },
+                    '7' => {
+                        self.state = ParserState::Ground;
+                        AnsiAction::Command(AnsiCommand::SaveCursor)
+                    },
+                    '8' => {
+                        self.state = ParserState::Ground;
+                        AnsiAction::Command(AnsiCommand::RestoreCursor)
+                    },
+                    _ => {
+                        self.state = ParserState::Ground;
+                        AnsiAction::Command(AnsiCommand::Unsupported)
+                    }
+                }
+            },
+            ParserState::Csi => {
+                match ch {
+                    '0'..='9' => {
+                        if !self.in_param {
+                            self.push_param();
+                            self.in_param = true;
+                        }
+                        if self.params.len > 0 {
+                            let p = &mut self.params.params[self.params.len - 1];
+                            *p = p.saturating_mul(10).saturating_add(ch as u16 - '0' as u16);
+                        }
+                        AnsiAction::None
+                    },
+                    ';' => {
+                        if !self.in_param {
+                            // Empty parameter
+                            self.push_param();
+                        }
+                        self.in_param = false;
+                        AnsiAction::None
+                    },
+                    '?' | '<' | '=' | '>' => {
+                        self.private = true;
+                        AnsiAction::None
+                    },
+                    '\x20'..='\x2f' => {
+                        // Intermediate bytes, ignore
+                        AnsiAction::None
+                    },
+                    '\x40'..='\x7e' => {
+                        self.state = ParserState::Ground;
+                        AnsiAction::Command(self.command(ch))
+                    },
+                    _ => {
+                        // Invalid char, abort sequence
+                        self.state = ParserState::Ground;
+                        AnsiAction::Command(AnsiCommand::Unsupported)
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len < MAX_ANSI_PARAMS {
+            self.params.params[self.params.len] = 0;
+            self.params.len += 1;
+        }
+    }
+
+    fn command(&self, final_byte: char) -> AnsiCommand {
+        if self.private {
+            return AnsiCommand::Unsupported;
+        }
+        let p = &self.params;
+        let n = p.get(0, 1) as usize;
+        match final_byte {
+            'A' => AnsiCommand::CursorUp(n),
+            'B' => AnsiCommand::CursorDown(n),
+            'C' => AnsiCommand::CursorForward(n),
+            'D' => AnsiCommand::CursorBack(n),
+            'E' => AnsiCommand::CursorNextLine(n),
+            'F' => AnsiCommand::CursorPrevLine(n),
+            'G' => AnsiCommand::CursorColumn(n - 1),
+            'd' => AnsiCommand::CursorRow(n - 1),
+            'H' | 'f' => AnsiCommand::CursorPosition(p.get(1, 1) as usize - 1, n - 1),
+            'J' => AnsiCommand::EraseDisplay(EraseMode::from(p.get(0, 0))),
+            'K' => AnsiCommand::EraseLine(EraseMode::from(p.get(0, 0))),
+            'S' => AnsiCommand::ScrollUp(n),
+            'T' => AnsiCommand::ScrollDown(n),
+            'r' => {
+                let bottom = p.get(1, 0) as usize;
+                AnsiCommand::ScrollRegion(n - 1, if bottom > 0 { Some(bottom - 1) } else { None })
+            },
+            's' => AnsiCommand::SaveCursor,
+            'u' => AnsiCommand::RestoreCursor,
+            'm' => AnsiCommand::Sgr(*p),
+            _ => AnsiCommand::Unsupported
+        }
+    }
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        Self::new()
     }
 }
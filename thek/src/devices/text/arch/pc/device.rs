@@ -4,9 +4,10 @@ use crate::devices::{
     register_device,
     text::{
         Text, CursorShape, CursorBlink,
-        ansi::{
-            AnsiColor, IntoAscii, IntoChar
-        }
+        vcon::{
+            self, NUM_VCONS
+        },
+        ansi::AnsiColor
     },
     Id, Interrupt, Device
 };
@@ -18,16 +19,21 @@ use crate::cpu::arch::{
 };
 
 use crate::sys::{
-    KMutex, KError
+    KError, Encoding, REPLACEMENT_BYTE
 };
 
 #[device(crate::devices::text::arch)]
 pub fn register_devices() {
-    register_device(Device::Text(&VGA_TEXT_DEVICE_1_MUTEX));
+    // The VGA device is not registered directly, it's multiplexed by the virtual consoles
+    vcon::init_vcons(&VGA_TEXT_DEVICE_1);
+    for i in 0..NUM_VCONS {
+        if let Some(vcon_mutex) = vcon::vcon_device(i) {
+            register_device(Device::Text(vcon_mutex));
+        }
+    }
 }
 
 static VGA_TEXT_DEVICE_1 : VgaTextDevice = VgaTextDevice::new();
-static VGA_TEXT_DEVICE_1_MUTEX : KMutex<&'static dyn Text> = KMutex::new(&VGA_TEXT_DEVICE_1);
 
 /// Vga console colors
 #[derive(Copy, Clone)]
@@ -131,6 +137,9 @@ impl From<u8> for VgaConsoleColor {
     }
 }
 
+/// Encoding of the VGA hardware font.
+const VGA_ENCODING : Encoding = Encoding::Cp437;
+
 const CONSOLE_COLS : usize = 80;
 const CONSOLE_ROWS : usize = 25;
 
@@ -142,6 +151,21 @@ impl VgaTextDevice {
     const fn new() -> Self {
         Self {}
     }
+
+    fn encode(ch: char) -> u8 {
+        VGA_ENCODING.encode_byte(ch).unwrap_or(REPLACEMENT_BYTE)
+    }
+
+    /// Build a VGA cell: char in the low byte and color in the high byte.
+    fn cell(text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> u16 {
+        let color = ((VgaConsoleColor::from(bg_color) as u8) << 4) | (VgaConsoleColor::from(text_color) as u8);
+        ((color as u16) << 8) | Self::encode(ch) as u16
+    }
+
+    /// Pointer to the cell at X,Y position in the VGA buffer.
+    fn cell_ptr(x: usize, y: usize) -> *mut u16 {
+        (0xB8000 + (CONSOLE_COLS * y + x) * 2) as *mut u16
+    }
 }
 
 impl Text for VgaTextDevice {
@@ -149,7 +173,7 @@ impl Text for VgaTextDevice {
         if x < CONSOLE_COLS && y < CONSOLE_ROWS {
             let pos = CONSOLE_COLS * y + x;
             unsafe {
-                *((0xB8000 + pos * 2) as *mut u8) = ch.into_ascii()?;
+                *((0xB8000 + pos * 2) as *mut u8) = Self::encode(ch);
                 let color = ((VgaConsoleColor::from(bg_color) as u8) << 4) | (VgaConsoleColor::from(text_color) as u8);
                 *((0xB8000 + pos * 2 + 1) as *mut u8) = color;
             }
@@ -164,7 +188,7 @@ impl Text for VgaTextDevice {
         if x < CONSOLE_COLS && y < CONSOLE_ROWS {
             let pos = CONSOLE_COLS * y + x;
             unsafe {
-                *((0xB8000 + pos * 2) as *mut u8) = ch.into_ascii()?;
+                *((0xB8000 + pos * 2) as *mut u8) = Self::encode(ch);
             }
             Ok(())
         }
@@ -202,7 +226,7 @@ impl Text for VgaTextDevice {
             
             Ok(
                 (
-                    ch.into_char()?,
+                    VGA_ENCODING.decode_byte(ch),
                     AnsiColor::from(text_color),
                     AnsiColor::from(bg_color)
                 )
@@ -270,11 +294,64 @@ impl Text for VgaTextDevice {
     fn size(&self) -> Result<(usize, usize), KError> {
         Ok((CONSOLE_COLS, CONSOLE_ROWS))
     }
+
+    fn copy_region(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        if src_x + width > CONSOLE_COLS || dst_x + width > CONSOLE_COLS || src_y + height > CONSOLE_ROWS || dst_y + height > CONSOLE_ROWS {
+            return Err(KError::OutBounds);
+        }
+        unsafe {
+            if width == CONSOLE_COLS {
+                // Full lines are contiguous in memory, copy all at once
+                core::ptr::copy(Self::cell_ptr(0, src_y), Self::cell_ptr(0, dst_y), width * height);
+            }
+            else if dst_y > src_y {
+                // Copy lines backwards to not overwrite source lines before they are copied
+                for dy in (0..height).rev() {
+                    core::ptr::copy(Self::cell_ptr(src_x, src_y + dy), Self::cell_ptr(dst_x, dst_y + dy), width);
+                }
+            }
+            else {
+                for dy in 0..height {
+                    core::ptr::copy(Self::cell_ptr(src_x, src_y + dy), Self::cell_ptr(dst_x, dst_y + dy), width);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_region(&self, x: usize, y: usize, width: usize, height: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        if x + width > CONSOLE_COLS || y + height > CONSOLE_ROWS {
+            return Err(KError::OutBounds);
+        }
+        let cell = Self::cell(text_color, bg_color, ch);
+        for dy in 0..height {
+            let line_ptr = Self::cell_ptr(x, y + dy);
+            for dx in 0..width {
+                unsafe {
+                    *line_ptr.add(dx) = cell;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_span(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, chars: &[char]) -> Result<(), KError> {
+        if x + chars.len() > CONSOLE_COLS || y >= CONSOLE_ROWS {
+            return Err(KError::OutBounds);
+        }
+        let line_ptr = Self::cell_ptr(x, y);
+        for (i, ch) in chars.iter().enumerate() {
+            unsafe {
+                *line_ptr.add(i) = Self::cell(text_color, bg_color, *ch);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Id for VgaTextDevice {
     fn id(&self) -> &str {
-        "CON1"
+        "VGA1"
     }
 }
 
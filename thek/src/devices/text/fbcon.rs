@@ -0,0 +1,310 @@
+//! Framebuffer text console.
+//!
+//! Text device that renders the glyphs of a PSF font on a graphic device, so text controllers work unchanged on graphic modes.
+//! The console size depends on the graphic mode and the font size, for example a 1024x768 mode with the built-in 8x16 font gives a 128x48 console.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let gfx = devices::get_gfx_device("GFX1").unwrap();
+//! gfx.unwrap_gfx().set_mode(1024, 768, PixelFormat::Xrgb8888).unwrap();
+//! fbcon::init_fbcon("GFX1", None).unwrap();
+//! let mut con = TextController::new(AnsiColor::White, AnsiColor::Black, "FBC1".to_owned()).unwrap();
+//! ```
+
+use alloc::{
+    vec,
+    vec::Vec
+};
+
+use super::{
+    Text, CursorShape, CursorBlink,
+    ansi::AnsiColor,
+    psf::{
+        PsfFont, DEFAULT_FONT
+    }
+};
+
+use crate::devices::{
+    self, register_device,
+    gfx::Rgb,
+    Id, Interrupt, Device
+};
+
+use crate::sys::{
+    KMutex, KError
+};
+
+/// Init the framebuffer console on graphic device `gfx_id`, that must be already in graphic mode, and register it as text device `FBC1`.
+///
+/// Uses the built-in font if `font` is `None`, otherwise it must contain a PSF1 or PSF2 font.
+pub fn init_fbcon(gfx_id: &str, font: Option<&'static [u8]>) -> Result<Device, KError> {
+    let gfx = devices::get_gfx_device(gfx_id).ok_or(KError::Other)?;
+    let mode = gfx.unwrap_gfx().mode()?;
+    let font = PsfFont::parse(font.unwrap_or(DEFAULT_FONT))?;
+    let (font_width, font_height) = font.size();
+    let (cols, rows) = (mode.width / font_width, mode.height / font_height);
+    if cols == 0 || rows == 0 {
+        return Err(KError::OutBounds);
+    }
+    let mut console = FbConsole {
+        gfx,
+        font,
+        cols, rows,
+        cells: vec![Cell::default(); cols * rows],
+        cursor: (0, 0),
+        cursor_enabled: true,
+        cursor_rows: (font_height.saturating_sub(2), font_height),
+        glyph_buf: Vec::with_capacity(font_width * font_height)
+    };
+    console.fill(0, 0, cols, rows, Cell::default())?;
+    *FBCON.acquire() = Some(console);
+    let device = Device::Text(&FBCON_DEVICE_MUTEX);
+    register_device(device);
+    Ok(device)
+}
+
+static FBCON_DEVICE : FbTextDevice = FbTextDevice::new();
+static FBCON_DEVICE_MUTEX : KMutex<&'static dyn Text> = KMutex::new(&FBCON_DEVICE);
+static FBCON : KMutex<Option<FbConsole>> = KMutex::new(None);
+
+/// Console char.
+#[derive(Copy, Clone)]
+struct Cell {
+    ch: char,
+    text_color: AnsiColor,
+    bg_color: AnsiColor
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            text_color: AnsiColor::White,
+            bg_color: AnsiColor::Black
+        }
+    }
+}
+
+/// Console state.
+struct FbConsole {
+    gfx: Device,
+    font: PsfFont,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    cursor: (usize, usize),
+    cursor_enabled: bool,
+    /// Glyph rows covered by the cursor, from first (included) to last (excluded).
+    cursor_rows: (usize, usize),
+    /// Pixels of the glyph being drawn.
+    glyph_buf: Vec<Rgb>
+}
+
+impl FbConsole {
+    fn check_region(&self, x: usize, y: usize, width: usize, height: usize) -> Result<(), KError> {
+        if x + width > self.cols || y + height > self.rows {
+            Err(KError::OutBounds)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    fn is_cursor_in(&self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        let (cx, cy) = self.cursor;
+        self.cursor_enabled && cx >= x && cx < x + width && cy >= y && cy < y + height
+    }
+
+    /// Render the cell at X,Y position, with the cursor if it's there.
+    fn draw_cell(&mut self, x: usize, y: usize) -> Result<(), KError> {
+        let cell = self.cells[y * self.cols + x];
+        let (text_color, bg_color) = (Rgb::from(cell.text_color), Rgb::from(cell.bg_color));
+        let (font_width, font_height) = self.font.size();
+        let with_cursor = self.cursor_enabled && self.cursor == (x, y);
+        let glyph = self.font.glyph(cell.ch);
+        self.glyph_buf.clear();
+        for gy in 0..font_height {
+            let inverse = with_cursor && gy >= self.cursor_rows.0 && gy < self.cursor_rows.1;
+            for gx in 0..font_width {
+                let set = glyph.is_set(gx, gy) != inverse;
+                self.glyph_buf.push(if set { text_color } else { bg_color });
+            }
+        }
+        self.gfx.unwrap_gfx().blit(x * font_width, y * font_height, font_width, font_height, &self.glyph_buf)
+    }
+
+    fn redraw_cursor(&mut self) -> Result<(), KError> {
+        let (x, y) = self.cursor;
+        self.draw_cell(x, y)
+    }
+
+    fn write(&mut self, x: usize, y: usize, cell: Cell) -> Result<(), KError> {
+        self.check_region(x, y, 1, 1)?;
+        self.cells[y * self.cols + x] = cell;
+        self.draw_cell(x, y)
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, cell: Cell) -> Result<(), KError> {
+        self.check_region(x, y, width, height)?;
+        for line in self.cells.chunks_mut(self.cols).skip(y).take(height) {
+            line[x..(x + width)].fill(cell);
+        }
+        if cell.ch == ' ' || cell.ch == '\0' {
+            // Empty chars are just background, fill the whole region at once
+            let (font_width, font_height) = self.font.size();
+            self.gfx.unwrap_gfx().fill_rect(x * font_width, y * font_height, width * font_width, height * font_height, Rgb::from(cell.bg_color))?;
+            if self.is_cursor_in(x, y, width, height) {
+                self.redraw_cursor()?;
+            }
+        }
+        else {
+            for dy in 0..height {
+                for dx in 0..width {
+                    self.draw_cell(x + dx, y + dy)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        self.check_region(src_x, src_y, width, height)?;
+        self.check_region(dst_x, dst_y, width, height)?;
+        let cols = self.cols;
+        // Copy lines backwards if destination is below source, to not overwrite source lines before they are copied
+        let mut copy_line = |dy: usize| {
+            let src = (src_y + dy) * cols + src_x;
+            self.cells.copy_within(src..(src + width), (dst_y + dy) * cols + dst_x);
+        };
+        if dst_y > src_y {
+            (0..height).rev().for_each(&mut copy_line);
+        }
+        else {
+            (0..height).for_each(&mut copy_line);
+        }
+        let (font_width, font_height) = self.font.size();
+        self.gfx.unwrap_gfx().copy_rect(
+            src_x * font_width, src_y * font_height,
+            dst_x * font_width, dst_y * font_height,
+            width * font_width, height * font_height
+        )?;
+        // The cursor image may have been copied or overwritten
+        if self.is_cursor_in(src_x, src_y, width, height) {
+            let (cx, cy) = self.cursor;
+            self.draw_cell(cx - src_x + dst_x, cy - src_y + dst_y)?;
+        }
+        if self.is_cursor_in(dst_x, dst_y, width, height) {
+            self.redraw_cursor()?;
+        }
+        Ok(())
+    }
+}
+
+/// Framebuffer text device.
+pub struct FbTextDevice {
+}
+
+impl FbTextDevice {
+    const fn new() -> Self {
+        Self {}
+    }
+
+    fn with_console<T>(func: impl FnOnce(&mut FbConsole) -> Result<T, KError>) -> Result<T, KError> {
+        if let Some(console) = FBCON.acquire().as_mut() {
+            func(console)
+        }
+        else {
+            Err(KError::Other)
+        }
+    }
+}
+
+impl Text for FbTextDevice {
+    fn write(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        Self::with_console(|con| con.write(x, y, Cell { ch, text_color, bg_color }))
+    }
+
+    fn put_char(&self, x: usize, y: usize, ch: char) -> Result<(), KError> {
+        Self::with_console(|con| {
+            con.check_region(x, y, 1, 1)?;
+            let cell = con.cells[y * con.cols + x];
+            con.write(x, y, Cell { ch, ..cell })
+        })
+    }
+
+    fn put_color(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor) -> Result<(), KError> {
+        Self::with_console(|con| {
+            con.check_region(x, y, 1, 1)?;
+            let cell = con.cells[y * con.cols + x];
+            con.write(x, y, Cell { text_color, bg_color, ..cell })
+        })
+    }
+
+    fn read(&self, x: usize, y: usize) -> Result<(char, AnsiColor, AnsiColor), KError> {
+        Self::with_console(|con| {
+            con.check_region(x, y, 1, 1)?;
+            let cell = con.cells[y * con.cols + x];
+            Ok((cell.ch, cell.text_color, cell.bg_color))
+        })
+    }
+
+    fn set_position(&self, x: usize, y: usize) -> Result<(), KError> {
+        Self::with_console(|con| {
+            con.check_region(x, y, 1, 1)?;
+            let (old_x, old_y) = con.cursor;
+            con.cursor = (x, y);
+            con.draw_cell(old_x, old_y)?;
+            con.redraw_cursor()
+        })
+    }
+
+    fn get_position(&self) -> Result<(usize, usize), KError> {
+        Self::with_console(|con| Ok(con.cursor))
+    }
+
+    fn config_cursor(&self, enabled: bool, shape: CursorShape, _blink: CursorBlink) -> Result<(), KError> {
+        Self::with_console(|con| {
+            let (_, font_height) = con.font.size();
+            con.cursor_enabled = enabled;
+            con.cursor_rows = match shape {
+                CursorShape::FullBlock => (0, font_height),
+                CursorShape::HalfBlock => (font_height / 2, font_height),
+                CursorShape::UnderLine | CursorShape::Default => (font_height.saturating_sub(2), font_height)
+            };
+            con.redraw_cursor()
+        })
+    }
+
+    fn size(&self) -> Result<(usize, usize), KError> {
+        Self::with_console(|con| Ok((con.cols, con.rows)))
+    }
+
+    fn copy_region(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        Self::with_console(|con| con.copy(src_x, src_y, dst_x, dst_y, width, height))
+    }
+
+    fn fill_region(&self, x: usize, y: usize, width: usize, height: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        Self::with_console(|con| con.fill(x, y, width, height, Cell { ch, text_color, bg_color }))
+    }
+
+    fn write_span(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, chars: &[char]) -> Result<(), KError> {
+        Self::with_console(|con| {
+            con.check_region(x, y, chars.len(), 1)?;
+            for (i, ch) in chars.iter().enumerate() {
+                con.write(x + i, y, Cell { ch: *ch, text_color, bg_color })?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Id for FbTextDevice {
+    fn id(&self) -> &str {
+        "FBC1"
+    }
+}
+
+impl Interrupt for FbTextDevice {
+    fn handler(&self, _: fn(Device)) -> bool { false }
+}
\ No newline at end of file
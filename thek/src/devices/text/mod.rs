@@ -8,10 +8,15 @@ use crate::{
     sys::KError
 };
 
-use ansi::AnsiColor;
+use ansi::{
+    AnsiColor, IntoChar
+};
 
 pub mod arch;
 pub mod ansi;
+pub mod vcon;
+pub mod psf;
+pub mod fbcon;
 
 /// Text screen cursor shape.
 pub enum CursorShape {
@@ -50,4 +55,50 @@ pub trait Text : Id + Interrupt {
     fn config_cursor(&self, enabled: bool, shape: CursorShape, blink: CursorBlink) -> Result<(), KError>;
     /// Get screen size in Columns,Rows.
     fn size(&self) -> Result<(usize, usize), KError>;
-}
+    /// Copy a region of `width` x `height` chars, with their colors, from `src_x`,`src_y` to `dst_x`,`dst_y`. Source and destination can overlap.
+    fn copy_region(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if src_x + width > cols || dst_x + width > cols || src_y + height > rows || dst_y + height > rows {
+            return Err(KError::OutBounds);
+        }
+        // If destination is after source, copy backwards to not overwrite source chars before they are copied
+        let backwards = dst_y * cols + dst_x > src_y * cols + src_x;
+        let num_chars = width * height;
+        for i in 0..num_chars {
+            let i = if backwards { num_chars - 1 - i } else { i };
+            let (dx, dy) = (i % width, i / width);
+            let (ch, text_color, bg_color) = self.read(src_x + dx, src_y + dy)?;
+            self.write(dst_x + dx, dst_y + dy, text_color, bg_color, ch)?;
+        }
+        Ok(())
+    }
+    /// Fill a region of `width` x `height` chars at X,Y position with a char and color.
+    fn fill_region(&self, x: usize, y: usize, width: usize, height: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        for dy in 0..height {
+            for dx in 0..width {
+                self.write(x + dx, y + dy, text_color, bg_color, ch)?;
+            }
+        }
+        Ok(())
+    }
+    /// Print a span of chars with color, starting at X,Y position. The span must fit in the line.
+    fn write_span(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, chars: &[char]) -> Result<(), KError> {
+        let (cols, _) = self.size()?;
+        if x + chars.len() > cols {
+            return Err(KError::OutBounds);
+        }
+        for (i, ch) in chars.iter().enumerate() {
+            self.write(x + i, y, text_color, bg_color, *ch)?;
+        }
+        Ok(())
+    }
+    /// Scroll lines from `top` to `bottom` (both included) one line up, and fill the bottom line with empty chars of the given colors.
+    fn scroll_up(&self, top: usize, bottom: usize, text_color: AnsiColor, bg_color: AnsiColor) -> Result<(), KError> {
+        let (cols, _) = self.size()?;
+        if top > bottom {
+            return Err(KError::OutBounds);
+        }
+        self.copy_region(0, top + 1, 0, top, cols, bottom - top)?;
+        self.fill_region(0, bottom, cols, 1, text_color, bg_color, 0u8.into_char()?)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,199 @@
+//! PC Screen Font (PSF) bitmap fonts.
+//!
+//! Supports PSF1 and PSF2 fonts, with or without unicode table.
+
+use alloc::vec::Vec;
+
+use crate::sys::KError;
+
+/// Built-in 8x16 font. Latin, Greek and Cyrillic glyphs come from the public domain X11 "fixed" 8x13 font, box drawing and block elements are drawn to fill the whole cell.
+pub const DEFAULT_FONT: &[u8] = include_bytes!("fonts/default8x16.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_STARTSEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_STARTSEQ: u8 = 0xFE;
+
+/// Bitmap font.
+pub struct PsfFont {
+    data: &'static [u8],
+    glyphs_offset: usize,
+    num_glyphs: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+    /// Unicode table: code points and glyph index, sorted by code point.
+    unicode: Vec<(char, usize)>,
+    /// Glyph used for chars not in the font.
+    missing: usize
+}
+
+impl PsfFont {
+    /// Parse a PSF font.
+    pub fn parse(data: &'static [u8]) -> Result<Self, KError> {
+        let mut font = if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)?
+        }
+        else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)?
+        }
+        else {
+            return Err(KError::Other);
+        };
+        font.unicode.sort_unstable_by_key(|(ch, _)| *ch);
+        font.unicode.dedup_by_key(|(ch, _)| *ch);
+        font.missing = font.lookup(char::REPLACEMENT_CHARACTER)
+            .or_else(|| font.lookup('?'))
+            .unwrap_or(0);
+        Ok(font)
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Self, KError> {
+        if data.len() < 4 {
+            return Err(KError::Other);
+        }
+        let mode = data[2];
+        let height = data[3] as usize;
+        if height == 0 {
+            return Err(KError::Other);
+        }
+        let num_glyphs = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+        let mut font = Self {
+            data,
+            glyphs_offset: 4,
+            num_glyphs,
+            glyph_size: height,
+            width: 8,
+            height,
+            unicode: Vec::new(),
+            missing: 0
+        };
+        let table_offset = font.glyphs_end()?;
+        if mode & PSF1_MODEHASTAB != 0 {
+            let mut glyph = 0;
+            let mut in_seq = false;
+            for entry in data[table_offset..].chunks_exact(2) {
+                if glyph >= num_glyphs {
+                    break;
+                }
+                match u16::from_le_bytes([entry[0], entry[1]]) {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_seq = false;
+                    },
+                    PSF1_STARTSEQ => in_seq = true,
+                    code => {
+                        // Sequences of combining chars are not supported, skip them
+                        if !in_seq {
+                            if let Some(ch) = char::from_u32(code as u32) {
+                                font.unicode.push((ch, glyph));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(font)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Self, KError> {
+        if data.len() < 32 {
+            return Err(KError::Other);
+        }
+        let field = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]) as usize;
+        let mut font = Self {
+            data,
+            glyphs_offset: field(2),
+            num_glyphs: field(4),
+            glyph_size: field(5),
+            width: field(7),
+            height: field(6),
+            unicode: Vec::new(),
+            missing: 0
+        };
+        let min_glyph_size = font.height.checked_mul(font.width.div_ceil(8)).ok_or(KError::Other)?;
+        if font.num_glyphs == 0 || font.width == 0 || font.height == 0 || font.glyph_size < min_glyph_size {
+            return Err(KError::Other);
+        }
+        let table_offset = font.glyphs_end()?;
+        if field(3) as u32 & PSF2_HAS_UNICODE_TABLE != 0 {
+            let entries = data[table_offset..].split(|b| *b == PSF2_SEPARATOR);
+            for (glyph, entry) in entries.take(font.num_glyphs).enumerate() {
+                // Sequences of combining chars are not supported, only take chars before the first sequence
+                let singles = entry.split(|b| *b == PSF2_STARTSEQ).next().unwrap_or_default();
+                if let Ok(s) = core::str::from_utf8(singles) {
+                    for ch in s.chars() {
+                        font.unicode.push((ch, glyph));
+                    }
+                }
+            }
+        }
+        Ok(font)
+    }
+
+    /// Offset where glyphs end, checking that all glyphs are inside the font data.
+    fn glyphs_end(&self) -> Result<usize, KError> {
+        let end = self.num_glyphs.checked_mul(self.glyph_size)
+            .and_then(|size| size.checked_add(self.glyphs_offset))
+            .ok_or(KError::OutBounds)?;
+        if end > self.data.len() {
+            Err(KError::OutBounds)
+        }
+        else {
+            Ok(end)
+        }
+    }
+
+    fn lookup(&self, ch: char) -> Option<usize> {
+        if self.unicode.is_empty() {
+            // No unicode table, assume glyphs are ordered by code point
+            let code = ch as usize;
+            if code < self.num_glyphs { Some(code) } else { None }
+        }
+        else {
+            self.unicode.binary_search_by_key(&ch, |(c, _)| *c)
+                .ok()
+                .map(|i| self.unicode[i].1)
+        }
+    }
+
+    /// Glyph size in pixels, width and height.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Font contains a glyph for `ch`.
+    pub fn has_glyph(&self, ch: char) -> bool {
+        self.lookup(ch).is_some()
+    }
+
+    /// Get glyph bitmap for a char. Returns the replacement glyph if the font doesn't contain the char.
+    pub fn glyph(&self, ch: char) -> Glyph<'_> {
+        let index = self.lookup(ch).unwrap_or(self.missing);
+        let offset = self.glyphs_offset + index * self.glyph_size;
+        Glyph {
+            bitmap: &self.data[offset..(offset + self.glyph_size)],
+            bytes_per_row: self.width.div_ceil(8)
+        }
+    }
+}
+
+/// Glyph bitmap.
+pub struct Glyph<'a> {
+    bitmap: &'a [u8],
+    bytes_per_row: usize
+}
+
+impl<'a> Glyph<'a> {
+    /// Pixel at X,Y is set.
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        let byte = self.bitmap[y * self.bytes_per_row + x / 8];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
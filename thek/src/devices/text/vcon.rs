@@ -0,0 +1,495 @@
+//! Virtual consoles.
+//!
+//! Multiplex several text consoles (CON1 to CON4) over a single hardware text device. Each console keeps its own content and cursor in memory,
+//! and only the active one is drawn on the hardware. Lines scrolled out of the top of a console are kept in a scrollback history that can be viewed with [`page_up()`] and [`page_down()`].
+//!
+//! Default key combos, processed by [`handle_scancode()`]:
+//!
+//! - `Alt+F1` to `Alt+F4`: switch to console CON1 to CON4.
+//! - `Shift+PgUp` and `Shift+PgDn`: scroll the history of the active console.
+//!
+//! Key combos are processed in the keyboard interrupt handler, so console buffers are always locked with interrupts disabled.
+
+use core::sync::atomic::{
+    AtomicUsize, AtomicBool, Ordering
+};
+
+use super::{
+    Text, CursorShape, CursorBlink,
+    ansi::AnsiColor
+};
+
+use crate::devices::{
+    Id, Interrupt, Device
+};
+
+use crate::sys::{
+    KMutex, KError
+};
+
+use crate::cpu::without_ints;
+
+/// Number of virtual consoles.
+pub const NUM_VCONS: usize = 4;
+
+/// Maximum number of columns of a virtual console.
+pub const VCON_MAX_COLS: usize = 80;
+
+/// Maximum number of rows of a virtual console.
+pub const VCON_MAX_ROWS: usize = 25;
+
+/// Number of lines kept in the scrollback history of each console.
+pub const SCROLLBACK_LINES: usize = 100;
+
+/// Init virtual consoles using `backend` as the hardware device.
+///
+/// The current content of the hardware is preserved in the first console, that becomes the active one.
+pub fn init_vcons(backend: &'static dyn Text) {
+    without_ints(|| *BACKEND.acquire() = Some(backend));
+    let (cols, rows) = VCONS[0].size().unwrap_or((0, 0));
+    with_buffer(0, |buffer| {
+        for y in 0..rows {
+            for x in 0..cols {
+                if let Ok((ch, text_color, bg_color)) = backend.read(x, y) {
+                    buffer.screen[y][x] = Cell { ch, text_color, bg_color };
+                }
+            }
+        }
+        buffer.cursor = backend.get_position().unwrap_or((0, 0));
+    });
+    ACTIVE.store(0, Ordering::SeqCst);
+}
+
+/// Get the device mutex of console at `index`, to register it.
+pub fn vcon_device(index: usize) -> Option<&'static KMutex<&'static dyn Text>> {
+    VCON_MUTEXES.get(index)
+}
+
+/// Index of the active console.
+pub fn active_vcon() -> usize {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Switch the active console and redraw it.
+pub fn switch_vcon(index: usize) -> Result<(), KError> {
+    if index >= NUM_VCONS {
+        return Err(KError::OutBounds);
+    }
+    ACTIVE.store(index, Ordering::SeqCst);
+    with_buffer(index, |buffer| {
+        buffer.view_offset = 0;
+        VCONS[index].redraw(buffer)
+    })
+}
+
+/// Scroll back one page in the history of the active console.
+pub fn page_up() -> Result<(), KError> {
+    let index = active_vcon();
+    let (_, rows) = VCONS[index].size()?;
+    with_buffer(index, |buffer| {
+        buffer.view_offset = (buffer.view_offset + rows).min(buffer.history_len);
+        VCONS[index].redraw(buffer)
+    })
+}
+
+/// Scroll forward one page in the history of the active console.
+pub fn page_down() -> Result<(), KError> {
+    let index = active_vcon();
+    let (_, rows) = VCONS[index].size()?;
+    with_buffer(index, |buffer| {
+        buffer.view_offset = buffer.view_offset.saturating_sub(rows);
+        VCONS[index].redraw(buffer)
+    })
+}
+
+/// Number of history lines the console at `index` is scrolled back, 0 if it shows the current screen.
+pub fn scroll_offset(index: usize) -> usize {
+    if index < NUM_VCONS {
+        with_buffer(index, |buffer| buffer.view_offset)
+    }
+    else {
+        0
+    }
+}
+
+/// Process a raw PC keyboard scancode (set 1) looking for console key combos.
+///
+/// Keyboard drivers should pass all scancodes here. Returns true if the scancode triggered a combo and must not be processed as a regular key.
+pub fn handle_scancode(code: u8) -> bool {
+    let extended = EXTENDED_KEY.swap(false, Ordering::SeqCst);
+    match code {
+        0xE0 => {
+            EXTENDED_KEY.store(true, Ordering::SeqCst);
+            false
+        },
+        // Alt press and release
+        0x38 => { ALT_KEY.store(true, Ordering::SeqCst); false },
+        0xB8 => { ALT_KEY.store(false, Ordering::SeqCst); false },
+        // Left and right shift press and release
+        0x2A | 0x36 => { SHIFT_KEY.store(true, Ordering::SeqCst); false },
+        0xAA | 0xB6 => { SHIFT_KEY.store(false, Ordering::SeqCst); false },
+        // F1 to F4
+        0x3B..=0x3E if ALT_KEY.load(Ordering::SeqCst) => {
+            switch_vcon((code - 0x3B) as usize).is_ok()
+        },
+        // Page up
+        0x49 if extended && SHIFT_KEY.load(Ordering::SeqCst) => {
+            page_up().is_ok()
+        },
+        // Page down
+        0x51 if extended && SHIFT_KEY.load(Ordering::SeqCst) => {
+            page_down().is_ok()
+        },
+        _ => false
+    }
+}
+
+/// Reset all locks and make CON1 active.
+///
+/// `WARNING`: Don't call it unless you know very well what you are doing! Only intended to be used by the panic handler.
+pub fn reset() {
+    BACKEND.reset();
+    for buffer in BUFFERS.iter() {
+        buffer.reset();
+    }
+    switch_vcon(0).unwrap_or_default();
+}
+
+/// A screen cell.
+#[derive(Copy, Clone)]
+struct Cell {
+    ch: char,
+    text_color: AnsiColor,
+    bg_color: AnsiColor
+}
+
+impl Cell {
+    const fn empty() -> Self {
+        Self {
+            ch: '\0',
+            text_color: AnsiColor::Black,
+            bg_color: AnsiColor::Black
+        }
+    }
+}
+
+type Line = [Cell; VCON_MAX_COLS];
+
+/// Content of a virtual console.
+struct VconBuffer {
+    screen: [Line; VCON_MAX_ROWS],
+    /// Ring buffer of lines scrolled out of the screen.
+    history: [Line; SCROLLBACK_LINES],
+    /// Index where the next history line will be stored.
+    history_next: usize,
+    /// Number of valid lines in history.
+    history_len: usize,
+    cursor: (usize, usize),
+    /// Number of history lines the view is scrolled back. Zero is the current screen.
+    view_offset: usize
+}
+
+impl VconBuffer {
+    const fn new() -> Self {
+        Self {
+            screen: [[Cell::empty(); VCON_MAX_COLS]; VCON_MAX_ROWS],
+            history: [[Cell::empty(); VCON_MAX_COLS]; SCROLLBACK_LINES],
+            history_next: 0,
+            history_len: 0,
+            cursor: (0, 0),
+            view_offset: 0
+        }
+    }
+
+    fn push_history(&mut self, line: Line) {
+        self.history[self.history_next] = line;
+        self.history_next = (self.history_next + 1) % SCROLLBACK_LINES;
+        if self.history_len < SCROLLBACK_LINES {
+            self.history_len += 1;
+        }
+    }
+
+    fn copy_region(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) {
+        let mut copy_line = |dy: usize| {
+            let src_line = self.screen[src_y + dy];
+            self.screen[dst_y + dy][dst_x..(dst_x + width)].copy_from_slice(&src_line[src_x..(src_x + width)]);
+        };
+        if dst_y > src_y {
+            (0..height).rev().for_each(&mut copy_line);
+        }
+        else {
+            (0..height).for_each(&mut copy_line);
+        }
+    }
+
+    fn fill_region(&mut self, x: usize, y: usize, width: usize, height: usize, cell: Cell) {
+        for line in self.screen[y..(y + height)].iter_mut() {
+            line[x..(x + width)].fill(cell);
+        }
+    }
+
+    /// Get a line of the current view, history lines on top of screen lines.
+    fn view_line(&self, row: usize) -> &Line {
+        let line = self.history_len - self.view_offset + row;
+        if line < self.history_len {
+            let first = (self.history_next + SCROLLBACK_LINES - self.history_len) % SCROLLBACK_LINES;
+            &self.history[(first + line) % SCROLLBACK_LINES]
+        }
+        else {
+            &self.screen[line - self.history_len]
+        }
+    }
+}
+
+/// Virtual console device.
+pub struct VirtualConsole {
+    index: usize
+}
+
+impl VirtualConsole {
+    const fn new(index: usize) -> Self {
+        Self {
+            index
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        active_vcon() == self.index
+    }
+
+    fn backend() -> Result<&'static dyn Text, KError> {
+        without_ints(|| *BACKEND.acquire()).ok_or(KError::Other)
+    }
+
+    fn check_bounds(&self, x: usize, y: usize) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if x < cols && y < rows {
+            Ok(())
+        }
+        else {
+            Err(KError::OutBounds)
+        }
+    }
+
+    /// Prepare to write on the hardware. Returns the backend only if this console is active, returning to the current screen if we were viewing the history.
+    fn output(&self, buffer: &mut VconBuffer) -> Result<Option<&'static dyn Text>, KError> {
+        if !self.is_active() {
+            return Ok(None);
+        }
+        if buffer.view_offset > 0 {
+            buffer.view_offset = 0;
+            self.redraw(buffer)?;
+        }
+        Ok(Some(Self::backend()?))
+    }
+
+    /// Draw the current view on the hardware.
+    fn redraw(&self, buffer: &VconBuffer) -> Result<(), KError> {
+        let backend = Self::backend()?;
+        let (cols, rows) = self.size()?;
+        let mut chars = ['\0'; VCON_MAX_COLS];
+        for y in 0..rows {
+            let line = buffer.view_line(y);
+            // Write runs of chars with the same color as spans
+            let mut start = 0;
+            while start < cols {
+                let (text_color, bg_color) = (line[start].text_color, line[start].bg_color);
+                let mut end = start;
+                while end < cols && line[end].text_color == text_color && line[end].bg_color == bg_color {
+                    chars[end] = line[end].ch;
+                    end += 1;
+                }
+                backend.write_span(start, y, text_color, bg_color, &chars[start..end])?;
+                start = end;
+            }
+        }
+        if buffer.view_offset == 0 {
+            backend.config_cursor(true, CursorShape::Default, CursorBlink::Default)?;
+            backend.set_position(buffer.cursor.0, buffer.cursor.1)
+        }
+        else {
+            backend.config_cursor(false, CursorShape::Default, CursorBlink::Default)
+        }
+    }
+}
+
+impl Text for VirtualConsole {
+    fn write(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        self.check_bounds(x, y)?;
+        with_buffer(self.index, |buffer| {
+            buffer.screen[y][x] = Cell { ch, text_color, bg_color };
+            if let Some(backend) = self.output(buffer)? {
+                backend.write(x, y, text_color, bg_color, ch)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn put_char(&self, x: usize, y: usize, ch: char) -> Result<(), KError> {
+        self.check_bounds(x, y)?;
+        with_buffer(self.index, |buffer| {
+            buffer.screen[y][x].ch = ch;
+            if let Some(backend) = self.output(buffer)? {
+                backend.put_char(x, y, ch)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn put_color(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor) -> Result<(), KError> {
+        self.check_bounds(x, y)?;
+        with_buffer(self.index, |buffer| {
+            buffer.screen[y][x].text_color = text_color;
+            buffer.screen[y][x].bg_color = bg_color;
+            if let Some(backend) = self.output(buffer)? {
+                backend.put_color(x, y, text_color, bg_color)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn read(&self, x: usize, y: usize) -> Result<(char, AnsiColor, AnsiColor), KError> {
+        self.check_bounds(x, y)?;
+        let cell = with_buffer(self.index, |buffer| buffer.screen[y][x]);
+        Ok((cell.ch, cell.text_color, cell.bg_color))
+    }
+
+    fn set_position(&self, x: usize, y: usize) -> Result<(), KError> {
+        self.check_bounds(x, y)?;
+        with_buffer(self.index, |buffer| {
+            buffer.cursor = (x, y);
+            if let Some(backend) = self.output(buffer)? {
+                backend.set_position(x, y)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_position(&self) -> Result<(usize, usize), KError> {
+        Ok(with_buffer(self.index, |buffer| buffer.cursor))
+    }
+
+    fn config_cursor(&self, enabled: bool, shape: CursorShape, blink: CursorBlink) -> Result<(), KError> {
+        if self.is_active() {
+            Self::backend()?.config_cursor(enabled, shape, blink)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    fn size(&self) -> Result<(usize, usize), KError> {
+        let (cols, rows) = Self::backend()?.size()?;
+        Ok((cols.min(VCON_MAX_COLS), rows.min(VCON_MAX_ROWS)))
+    }
+
+    fn copy_region(&self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, width: usize, height: usize) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if src_x + width > cols || dst_x + width > cols || src_y + height > rows || dst_y + height > rows {
+            return Err(KError::OutBounds);
+        }
+        with_buffer(self.index, |buffer| {
+            buffer.copy_region(src_x, src_y, dst_x, dst_y, width, height);
+            if let Some(backend) = self.output(buffer)? {
+                backend.copy_region(src_x, src_y, dst_x, dst_y, width, height)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn fill_region(&self, x: usize, y: usize, width: usize, height: usize, text_color: AnsiColor, bg_color: AnsiColor, ch: char) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if x + width > cols || y + height > rows {
+            return Err(KError::OutBounds);
+        }
+        with_buffer(self.index, |buffer| {
+            buffer.fill_region(x, y, width, height, Cell { ch, text_color, bg_color });
+            if let Some(backend) = self.output(buffer)? {
+                backend.fill_region(x, y, width, height, text_color, bg_color, ch)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_span(&self, x: usize, y: usize, text_color: AnsiColor, bg_color: AnsiColor, chars: &[char]) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if x + chars.len() > cols || y >= rows {
+            return Err(KError::OutBounds);
+        }
+        with_buffer(self.index, |buffer| {
+            for (cell, ch) in buffer.screen[y][x..].iter_mut().zip(chars.iter()) {
+                *cell = Cell { ch: *ch, text_color, bg_color };
+            }
+            if let Some(backend) = self.output(buffer)? {
+                backend.write_span(x, y, text_color, bg_color, chars)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn scroll_up(&self, top: usize, bottom: usize, text_color: AnsiColor, bg_color: AnsiColor) -> Result<(), KError> {
+        let (cols, rows) = self.size()?;
+        if top > bottom || bottom >= rows {
+            return Err(KError::OutBounds);
+        }
+        with_buffer(self.index, |buffer| {
+            // Lines scrolled out of the top of the screen go to history
+            if top == 0 {
+                let line = buffer.screen[0];
+                buffer.push_history(line);
+            }
+            buffer.copy_region(0, top + 1, 0, top, cols, bottom - top);
+            buffer.fill_region(0, bottom, cols, 1, Cell { ch: '\0', text_color, bg_color });
+            if let Some(backend) = self.output(buffer)? {
+                backend.scroll_up(top, bottom, text_color, bg_color)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Id for VirtualConsole {
+    fn id(&self) -> &str {
+        VCON_IDS[self.index]
+    }
+}
+
+impl Interrupt for VirtualConsole {
+    fn handler(&self, _: fn(Device)) -> bool { false }
+}
+
+const VCON_IDS: [&str; NUM_VCONS] = ["CON1", "CON2", "CON3", "CON4"];
+
+static VCONS: [VirtualConsole; NUM_VCONS] = [
+    VirtualConsole::new(0),
+    VirtualConsole::new(1),
+    VirtualConsole::new(2),
+    VirtualConsole::new(3)
+];
+
+static VCON_MUTEXES: [KMutex<&'static dyn Text>; NUM_VCONS] = [
+    KMutex::new(&VCONS[0]),
+    KMutex::new(&VCONS[1]),
+    KMutex::new(&VCONS[2]),
+    KMutex::new(&VCONS[3])
+];
+
+/// Lock the buffer of console `index`, with interrupts disabled.
+fn with_buffer<T>(index: usize, func: impl FnOnce(&mut VconBuffer) -> T) -> T {
+    without_ints(|| func(&mut BUFFERS[index].acquire()))
+}
+
+static BUFFERS: [KMutex<VconBuffer>; NUM_VCONS] = [
+    KMutex::new(VconBuffer::new()),
+    KMutex::new(VconBuffer::new()),
+    KMutex::new(VconBuffer::new()),
+    KMutex::new(VconBuffer::new())
+];
+
+static BACKEND: KMutex<Option<&'static dyn Text>> = KMutex::new(None);
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+// Key combo state
+static ALT_KEY: AtomicBool = AtomicBool::new(false);
+static SHIFT_KEY: AtomicBool = AtomicBool::new(false);
+static EXTENDED_KEY: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,281 @@
+//! PC PS/2 mouse device.
+//!
+//! Supports the standard 3 button mouse and the IntelliMouse extension with scroll wheel. Packets are received and decoded on IRQ 12.
+
+use crate::devices::{
+    register_device,
+    tracker::{
+        Tracker, TrackerEvent, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_MIDDLE
+    },
+    Id, Interrupt, Device
+};
+
+use macros::device;
+
+use crate::cpu::{
+    without_ints,
+    arch::{
+        inb, outb, set_irq_handler
+    }
+};
+
+use crate::sys::KMutex;
+
+#[device(crate::devices::tracker::arch)]
+pub fn register_devices() {
+    if let Some(has_wheel) = PcMouseDevice::init() {
+        MOUSE_STATE.acquire().packet_len = if has_wheel { 4 } else { 3 };
+        // Interrupts are enabled in the controller only once the handler is installed
+        if set_irq_handler(MOUSE_IRQ, PcMouseDevice::isr) && PcMouseDevice::enable_int().is_some() {
+            register_device(Device::Tracker(&PC_MOUSE_DEVICE_1_MUTEX));
+        }
+    }
+}
+
+static PC_MOUSE_DEVICE_1 : PcMouseDevice = PcMouseDevice::new();
+static PC_MOUSE_DEVICE_1_MUTEX : KMutex<&'static dyn Tracker> = KMutex::new(&PC_MOUSE_DEVICE_1);
+
+const MOUSE_IRQ : u8 = 12;
+
+const PS2_DATA : u16 = 0x60;
+const PS2_STATUS : u16 = 0x64;
+const PS2_COMMAND : u16 = 0x64;
+
+const PS2_STATUS_OUTPUT_FULL : u8 = 0x01;
+const PS2_STATUS_INPUT_FULL : u8 = 0x02;
+
+const PS2_CMD_READ_CONFIG : u8 = 0x20;
+const PS2_CMD_WRITE_CONFIG : u8 = 0x60;
+const PS2_CMD_ENABLE_AUX : u8 = 0xA8;
+const PS2_CMD_WRITE_AUX : u8 = 0xD4;
+
+const PS2_CONFIG_AUX_INT : u8 = 0x02;
+const PS2_CONFIG_AUX_CLOCK_DISABLED : u8 = 0x20;
+
+const MOUSE_CMD_SET_SAMPLE_RATE : u8 = 0xF3;
+const MOUSE_CMD_GET_ID : u8 = 0xF2;
+const MOUSE_CMD_ENABLE_REPORTING : u8 = 0xF4;
+const MOUSE_CMD_SET_DEFAULTS : u8 = 0xF6;
+const MOUSE_ACK : u8 = 0xFA;
+/// Device ID reported by an IntelliMouse with scroll wheel.
+const MOUSE_ID_WHEEL : u8 = 3;
+
+/// Bit always set in the first byte of a packet, used to keep packets in sync.
+const PACKET_ALWAYS_ONE : u8 = 0x08;
+const PACKET_X_SIGN : u8 = 0x10;
+const PACKET_Y_SIGN : u8 = 0x20;
+const PACKET_OVERFLOW : u8 = 0xC0;
+
+/// Polls before giving up waiting for the controller.
+const PS2_TIMEOUT : usize = 100_000;
+
+const QUEUE_SIZE : usize = 64;
+
+/// Packet assembly and event queue, shared with the interrupt handler.
+struct MouseState {
+    packet: [u8; 4],
+    packet_pos: usize,
+    packet_len: usize,
+    buttons: u8,
+    queue: [TrackerEvent; QUEUE_SIZE],
+    head: usize,
+    count: usize,
+    handler: Option<fn(Device)>
+}
+
+impl MouseState {
+    fn push(&mut self, event: TrackerEvent) {
+        if self.count == QUEUE_SIZE {
+            // Queue full, drop the oldest event
+            self.head = (self.head + 1) % QUEUE_SIZE;
+            self.count -= 1;
+        }
+        self.queue[(self.head + self.count) % QUEUE_SIZE] = event;
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<TrackerEvent> {
+        if self.count == 0 {
+            None
+        }
+        else {
+            let event = self.queue[self.head];
+            self.head = (self.head + 1) % QUEUE_SIZE;
+            self.count -= 1;
+            Some(event)
+        }
+    }
+
+    /// Process a byte received from the mouse.
+    /// * Return: event, if a packet was completed.
+    fn receive(&mut self, byte: u8) -> Option<TrackerEvent> {
+        if self.packet_pos == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            // Not the first byte of a packet, we lost sync
+            return None;
+        }
+        self.packet[self.packet_pos] = byte;
+        self.packet_pos += 1;
+        if self.packet_pos < self.packet_len {
+            return None;
+        }
+        self.packet_pos = 0;
+        let flags = self.packet[0];
+        self.buttons = flags & (BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE);
+        let (mut dx, mut dy) = (self.packet[1] as i32, self.packet[2] as i32);
+        if flags & PACKET_X_SIGN != 0 {
+            dx -= 0x100;
+        }
+        if flags & PACKET_Y_SIGN != 0 {
+            dy -= 0x100;
+        }
+        if flags & PACKET_OVERFLOW != 0 {
+            // Motion is not reliable
+            dx = 0;
+            dy = 0;
+        }
+        let wheel = if self.packet_len == 4 { self.packet[3] as i8 as i32 } else { 0 };
+        Some(TrackerEvent {
+            dx,
+            // PS/2 Y axis goes upwards
+            dy: -dy,
+            wheel,
+            buttons: self.buttons
+        })
+    }
+}
+
+static MOUSE_STATE : KMutex<MouseState> = KMutex::new(MouseState {
+    packet: [0; 4],
+    packet_pos: 0,
+    packet_len: 3,
+    buttons: 0,
+    queue: [TrackerEvent { dx: 0, dy: 0, wheel: 0, buttons: 0 }; QUEUE_SIZE],
+    head: 0,
+    count: 0,
+    handler: None
+});
+
+/// PC PS/2 mouse device.
+pub struct PcMouseDevice {
+}
+
+impl PcMouseDevice {
+    const fn new() -> Self {
+        Self {}
+    }
+
+    /// Run `func` with the mouse state locked. The state is also locked by the interrupt handler, so interrupts are disabled meanwhile.
+    fn with_state<T>(func: impl FnOnce(&mut MouseState) -> T) -> T {
+        without_ints(|| func(&mut MOUSE_STATE.acquire()))
+    }
+
+    fn wait_write() -> bool {
+        (0..PS2_TIMEOUT).any(|_| inb(PS2_STATUS) & PS2_STATUS_INPUT_FULL == 0)
+    }
+
+    fn wait_read() -> bool {
+        (0..PS2_TIMEOUT).any(|_| inb(PS2_STATUS) & PS2_STATUS_OUTPUT_FULL != 0)
+    }
+
+    fn controller_command(cmd: u8) -> Option<()> {
+        Self::wait_write().then(|| outb(PS2_COMMAND, cmd))
+    }
+
+    fn read_data() -> Option<u8> {
+        Self::wait_read().then(|| inb(PS2_DATA))
+    }
+
+    fn write_data(data: u8) -> Option<()> {
+        Self::wait_write().then(|| outb(PS2_DATA, data))
+    }
+
+    /// Send a command (or command argument) to the mouse and wait for the acknowledge.
+    fn mouse_command(cmd: u8) -> Option<()> {
+        Self::controller_command(PS2_CMD_WRITE_AUX)?;
+        Self::write_data(cmd)?;
+        (Self::read_data()? == MOUSE_ACK).then_some(())
+    }
+
+    /// Enable the auxiliary port and the mouse, with interrupts still disabled in the controller.
+    /// * Return: `None` if there is no mouse, or if it has a scroll wheel otherwise.
+    fn init() -> Option<bool> {
+        Self::controller_command(PS2_CMD_ENABLE_AUX)?;
+        Self::update_config(|config| config & !PS2_CONFIG_AUX_CLOCK_DISABLED & !PS2_CONFIG_AUX_INT)?;
+        Self::mouse_command(MOUSE_CMD_SET_DEFAULTS)?;
+        // Magic sample rate sequence that enables the IntelliMouse scroll wheel
+        for rate in [200, 100, 80] {
+            Self::mouse_command(MOUSE_CMD_SET_SAMPLE_RATE)?;
+            Self::mouse_command(rate)?;
+        }
+        Self::mouse_command(MOUSE_CMD_GET_ID)?;
+        let has_wheel = Self::read_data()? == MOUSE_ID_WHEEL;
+        Self::mouse_command(MOUSE_CMD_ENABLE_REPORTING)?;
+        Some(has_wheel)
+    }
+
+    /// Enable the auxiliary port interrupt in the controller.
+    fn enable_int() -> Option<()> {
+        Self::update_config(|config| config | PS2_CONFIG_AUX_INT)
+    }
+
+    /// Read the controller configuration byte, change it with `func` and write it back.
+    fn update_config(func: impl FnOnce(u8) -> u8) -> Option<()> {
+        Self::controller_command(PS2_CMD_READ_CONFIG)?;
+        let config = func(Self::read_data()?);
+        Self::controller_command(PS2_CMD_WRITE_CONFIG)?;
+        Self::write_data(config)
+    }
+
+    /// IRQ 12 handler. Runs with interrupts disabled.
+    fn isr() {
+        let byte = inb(PS2_DATA);
+        let mut state = MOUSE_STATE.acquire();
+        if let Some(event) = state.receive(byte) {
+            state.push(event);
+            let handler = state.handler;
+            drop(state);
+            if let Some(func) = handler {
+                func(Device::Tracker(&PC_MOUSE_DEVICE_1_MUTEX));
+            }
+        }
+    }
+}
+
+impl Tracker for PcMouseDevice {
+    fn is_ready(&self) -> bool {
+        Self::with_state(|state| state.count > 0)
+    }
+
+    fn read(&self) -> TrackerEvent {
+        loop {
+            if let Some(event) = Self::with_state(|state| state.pop()) {
+                return event;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn buttons(&self) -> usize {
+        3
+    }
+
+    fn has_wheel(&self) -> bool {
+        Self::with_state(|state| state.packet_len == 4)
+    }
+}
+
+impl Id for PcMouseDevice {
+    fn id(&self) -> &str {
+        "MOU1"
+    }
+}
+
+impl Interrupt for PcMouseDevice {
+    /// Set a function to be called from the interrupt handler each time an event is queued.
+    ///
+    /// The device lock may be held by the interrupted code, so the handler must not block on it. Use [`Tracker::is_ready`] and [`Tracker::read`] from regular code instead.
+    fn handler(&self, func: fn(Device)) -> bool {
+        Self::with_state(|state| state.handler = Some(func));
+        true
+    }
+}
\ No newline at end of file
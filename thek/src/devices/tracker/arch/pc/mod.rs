@@ -0,0 +1,4 @@
+//! PC tracker devices.
+
+pub mod device;
+pub use self::device::*;
@@ -0,0 +1,39 @@
+//! Tracker devices.
+
+use super::{
+    Id, Interrupt
+};
+
+pub mod arch;
+
+/// Left button bit in [`TrackerEvent::buttons`].
+pub const BUTTON_LEFT: u8 = 0x01;
+/// Right button bit in [`TrackerEvent::buttons`].
+pub const BUTTON_RIGHT: u8 = 0x02;
+/// Middle button bit in [`TrackerEvent::buttons`].
+pub const BUTTON_MIDDLE: u8 = 0x04;
+
+/// Tracker event.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct TrackerEvent {
+    /// Relative horizontal motion, positive to the right.
+    pub dx: i32,
+    /// Relative vertical motion, positive downwards (screen coordinates).
+    pub dy: i32,
+    /// Wheel motion, positive downwards. Always 0 if the device has no wheel.
+    pub wheel: i32,
+    /// Buttons pressed when the event happened, a combination of `BUTTON_*` bits.
+    pub buttons: u8
+}
+
+/// Tracker device interface.
+pub trait Tracker : Id + Interrupt {
+    /// There is an event ready to be read.
+    fn is_ready(&self) -> bool;
+    /// Read next event. Blocks if no event ready.
+    fn read(&self) -> TrackerEvent;
+    /// Number of buttons.
+    fn buttons(&self) -> usize;
+    /// Device has a scroll wheel.
+    fn has_wheel(&self) -> bool;
+}
\ No newline at end of file
@@ -11,16 +11,16 @@
 //! We have a fixed number of buckets ordered from smaller segment size to bigger.<br>
 //! At start, we create a struct for each bucket, that contains:
 //! 
-//! - Stack. We put in the stack the starting address of each segment.
+//! - Free list. A lock-free list of free segments, with the links stored apart from the segments.
 //! - Segment size (in byte).
 //! - Bucket size (total number of segments).
-//! - Counter with the current number of free segments.
+//! - Counter with the current number of used segments.
 //! 
 //! Once an alloc happens, we check the size requested and we select the bucket with the closest segment size.
-//! We pop an address from the stack and decrease the counter.
-//! If no segments available in the bucklet, we try with the next bucket size, and so on.
+//! We pop a segment from the free list and increase the counter.
+//! If no segments available in the bucklet, we try with the next bucket size, and so on (see `mem::SpillPolicy`).
 //! 
-//! When a free happens, we just push the segment address into the bucket (each segment has a header with a pointer to the bucket struct it belongs to), and increase the counter.
+//! When a free happens, we just push the segment into the free list of the bucket that contains its address, and decrease the counter.
 //! 
 //! Advantages:
 //! 
@@ -44,7 +44,8 @@
 //! 
 //! # Choosing The Right Memory Schema
 //! 
-//! TODO
+//! The schema is selected at build time (see `mem::SCHEMA`). To find one that fits an application, build it with the `alloc-profile` feature, run a representative workload and call `mem::profile::profile_report()`.
+//! It outputs the allocation size histogram and a recommended schema through the serial port, both as a `mem::init::setup_mem` call and in the format of the schema file.
 //! 
 //! # Real Time Applications
 //! 
@@ -88,14 +89,21 @@ pub mod cpu;
 
 pub mod task;
 
+pub mod logger;
+
+pub mod testing;
+
 //#[macro_use]
 extern crate alloc;
+#[cfg(not(feature = "test"))]
 use alloc::borrow::ToOwned;
 
+#[cfg(not(feature = "test"))]
 use controllers::text::{
     TextController
 };
 
+#[cfg(not(feature = "test"))]
 use devices::{
     Device,
     text::{
@@ -103,6 +111,7 @@ use devices::{
     }
 };
 
+#[cfg(not(feature = "test"))]
 use core::{
     panic::PanicInfo,
     fmt::Write
@@ -137,8 +146,12 @@ macro_rules! thek_dbg {
 }
 
 /// Panic handler.
+#[cfg(not(feature = "test"))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    if testing::is_running() {
+        testing::test_panic(info);
+    }
     cpu::disable_ints();
     let dev_id = "CON1";
     if let Some(device) = devices::get_text_device(dev_id) {
@@ -146,6 +159,8 @@ fn panic(info: &PanicInfo) -> ! {
             // Reset mutex, just in case we panicked while still holding a lock.
             txt_dev.reset();
         }
+        devices::text::vcon::reset();
+        controllers::text::reset_shadows();
         let mut con = TextController::new(
             AnsiColor::BrightWhite,
             AnsiColor::Red,
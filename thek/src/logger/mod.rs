@@ -0,0 +1,211 @@
+//! Kernel logging.
+//!
+//! Implements the [`log`] crate facade, so any crate using the `log` macros (`error!`, `warn!`, `info!`, `debug!` and `trace!`) will output through the kernel logger.
+//!
+//! Every record is stamped with the timer tick counter, filtered by level (globally or per module), stored in a ring buffer and then written to all the registered sinks.
+//! A sink is anything implementing [`core::fmt::Write`], usually a controller like [`PortController`](crate::controllers::port::PortController) or [`TextController`](crate::controllers::text::TextController).
+//!
+//! Filters and ring buffer locks are taken with interrupts disabled, so interrupt handlers can log without deadlocking a task that is logging.
+//! Sinks can be slow and are written with interrupts enabled. A log call that finds them busy (an interrupt handler, or a sink that logs) leaves its record in the ring buffer, and it's written by the sinks holder.
+//!
+//! # Example
+//!
+//! ```ignore
+//! logger::init_logger(LevelFilter::Info);
+//! logger::add_sink(Box::new(PortController::default()));
+//! logger::set_module_level("thek::mem", LevelFilter::Trace);
+//! log::info!("Hello from the kernel!");
+//! ```
+
+mod record;
+pub use self::record::*;
+
+pub use log::{
+    Level, LevelFilter
+};
+
+use log::{
+    Log, Metadata, Record
+};
+
+use alloc::{
+    boxed::Box,
+    string::String,
+    vec::Vec,
+    borrow::ToOwned
+};
+
+use core::{
+    fmt::Write,
+    sync::atomic::{
+        AtomicUsize, Ordering
+    }
+};
+
+use crate::{
+    cpu::without_ints,
+    sys::KMutex,
+    task::ticks
+};
+
+/// Kernel logger.
+struct KLogger;
+
+impl Log for KLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        without_ints(|| metadata.level() <= FILTERS.acquire().level_for(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let record = LogRecord::new(ticks(), record.level(), record.target(), record.args());
+        without_ints(|| RECORDS.acquire().push(record));
+        write_sinks();
+    }
+
+    fn flush(&self) {}
+}
+
+/// Level filters.
+struct LogFilters {
+    /// Default level for all modules.
+    level: LevelFilter,
+    /// Per module levels: module path prefix and level.
+    modules: Vec<(String, LevelFilter)>
+}
+
+impl LogFilters {
+    const fn new() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            modules: Vec::new()
+        }
+    }
+
+    /// Find the level for a target, using the longest matching module prefix.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let mut level = self.level;
+        let mut match_len = 0;
+        for (module, module_level) in self.modules.iter() {
+            if module.len() >= match_len && Self::is_module_of(target, module) {
+                level = *module_level;
+                match_len = module.len();
+            }
+        }
+        level
+    }
+
+    /// Target is the module or one of its submodules.
+    fn is_module_of(target: &str, module: &str) -> bool {
+        if let Some(rest) = target.strip_prefix(module) {
+            rest.is_empty() || rest.starts_with("::")
+        }
+        else {
+            false
+        }
+    }
+
+    /// Most verbose level of all filters.
+    fn max_level(&self) -> LevelFilter {
+        let mut max = self.level;
+        for (_, module_level) in self.modules.iter() {
+            if *module_level > max {
+                max = *module_level;
+            }
+        }
+        max
+    }
+}
+
+/// Write the records not written yet to all the sinks.
+///
+/// If the sinks are busy, returns without waiting: the holder writes the records before releasing them.
+fn write_sinks() {
+    loop {
+        if let Some(mut sinks) = SINKS.try_acquire() {
+            while let Some((seq, record)) = next_record() {
+                SINKS_SEQ.store(seq.wrapping_add(1), Ordering::Relaxed);
+                for sink in sinks.iter_mut() {
+                    writeln!(sink, "{}", record).unwrap_or_default();
+                }
+            }
+        }
+        else {
+            return;
+        }
+        // A record pushed after the last check, while the sinks were still locked, was left for us
+        if next_record().is_none() {
+            return;
+        }
+    }
+}
+
+/// Next record to be written to the sinks.
+fn next_record() -> Option<(usize, LogRecord)> {
+    without_ints(|| RECORDS.acquire().next_record(SINKS_SEQ.load(Ordering::Relaxed)))
+}
+
+/// Init the kernel logger with a default level.
+///
+/// Returns false if a logger was already set.
+pub fn init_logger(level: LevelFilter) -> bool {
+    set_level(level);
+    log::set_logger(&LOGGER).is_ok()
+}
+
+/// Add a sink where log records will be written.
+///
+/// Don't call it from interrupt handlers, sinks are locked with interrupts enabled.
+pub fn add_sink(sink: Box<dyn Write>) {
+    SINKS.acquire().push(sink);
+}
+
+/// Remove all sinks.
+///
+/// Don't call it from interrupt handlers, sinks are locked with interrupts enabled.
+pub fn clear_sinks() {
+    SINKS.acquire().clear();
+}
+
+/// Set default log level.
+pub fn set_level(level: LevelFilter) {
+    without_ints(|| {
+        let mut filters = FILTERS.acquire();
+        filters.level = level;
+        log::set_max_level(filters.max_level());
+    });
+}
+
+/// Set log level for a module and its submodules, overriding the default level.
+pub fn set_module_level(module: &str, level: LevelFilter) {
+    without_ints(|| {
+        let mut filters = FILTERS.acquire();
+        if let Some(entry) = filters.modules.iter_mut().find(|(m, _)| m == module) {
+            entry.1 = level;
+        }
+        else {
+            filters.modules.push((module.to_owned(), level));
+        }
+        log::set_max_level(filters.max_level());
+    });
+}
+
+/// Iterate the most recent log records, from oldest to newest.
+pub fn recent_records(func: impl FnMut(&LogRecord)) {
+    without_ints(|| RECORDS.acquire().for_each(func));
+}
+
+/// Remove all records from the ring buffer.
+pub fn clear_records() {
+    without_ints(|| RECORDS.acquire().clear());
+}
+
+static LOGGER: KLogger = KLogger;
+static FILTERS: KMutex<LogFilters> = KMutex::new(LogFilters::new());
+static RECORDS: KMutex<LogRing> = KMutex::new(LogRing::new());
+static SINKS: KMutex<Vec<Box<dyn Write>>> = KMutex::new(Vec::new());
+/// Sequence number of the next record to be written to the sinks. Only changed with the sinks locked.
+static SINKS_SEQ: AtomicUsize = AtomicUsize::new(0);
@@ -0,0 +1,191 @@
+use core::fmt::{
+    self,
+    Write,
+    Display,
+    Formatter
+};
+
+use log::Level;
+
+use crate::task::TIMER_PERIOD_SEC;
+
+/// Maximum length of a log target (module path) stored in a record.
+pub const LOG_TARGET_LEN: usize = 32;
+
+/// Maximum length of a log message stored in a record.
+pub const LOG_MSG_LEN: usize = 128;
+
+/// Number of records kept in the ring buffer.
+pub const LOG_RING_LEN: usize = 64;
+
+/// A log record with fixed size storage, so we don't need to allocate memory to log.
+#[derive(Copy, Clone)]
+pub struct LogRecord {
+    ticks: u32,
+    level: Level,
+    target: [u8; LOG_TARGET_LEN],
+    target_len: u8,
+    msg: [u8; LOG_MSG_LEN],
+    msg_len: u8
+}
+
+impl LogRecord {
+    const fn empty() -> Self {
+        Self {
+            ticks: 0,
+            level: Level::Trace,
+            target: [0; LOG_TARGET_LEN],
+            target_len: 0,
+            msg: [0; LOG_MSG_LEN],
+            msg_len: 0
+        }
+    }
+
+    /// Create a record. Target and message are truncated if they don't fit.
+    pub fn new(ticks: u32, level: Level, target: &str, args: &fmt::Arguments) -> Self {
+        let mut record = Self::empty();
+        record.ticks = ticks;
+        record.level = level;
+
+        let mut target_buf = FixedBuf::new(&mut record.target);
+        target_buf.write_str(target).unwrap_or_default();
+        record.target_len = target_buf.len() as u8;
+
+        let mut msg_buf = FixedBuf::new(&mut record.msg);
+        fmt::write(&mut msg_buf, *args).unwrap_or_default();
+        record.msg_len = msg_buf.len() as u8;
+
+        record
+    }
+
+    /// Timer ticks when the record was created.
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Time in seconds when the record was created.
+    pub fn secs(&self) -> f64 {
+        self.ticks as f64 * TIMER_PERIOD_SEC
+    }
+
+    /// Log level.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Log target, usually the module path.
+    pub fn target(&self) -> &str {
+        // Buffers are only filled with complete UTF-8 chars.
+        unsafe {
+            core::str::from_utf8_unchecked(&self.target[..self.target_len as usize])
+        }
+    }
+
+    /// Log message.
+    pub fn message(&self) -> &str {
+        // Buffers are only filled with complete UTF-8 chars.
+        unsafe {
+            core::str::from_utf8_unchecked(&self.msg[..self.msg_len as usize])
+        }
+    }
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:>10.3}] {:<5} {}: {}", self.secs(), self.level(), self.target(), self.message())
+    }
+}
+
+/// Ring buffer of the most recent log records.
+pub(super) struct LogRing {
+    records: [LogRecord; LOG_RING_LEN],
+    /// Index where the next record will be stored.
+    next: usize,
+    /// Number of valid records.
+    len: usize,
+    /// Number of records pushed since the start, the sequence number of the next record. Not changed by [`clear`](Self::clear).
+    pushed: usize
+}
+
+impl LogRing {
+    pub(super) const fn new() -> Self {
+        Self {
+            records: [LogRecord::empty(); LOG_RING_LEN],
+            next: 0,
+            len: 0,
+            pushed: 0
+        }
+    }
+
+    /// Store a record, overwriting the oldest one if the ring is full.
+    pub(super) fn push(&mut self, record: LogRecord) {
+        self.records[self.next] = record;
+        self.next = (self.next + 1) % LOG_RING_LEN;
+        if self.len < LOG_RING_LEN {
+            self.len += 1;
+        }
+        self.pushed = self.pushed.wrapping_add(1);
+    }
+
+    /// Oldest record in the ring with sequence number `seq` or later, along with its sequence number.
+    pub(super) fn next_record(&self, seq: usize) -> Option<(usize, LogRecord)> {
+        let oldest = self.pushed.wrapping_sub(self.len);
+        // Records already out of the ring are lost, continue from the oldest one
+        let skip = match seq.wrapping_sub(oldest) {
+            distance if distance > self.len => 0,
+            distance => distance
+        };
+        if skip == self.len {
+            return None;
+        }
+        let first = (self.next + LOG_RING_LEN - self.len) % LOG_RING_LEN;
+        Some((oldest.wrapping_add(skip), self.records[(first + skip) % LOG_RING_LEN]))
+    }
+
+    /// Iterate records, from oldest to newest.
+    pub(super) fn for_each(&self, mut func: impl FnMut(&LogRecord)) {
+        let first = (self.next + LOG_RING_LEN - self.len) % LOG_RING_LEN;
+        for i in 0..self.len {
+            func(&self.records[(first + i) % LOG_RING_LEN]);
+        }
+    }
+
+    /// Remove all records.
+    pub(super) fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
+
+/// Writer over a fixed buffer that silently truncates, never splitting a UTF-8 char.
+struct FixedBuf<'a> {
+    buf: &'a mut [u8],
+    len: usize
+}
+
+impl<'a> FixedBuf<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<'a> Write for FixedBuf<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            let ch_len = ch.len_utf8();
+            if self.len + ch_len > self.buf.len() {
+                break;
+            }
+            ch.encode_utf8(&mut self.buf[self.len..]);
+            self.len += ch_len;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Memory infrastructure for host tests.
+//!
+//! Raw memory regions are buffers allocated by the host allocator, so the memory schema can be set up and tested as a regular program.
+
+use core::ptr::null_mut;
+
+use alloc::vec;
+
+use crate::sys::KMutex;
+
+use super::super::layout::{
+    MemRegion, MemRegions
+};
+
+/// Return pointer and size of the first raw memory region, the one that holds the block set. Pointer is null if no raw memory has been added.
+pub unsafe fn raw_mem() -> (*mut u8, usize) {
+    match RAW_MEM.acquire().as_slice().first() {
+        Some(region) => (region.ptr, region.size),
+        None => (null_mut(), 0)
+    }
+}
+
+/// All raw memory regions.
+pub fn raw_mem_regions() -> MemRegions {
+    *RAW_MEM.acquire()
+}
+
+/// Add a raw memory region to be used by the allocator.
+///
+/// Regions must be added before setting up the memory schema.
+/// * Return: could be added or not (too many regions).
+///
+/// # Safety
+///
+/// `ptr` must point to `size` bytes of writable and otherwise unused memory, that will be owned by the allocator forever.
+pub unsafe fn add_raw_mem(ptr: *mut u8, size: usize) -> bool {
+    // Align region start, the size will be adjusted by the block setup
+    let offset = ptr.align_offset(ALIGN);
+    if size <= offset {
+        return false;
+    }
+    RAW_MEM.acquire().push(MemRegion { ptr: ptr.add(offset), size: size - offset })
+}
+
+/// Allocate a host buffer of `size` bytes, aligned to `MAX_SEGMENT_ALIGN`, and add it as a raw memory region.
+///
+/// The buffer is leaked, because the allocator may keep using it after the region list is reset.
+/// * Return: region start address, or `None` if it could not be added (too many regions).
+pub fn add_host_mem(size: usize) -> Option<*mut u8> {
+    let buffer = vec![0u8; size + MAX_SEGMENT_ALIGN].leak();
+    let offset = buffer.as_ptr().align_offset(MAX_SEGMENT_ALIGN);
+    let ptr = buffer[offset..].as_mut_ptr();
+    unsafe {
+        add_raw_mem(ptr, size).then_some(ptr)
+    }
+}
+
+/// Remove all raw memory regions, to set up a new memory schema from scratch.
+pub fn reset_raw_mem() {
+    *RAW_MEM.acquire() = MemRegions::new();
+}
+
+/// Return addresses of the calling functions. Not available on the host, no frames are ever found.
+pub fn backtrace(_frames: &mut [usize]) -> usize {
+    0
+}
+
+/// Memory alignment
+pub const ALIGN : usize = 16;
+
+/// Maximum alignment guaranteed for segments (page size).
+pub const MAX_SEGMENT_ALIGN : usize = 4096;
+
+static RAW_MEM : KMutex<MemRegions> = KMutex::new(MemRegions::new());
\ No newline at end of file
@@ -1,6 +1,11 @@
 //! Architectue dependant memory infrastructure.
 
-#[cfg(feature = "pc64")]
+#[cfg(all(feature = "pc64", not(feature = "test")))]
 mod x86_64;
-#[cfg(feature = "pc64")]
+#[cfg(all(feature = "pc64", not(feature = "test")))]
 pub use self::x86_64::*;
+
+#[cfg(feature = "test")]
+mod host;
+#[cfg(feature = "test")]
+pub use self::host::*;
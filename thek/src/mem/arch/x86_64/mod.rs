@@ -1,13 +1,95 @@
 //! Memory infrastructure for x86_64.
 
-/// Return pointer, size and alignment.
+use core::ptr::null_mut;
+
+use crate::sys::KMutex;
+
+use super::super::{
+    layout::{
+        MemRegion, MemRegions
+    },
+    paging::{
+        self, PAGE_SIZE
+    }
+};
+
+/// Return pointer and size of the first raw memory region, the one that holds the block set. Pointer is null if no raw memory has been added.
 pub unsafe fn raw_mem() -> (*mut u8, usize) {
-    (RAW_MEMORY.as_mut_ptr(), MEM_SIZE)
+    match RAW_MEM.acquire().as_slice().first() {
+        Some(region) => (region.ptr, region.size),
+        None => (null_mut(), 0)
+    }
+}
+
+/// All raw memory regions.
+pub fn raw_mem_regions() -> MemRegions {
+    *RAW_MEM.acquire()
+}
+
+/// Add a raw memory region to be used by the allocator, usually a usable region in the memory map provided by the bootloader.
+///
+/// Regions must be added before setting up the memory schema.
+/// * Return: could be added or not (too many regions).
+///
+/// # Safety
+///
+/// `ptr` must point to `size` bytes of mapped, writable and otherwise unused memory, that will be owned by the allocator forever.
+pub unsafe fn add_raw_mem(ptr: *mut u8, size: usize) -> bool {
+    // Align region start, the size will be adjusted by the block setup
+    let offset = ptr.align_offset(ALIGN);
+    if size <= offset {
+        return false;
+    }
+    RAW_MEM.acquire().push(MemRegion { ptr: ptr.add(offset), size: size - offset })
+}
+
+/// Fill `frames` with the return addresses of the calling functions, from the innermost, walking the frame pointers.
+///
+/// The kernel must be built with frame pointers (`-C force-frame-pointers=yes`), otherwise the addresses are garbage.
+/// The walk never leaves the current stack, the mapped pages right above the stack pointer, so a broken frame pointer chain can't fault.
+/// * Return: number of frames found.
+#[inline(never)]
+pub fn backtrace(frames: &mut [usize]) -> usize {
+    let mut frame_ptr: usize;
+    let stack_ptr: usize;
+    unsafe {
+        asm!("mov {}, rbp", "mov {}, rsp", out(reg) frame_ptr, out(reg) stack_ptr);
+    }
+    // End of the stack pages known to be mapped, the one of the stack pointer is
+    let mut mapped_end = stack_ptr - stack_ptr % PAGE_SIZE + PAGE_SIZE;
+    let mut count = 0;
+    // Stop at the outermost frame, or if the chain leaves the stack or doesn't look like a stack growing down
+    while count < frames.len() && frame_ptr % 8 == 0 && frame_ptr >= stack_ptr && frame_ptr - stack_ptr < MAX_BACKTRACE_STACK {
+        // A frame is the saved frame pointer followed by the return address
+        while mapped_end < frame_ptr + 16 {
+            if paging::translate(mapped_end).is_none() {
+                return count;
+            }
+            mapped_end += PAGE_SIZE;
+        }
+        let (next_frame_ptr, return_addr) = unsafe {
+            (*(frame_ptr as *const usize), *((frame_ptr + 8) as *const usize))
+        };
+        if return_addr == 0 {
+            break;
+        }
+        frames[count] = return_addr;
+        count += 1;
+        if next_frame_ptr <= frame_ptr {
+            break;
+        }
+        frame_ptr = next_frame_ptr;
+    }
+    count
 }
 
 /// Memory alignment
-pub const ALIGN : usize = 4;
+pub const ALIGN : usize = 16;
+
+/// Maximum alignment guaranteed for segments (page size).
+pub const MAX_SEGMENT_ALIGN : usize = 4096;
+
+/// Maximum distance from the stack pointer to the outermost frame of a backtrace.
+const MAX_BACKTRACE_STACK : usize = 1024 * 1024;
 
-/// Simulated raw memory (until we access actual raw mem)
-const MEM_SIZE : usize = 10*1024*1024;
-static mut RAW_MEMORY : [u8; MEM_SIZE] = [0; MEM_SIZE];
\ No newline at end of file
+static RAW_MEM : KMutex<MemRegions> = KMutex::new(MemRegions::new());
\ No newline at end of file
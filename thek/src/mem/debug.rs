@@ -0,0 +1,122 @@
+//! Heap debugging, enabled with the `heap-debug` feature.
+//!
+//! - Free segments are filled with [`FREE_PATTERN`]. The pattern is checked when the segment is allocated again, so writes after free are detected.
+//! - Every allocation is followed by a red zone of [`RED_ZONE_SIZE`] bytes filled with [`RED_ZONE_PATTERN`]. It's checked when the buffer is freed or reallocated, so overflows are detected.
+//! - Each segment is marked as allocated or free (see [`MemBlockLayout::is_allocated`]), so double frees and frees of pointers that are not segments are detected.
+//!
+//! Any error panics with the address of the segment, the layout and the block. All checks are skipped when the feature is disabled.
+//! Filling and checking the patterns touches the whole segment, so allocations are much slower in big segments.
+
+use core::{
+    ptr,
+    slice
+};
+
+use super::layout::MemBlockLayout;
+
+/// Heap debugging enabled.
+pub const HEAP_DEBUG: bool = cfg!(feature = "heap-debug");
+
+/// Byte used to fill free segments.
+pub const FREE_PATTERN: u8 = 0xDD;
+
+/// Byte used to fill the red zones.
+pub const RED_ZONE_PATTERN: u8 = 0xFD;
+
+/// Size of the red zone placed after each allocation, zero when heap debugging is disabled.
+pub const RED_ZONE_SIZE: usize = if HEAP_DEBUG { 16 } else { 0 };
+
+/// Size needed in a segment for a buffer of `size` bytes and its red zone.
+pub fn padded_size(size: usize) -> usize {
+    size.saturating_add(RED_ZONE_SIZE)
+}
+
+/// Fill a whole block payload with the free pattern.
+///
+/// # Safety
+///
+/// The block payload must be writable and not in use.
+pub unsafe fn poison_block(block: &MemBlockLayout) {
+    if HEAP_DEBUG {
+        ptr::write_bytes(block.payload_ptr, FREE_PATTERN, block.num_segments * block.segment_size);
+    }
+}
+
+/// Check a segment just allocated for a buffer of `size` bytes is still poisoned, and place the red zone.
+///
+/// # Safety
+///
+/// `segment_ptr` must be a segment of `block`, owned by the caller.
+pub unsafe fn on_alloc(block: &MemBlockLayout, segment_ptr: *mut u8, size: usize) {
+    if !HEAP_DEBUG {
+        return;
+    }
+    let segment = slice::from_raw_parts(segment_ptr, block.segment_size);
+    if let Some(offset) = segment.iter().position(|b| *b != FREE_PATTERN) {
+        panic!(
+            "Heap corruption: segment {:#x} (size {}) was written after free, at offset {}",
+            segment_ptr as usize, block.segment_size, offset
+        );
+    }
+    write_red_zone(segment_ptr, size);
+}
+
+/// Check a buffer of `size` bytes about to be freed, and fill its segment with the free pattern.
+///
+/// # Safety
+///
+/// `segment_ptr` must be a segment of `block`, and the caller must be its owner.
+pub unsafe fn on_free(block: &MemBlockLayout, segment_ptr: *mut u8, size: usize, align: usize) {
+    if !HEAP_DEBUG {
+        return;
+    }
+    check_allocated(block, segment_ptr, size, align, "Double free");
+    check_red_zone(block, segment_ptr, size, align);
+    ptr::write_bytes(segment_ptr, FREE_PATTERN, block.segment_size);
+}
+
+/// Check a buffer of `size` bytes resized in place to `new_size` bytes, and move its red zone.
+///
+/// # Safety
+///
+/// `segment_ptr` must be a segment of `block`, and the caller must be its owner.
+pub unsafe fn on_resize(block: &MemBlockLayout, segment_ptr: *mut u8, size: usize, align: usize, new_size: usize) {
+    if !HEAP_DEBUG {
+        return;
+    }
+    check_allocated(block, segment_ptr, size, align, "Realloc after free");
+    check_red_zone(block, segment_ptr, size, align);
+    if new_size > size {
+        // Bytes between the old and the new red zone are not initialized, keep them poisoned
+        ptr::write_bytes(segment_ptr.add(size), FREE_PATTERN, new_size - size);
+    }
+    write_red_zone(segment_ptr, new_size);
+}
+
+/// Report an invalid pointer passed to free or realloc, it doesn't belong to the heap or is not the start of a segment.
+pub fn invalid_pointer(ptr: *mut u8, size: usize, align: usize) -> ! {
+    panic!("Invalid heap pointer {:#x} (size {}, align {}): not a heap segment", ptr as usize, size, align);
+}
+
+fn check_allocated(block: &MemBlockLayout, segment_ptr: *mut u8, size: usize, align: usize, what: &str) {
+    if !block.is_allocated(segment_ptr) {
+        panic!(
+            "{} of {:#x} (size {}, align {}) in block of segments of {} bytes",
+            what, segment_ptr as usize, size, align, block.segment_size
+        );
+    }
+}
+
+unsafe fn check_red_zone(block: &MemBlockLayout, segment_ptr: *mut u8, size: usize, align: usize) {
+    let red_zone = slice::from_raw_parts(segment_ptr.add(size), RED_ZONE_SIZE);
+    if let Some(offset) = red_zone.iter().position(|b| *b != RED_ZONE_PATTERN) {
+        panic!(
+            "Heap buffer overflow: {:#x} (size {}, align {}) was written past the end, at byte {} (segment size {})",
+            segment_ptr as usize, size, align, size + offset, block.segment_size
+        );
+    }
+}
+
+unsafe fn write_red_zone(segment_ptr: *mut u8, size: usize) {
+    ptr::write_bytes(segment_ptr.add(size), RED_ZONE_PATTERN, RED_ZONE_SIZE);
+}
\ No newline at end of file
@@ -1,135 +1,256 @@
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{
-    ptr::null_mut,
+    ptr::{
+        self, null_mut
+    },
     sync::atomic::{
-        AtomicUsize, Ordering
+        AtomicBool, AtomicPtr, AtomicUsize, Ordering
     },
 };
 use super::{
-    layout::MemBlockSet,
-    arch::raw_mem
+    layout::{
+        MemBlockSet, MemBlockLayout
+    },
+    debug::{
+        self, padded_size, RED_ZONE_SIZE
+    },
+    trace, profile
 };
-use crate::sys::KMutex;
 
 extern crate alloc;
 
 /// Memory Global Allocator
+///
+/// Doesn't use any lock, segments are popped and pushed atomically, so it can be used from interrupt handlers.
 struct Memory;
 
 impl Memory {
-    unsafe fn get_block_set(&self) -> &mut MemBlockSet {
-        let (mem_ptr, _) = raw_mem();
-        &mut*(mem_ptr as *mut MemBlockSet)
+    fn get_block_set(&self) -> Option<&'static MemBlockSet> {
+        unsafe {
+            BLOCK_SET.load(Ordering::Acquire).as_ref()
+        }
+    }
+
+    fn owner_block(&self, ptr: *mut u8, layout: &Layout) -> &'static MemBlockLayout {
+        if let Some(block_layout) = self.get_block_set().and_then(|block_set| block_set.owns_segment(ptr)) {
+            block_layout
+        }
+        else {
+            debug::invalid_pointer(ptr, layout.size(), layout.align());
+        }
+    }
+
+    /// Pop a segment and account the allocation.
+    ///
+    /// If `whole` is true, the allocation takes all the usable bytes of the segment instead of `size`. Tracing and profiling get `size` anyway, what the caller needs.
+    /// * Return: segment address and size of the allocation.
+    fn alloc_segment(&self, size: usize, align: usize, spill: bool, whole: bool) -> Option<(*mut u8, usize)> {
+        let (segment_ptr, block_layout, spilled) = self.get_block_set()?.pop_segment(padded_size(size), align, spill)?;
+        let alloc_size = if whole { block_layout.segment_size - RED_ZONE_SIZE } else { size };
+        unsafe {
+            debug::on_alloc(block_layout, segment_ptr, alloc_size);
+        }
+        trace::on_alloc(segment_ptr, size, block_layout.segment_size);
+        profile::on_alloc(size);
+        block_layout.requested_bytes.fetch_add(alloc_size, Ordering::Relaxed);
+        if spilled {
+            block_layout.spilled_allocs.fetch_add(1, Ordering::Relaxed);
+        }
+        let used_segments = NUM_SEGS.fetch_add(1, Ordering::Relaxed) + 1;
+        PEAK_SEGS.fetch_max(used_segments, Ordering::Relaxed);
+        USED_MEM.fetch_add(alloc_size, Ordering::Relaxed);
+        Some((segment_ptr, alloc_size))
+    }
+
+    /// Push a segment back and account the free. `requested` is the size the allocation was profiled with.
+    unsafe fn free_segment(&self, ptr: *mut u8, layout: Layout, requested: usize) {
+        let block_layout = self.owner_block(ptr, &layout);
+        debug::on_free(block_layout, ptr, layout.size(), layout.align());
+        trace::on_free(ptr);
+        profile::on_free(requested);
+        if let Err(e) = block_layout.push_address(ptr) {
+            panic!("Could not push address into segment stack: {}", e.msg());
+        }
+        block_layout.requested_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        NUM_SEGS.fetch_sub(1, Ordering::Relaxed);
+        USED_MEM.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    /// Move a buffer of `size` bytes to a new segment that fits `new_size` bytes, and free the old one.
+    unsafe fn move_segment(&self, ptr: *mut u8, size: usize, align: usize, new_size: usize, spill: bool) -> Option<*mut u8> {
+        let (new_ptr, _) = self.alloc_segment(new_size, align, spill, false)?;
+        // Only the bytes actually in use are copied, not the whole segment
+        ptr::copy_nonoverlapping(ptr, new_ptr, size.min(new_size));
+        self.dealloc(ptr, Layout::from_size_align_unchecked(size, align));
+        Some(new_ptr)
     }
 }
 
 unsafe impl GlobalAlloc for Memory {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let num_segs = NUM_SEGS.acquire();
-        let used_mem = USED_MEM.acquire();
-
-        let block_set = self.get_block_set();
-        if let Some(block_layout) = block_set.find_block(layout.size()) {
-            if layout.size() < block_layout.segment_size {
-                if let Some(segment_ptr) = block_layout.pop_address() {
-                    num_segs.fetch_add(1, Ordering::Relaxed);
-                    used_mem.fetch_add(layout.size(), Ordering::Relaxed);
-                    segment_ptr
-                }
-                else {
-                    null_mut()
-                }
-            }
-            else {
-                null_mut()
-            }
+        if let Some((segment_ptr, _)) = self.alloc_segment(layout.size(), layout.align(), SPILL.load(Ordering::Relaxed), false) {
+            segment_ptr
         }
         else {
+            FAILED_ALLOCS.fetch_add(1, Ordering::Relaxed);
             null_mut()
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let num_segs = NUM_SEGS.acquire();
-        let used_mem = USED_MEM.acquire();
-
-        let block_set = self.get_block_set();
-        if let Some(block_layout) = block_set.owns_segment(ptr) {
-            if let Err(e) = block_layout.push_address(ptr) {
-                panic!("Could not push address into segment stack: {}", e.msg());
-            }
-            num_segs.fetch_sub(1, Ordering::Relaxed);
-            used_mem.fetch_sub(layout.size(), Ordering::Relaxed);
-        }
-        else {
-            panic!("Could not find a block that owns the segment {:#x}", ptr as usize);
-        }
+        self.free_segment(ptr, layout, layout.size());
     }
 
+    /// Resize in place while the new size fits the segment, unless moving to a smaller bucket frees at least half of it.
+    ///
+    /// Growing past the segment size always moves the buffer to a segment of a bigger bucket and copies the bytes in use: neighbour segments can't be merged, so there is no way to grow in place.
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        let num_segs = NUM_SEGS.acquire();
-        let used_mem = USED_MEM.acquire();
-
-        let block_set = self.get_block_set();
-        if let Some(block_layout) = block_set.owns_segment(ptr) {
-            // If new size fits within the current memory segment, reuse it
-            if new_size <= block_layout.segment_size {
-                used_mem.fetch_add(new_size - layout.size(), Ordering::Relaxed);
-                ptr
-            }
-            else {
-                core::mem::drop(num_segs);
-                core::mem::drop(used_mem);
-                // Call default implementation of realloc on a different type, otherwise we would get a recursive infinite loop
-                Super::realloc(&AUX_M, ptr, layout, new_size)
+        let block_layout = self.owner_block(ptr, &layout);
+        let (size, align) = (layout.size(), layout.align());
+        if padded_size(new_size) <= block_layout.segment_size {
+            // Move down to a smaller bucket if it frees at least half of the segment, only if there are free segments there
+            let worth_moving = self.get_block_set()
+                .and_then(|block_set| block_set.bucket_size(padded_size(new_size), align))
+                .is_some_and(|bucket_size| bucket_size <= block_layout.segment_size / 2);
+            if worth_moving {
+                if let Some(new_ptr) = self.move_segment(ptr, size, align, new_size, false) {
+                    return new_ptr;
+                }
             }
+            debug::on_resize(block_layout, ptr, size, align, new_size);
+            trace::on_resize(ptr, new_size);
+            profile::on_free(size);
+            profile::on_alloc(new_size);
+            // Grow or shrink in place. Differences are added in two's complement, so shrinking wraps around correctly
+            let diff = new_size.wrapping_sub(size);
+            block_layout.requested_bytes.fetch_add(diff, Ordering::Relaxed);
+            USED_MEM.fetch_add(diff, Ordering::Relaxed);
+            ptr
+        }
+        else if let Some(new_ptr) = self.move_segment(ptr, size, align, new_size, SPILL.load(Ordering::Relaxed)) {
+            new_ptr
         }
         else {
-            panic!("Could not find a block that owns the segment {:#x}", ptr as usize);
+            // Old buffer is left untouched, as required by GlobalAlloc
+            FAILED_ALLOCS.fetch_add(1, Ordering::Relaxed);
+            null_mut()
         }
     }
 }
 
 /// Global Allocator static instance
-#[global_allocator]
+#[cfg_attr(not(feature = "test"), global_allocator)]
 static GLOB_ALLOC : Memory = Memory;
 
-/// Awful trick used to avoid reimplementing GlobalAlloc::realloc and to emulate a call to "super" (trait default implementation of realloc).
-struct _M;
+/// Allocator instance, to be tested on the host without being the global allocator.
+#[cfg(feature = "test")]
+pub fn test_allocator() -> &'static impl GlobalAlloc {
+    &GLOB_ALLOC
+}
+
+/// Allocate a whole segment, from the bucket that fits `size` bytes aligned to `align` (following the spill policy).
+///
+/// The allocation takes all the usable bytes of the segment, not only `size`, and it's accounted that way. Allocation tracing and profiling record `size`.
+/// * Return: segment address and its usable size, at least `size`. Free it with [`dealloc_segment`], the usable size and `size`.
+pub fn alloc_segment(size: usize, align: usize) -> Option<(*mut u8, usize)> {
+    let segment = GLOB_ALLOC.alloc_segment(size, align, SPILL.load(Ordering::Relaxed), true);
+    if segment.is_none() {
+        FAILED_ALLOCS.fetch_add(1, Ordering::Relaxed);
+    }
+    segment
+}
+
+/// Free a segment allocated with [`alloc_segment`].
+///
+/// # Safety
+///
+/// `ptr` must come from [`alloc_segment`] with the same `align` and `requested` size, `size` must be the usable size it returned, and the segment must not be used anymore.
+pub unsafe fn dealloc_segment(ptr: *mut u8, size: usize, align: usize, requested: usize) {
+    GLOB_ALLOC.free_segment(ptr, Layout::from_size_align_unchecked(size, align), requested);
+}
+
+/// Usable size of the segments in the bucket that fits `size` bytes aligned to `align`, what [`alloc_segment`] returns if the allocation doesn't spill.
+/// * Return: the size, or `None` if no bucket fits or memory is not set up.
+pub fn usable_size(size: usize, align: usize) -> Option<usize> {
+    GLOB_ALLOC.get_block_set()?
+        .bucket_size(padded_size(size), align)
+        .map(|segment_size| segment_size - RED_ZONE_SIZE)
+}
 
-type Super = _M;
+/// Block set used by the allocator, null until the memory is set up.
+static BLOCK_SET : AtomicPtr<MemBlockSet> = AtomicPtr::new(null_mut());
 
-unsafe impl GlobalAlloc for _M {
-    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
-        GLOB_ALLOC.alloc(_layout)
+/// Set the block set used by the allocator, and reset the counters.
+pub(super) fn set_block_set(block_set: *mut MemBlockSet) {
+    NUM_SEGS.store(0, Ordering::Relaxed);
+    USED_MEM.store(0, Ordering::Relaxed);
+    PEAK_SEGS.store(0, Ordering::Relaxed);
+    FAILED_ALLOCS.store(0, Ordering::Relaxed);
+    trace::reset();
+    profile::reset();
+    BLOCK_SET.store(block_set, Ordering::Release);
+}
+
+/// Number of segments used.
+static NUM_SEGS : AtomicUsize = AtomicUsize::new(0);
+
+/// Actual memory used.
+static USED_MEM : AtomicUsize = AtomicUsize::new(0);
+
+/// What to do when the bucket that fits an allocation is full.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SpillPolicy {
+    /// Fail the allocation.
+    Strict,
+    /// Try with the next bucket size, and so on. Default policy.
+    Spill
+}
+
+/// Set the policy used when the bucket that fits an allocation is full.
+pub fn set_spill_policy(policy: SpillPolicy) {
+    SPILL.store(policy == SpillPolicy::Spill, Ordering::Relaxed);
+}
+
+/// Current spill policy.
+pub fn spill_policy() -> SpillPolicy {
+    if SPILL.load(Ordering::Relaxed) {
+        SpillPolicy::Spill
     }
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        GLOB_ALLOC.dealloc(_ptr, _layout)
+    else {
+        SpillPolicy::Strict
     }
 }
 
-static AUX_M : _M = _M;
+static SPILL : AtomicBool = AtomicBool::new(true);
+
+/// Maximum number of segments used at the same time.
+static PEAK_SEGS : AtomicUsize = AtomicUsize::new(0);
 
-/// Number of segments used. It acts as both, a counter for statistics and a lock to acquire the Memory resource.
-static NUM_SEGS : KMutex<AtomicUsize> = KMutex::new(AtomicUsize::new(0));
+/// Number of allocations that failed.
+static FAILED_ALLOCS : AtomicUsize = AtomicUsize::new(0);
 
-/// Actual memory used. It acts as both, a counter for statistics and a lock to acquire the Memory resource.
-static USED_MEM : KMutex<AtomicUsize> = KMutex::new(AtomicUsize::new(0));
+/// Run `func` with the block set, if memory is already set up.
+/// * Arguments: block set, number of segments used at the same time, and number of failed allocations.
+pub(super) fn with_block_set<T>(func: impl FnOnce(Option<&MemBlockSet>, usize, usize) -> T) -> T {
+    func(GLOB_ALLOC.get_block_set(), PEAK_SEGS.load(Ordering::Relaxed), FAILED_ALLOCS.load(Ordering::Relaxed))
+}
 
+#[cfg(not(feature = "test"))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     //TODO: stop task scheduling, once we have multitasking
-    let block_set = unsafe { GLOB_ALLOC.get_block_set() };
     let mut total_num_segments = 0;
     let mut total_mem = 0;
-    for i in 0..block_set.num_blocks {
-        if let Some(block) = block_set.block_at(i) {
-            total_mem += block.segment_size * block.num_segments;
-            total_num_segments += block.num_segments;
+    if let Some(block_set) = GLOB_ALLOC.get_block_set() {
+        for i in 0..block_set.num_blocks {
+            if let Some(block) = block_set.block_at(i) {
+                total_mem += block.segment_size * block.num_segments;
+                total_num_segments += block.num_segments;
+            }
         }
     }
-    let used_num_segments = NUM_SEGS.reset().acquire().load(Ordering::Relaxed);
-    let used_mem = USED_MEM.reset().acquire().load(Ordering::Relaxed);
+    let used_num_segments = NUM_SEGS.load(Ordering::Relaxed);
+    let used_mem = USED_MEM.load(Ordering::Relaxed);
     panic!(
         "Memory allocation error: {:?} | Segments allocated: {}/{}({:.2}%) | Memory used: {}/{}({:.2}%)",
         layout,
@@ -140,4 +261,4 @@ fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
         total_mem,
         (used_mem as f64 / total_mem as f64) * 100.0
     );
-}
+}
\ No newline at end of file
@@ -1,24 +1,33 @@
 //! Memory initializations.
 
 use super::{
+    globalloc::set_block_set,
+    debug::poison_block,
     layout::{
-        MemBlockLayout, MemBlockSet, MAX_NUM_BLOCKS
+        MemBlockLayout, MemBlockSet, MAX_SCHEMA_BLOCKS
     },
     arch::{
-        raw_mem, ALIGN
+        raw_mem, raw_mem_regions, ALIGN, MAX_SEGMENT_ALIGN
     }
 };
 
-use core::mem::size_of;
+use core::{
+    mem::size_of,
+    sync::atomic::AtomicUsize
+};
 
 /// 4K segment size
 const DEFAULT_SEGMENT_SIZE : usize = 4*1024;
 
 /// Initialize memory structures.
 /// 
+/// The raw memory regions must be already added (see `arch::add_raw_mem`).
+/// 
 /// Divide the memory in N blocks (max 5) of specified segment size (in bytes) and % of the memory occupied by the block (the first and second tuple positions respectively).
+/// The percentages apply to the sum of all regions. Blocks are placed one after another, and a block that doesn't fit in the remaining space of a region continues in the next one, so it may end up split in several blocks with the same segment size.
+/// Memory at the end of a region that is too small for a segment is lost, so the last blocks may get a bit less memory than requested.
 /// The segment sizes must be sorted in ascending order, and the sum of all % must be 100, otherwise it will panic.
-/// Alignment is not adjusted in segments, only in blocks, so the user is responsable for choosing a segment size that is a multiple of the architecture alignment.
+/// Segment sizes are rounded up to a multiple of the architecture alignment. Each segment is aligned to the biggest power of two that divides its size (up to a page), so allocations requiring a bigger alignment go to blocks of bigger segments.
 /// 
 /// # Example
 /// 
@@ -34,122 +43,190 @@ pub fn setup_mem(schema: &[(usize, u8)]) {
         schema
     };
 
-    if schema.len() > MAX_NUM_BLOCKS {
-        panic!("Number of blocks can't be bigger than {} constant", MAX_NUM_BLOCKS);
+    check_schema(schema);
+
+    unsafe {
+        init_mem(schema);
+    }
+}
+
+/// Check a memory schema is valid, panic otherwise.
+///
+/// It's a const function, so a schema stored in a constant is validated at compile time:
+///
+/// ```ignore
+/// const MY_SCHEMA: &[(usize, u8)] = check_schema(&[(1024, 90), (usize::MAX, 10)]);
+/// ```
+pub const fn check_schema(schema: &[(usize, u8)]) -> &[(usize, u8)] {
+    if schema.is_empty() {
+        panic!("Memory schema must have at least one block");
+    }
+
+    if schema.len() > MAX_SCHEMA_BLOCKS {
+        panic!("Number of blocks can't be bigger than MAX_SCHEMA_BLOCKS constant");
     }
 
     let mut sum = 0;
     let mut last_size = 0;
-    for (size, percentage) in schema {
-        sum += percentage;
-        if *size <= last_size {
+    let mut i = 0;
+    while i < schema.len() {
+        let (size, percentage) = schema[i];
+        sum += percentage as usize;
+        if size <= last_size {
             panic!("Blocks must be ordered from smaller segment size to bigger");
         }
-        last_size = *size;
+        last_size = size;
+        i += 1;
     }
 
     if sum != 100 {
         panic!("Sum of block size percentage must be 100");
     }
 
-    unsafe {
-        init_mem(schema);
-    }
+    schema
 }
 
 unsafe fn init_mem(schema: &[(usize, u8)]) {
-    let (mem_ptr, mem_size) = raw_mem();
+    let (mem_ptr, first_size) = raw_mem();
+    if mem_ptr.is_null() || first_size <= size_of::<MemBlockSet>() {
+        panic!("No raw memory available, it must be set before setting up the memory schema");
+    }
+    let regions = raw_mem_regions();
+    let regions = regions.as_slice();
 
-    // Generate MemBlockSet struct
+    // Generate MemBlockSet struct, with empty layouts
     let mut block_set = MemBlockSet {
-        block_layouts: core::mem::MaybeUninit::uninit().assume_init(),
-        num_blocks: schema.len()
+        block_layouts: core::array::from_fn(|_| MemBlockLayout::empty()),
+        num_blocks: 0
     };
-    // Fill the array with empty layouts
-    for i in 0..MAX_NUM_BLOCKS {
-        block_set.block_layouts[i] = MemBlockLayout::empty();
-    }
 
-    let mut block_ptr = mem_ptr.add(size_of::<MemBlockSet>());
-    let mem_size = mem_size - size_of::<MemBlockSet>();
+    // The block set struct is stored at the beginning of the first region
+    let header_size = size_of::<MemBlockSet>().next_multiple_of(ALIGN);
+    let mem_size = regions.iter().map(|region| region.size).sum::<usize>() - header_size;
+    let mut region_index = 0;
+    let mut block_ptr = mem_ptr.add(header_size);
+    let mut region_end = mem_ptr.add(first_size);
 
     // Fill the valid block layouts
-    for i in 0..schema.len() {
-        let mut block_size = (mem_size * schema[i].1 as usize) / 100;
+    for (i, (segment_size, percentage)) in schema.iter().copied().enumerate() {
+        // Adjust alignment in segment size
+        let segment_size = segment_size.checked_next_multiple_of(ALIGN).unwrap_or(segment_size);
+        let mut pending_size = (mem_size * percentage as usize) / 100;
         // Adjust alignment in block size
-        block_size -= block_size % ALIGN;
-
-        if block_ptr as usize % ALIGN != 0 {
-            panic!("Bad alignment in block {} -> {} {:#x}", i, block_ptr as usize, block_size);
+        pending_size -= pending_size % ALIGN;
+        // Blocks of one single segment can be shrunk to fit, other blocks need at least one aligned segment
+        let min_size = if segment_size >= pending_size {
+            size_of::<*mut u8>() + MAX_SEGMENT_ALIGN + ALIGN
+        }
+        else {
+            segment_size + size_of::<*mut u8>() + segment_align(segment_size)
+        };
+
+        // Remaining of a split block too small for a segment is lost
+        while pending_size >= min_size {
+            let mut available = region_end.offset_from(block_ptr) as usize;
+            available -= available % ALIGN;
+            if available < min_size {
+                // Move to next region
+                region_index += 1;
+                if let Some(region) = regions.get(region_index) {
+                    block_ptr = region.ptr;
+                    region_end = region.ptr.add(region.size);
+                    continue;
+                }
+                else {
+                    break;
+                }
+            }
+            let block_size = pending_size.min(available);
+
+            if block_ptr as usize % ALIGN != 0 {
+                panic!("Bad alignment in block {} -> {} {:#x}", i, block_ptr as usize, block_size);
+            }
+
+            // Init block
+            block_set.block_layouts[block_set.num_blocks] = init_block(block_ptr, block_size, segment_size);
+            block_set.num_blocks += 1;
+            // Recalculate block starting address
+            block_ptr = block_ptr.add(block_size);
+            pending_size -= block_size;
         }
-
-        // Init block
-        block_set.block_layouts[i] = init_block(block_ptr, block_size, schema[i].0);
-        // Recalculate block starting address
-        block_ptr = block_ptr.add(block_size);
     }
 
     // Store block set struct
-    *((mem_ptr) as *mut MemBlockSet) = block_set;
+    let block_set_ptr = mem_ptr as *mut MemBlockSet;
+    block_set_ptr.write(block_set);
+    set_block_set(block_set_ptr);
+}
+
+/// Alignment of the segments of a given size: the biggest power of two that divides the size, up to `MAX_SEGMENT_ALIGN`.
+fn segment_align(segment_size: usize) -> usize {
+    (1usize << segment_size.trailing_zeros()).min(MAX_SEGMENT_ALIGN)
 }
 
 unsafe fn init_block(block_base_address: *mut u8, block_size: usize, segment_size: usize) -> MemBlockLayout {
     
     /*
-    block_size = stack_size_bytes + num_segments * segment_size
-    --> stack_size_bytes = block_size - num_segments * segment_size
-    stack_size_bytes = size_of::<*mut u8>() * num_segments
-    --> num_segments = stack_size_bytes / size_of::<*mut u8>()
-
-    stack_size_bytes = block_size - (stack_size_bytes / size_of::<*mut u8>()) * segment_size
-    stack_size_bytes = block_size - (stack_size_bytes * segment_size) / size_of::<*mut u8>()
-    stack_size_bytes + (stack_size_bytes * segment_size) / size_of::<*mut u8>() = block_size
-    (size_of::<*mut u8>() * stack_size_bytes) / size_of::<*mut u8>() + (stack_size_bytes * segment_size) / size_of::<*mut u8>() = block_size
-    (size_of::<*mut u8>() * stack_size_bytes + stack_size_bytes * segment_size) / size_of::<*mut u8>() = block_size
-    stack_size_bytes * (size_of::<*mut u8>() + segment_size) / size_of::<*mut u8>() = block_size
-    stack_size_bytes * (size_of::<*mut u8>() + segment_size) = block_size * size_of::<*mut u8>()
-    stack_size_bytes = block_size * size_of::<*mut u8>() / (size_of::<*mut u8>() + segment_size)
+    block_size = links_size_bytes + padding + num_segments * segment_size
+    links_size_bytes = size_of::<*mut u8>() * num_segments
+    padding < align
+
+    Taking the worst case padding:
+    block_size - (align - 1) >= num_segments * size_of::<*mut u8>() + num_segments * segment_size
+    --> num_segments = (block_size - (align - 1)) / (size_of::<*mut u8>() + segment_size)
     */
 
-    let (num_segments, segment_size) = if segment_size > block_size {
+    let block_end = block_base_address.add(block_size);
+    let align = segment_align(segment_size);
+    let num_segments = if segment_size < block_size {
+        block_size.saturating_sub(align - 1) / (size_of::<*mut u8>() + segment_size)
+    }
+    else {
+        0
+    };
+
+    let (num_segments, segment_size, align, payload_ptr) = if num_segments > 0 {
+        // Payload at the end of the block, aligned down, the padding goes between the stack and the payload
+        let payload_ptr = block_end.sub(num_segments * segment_size);
+        let payload_ptr = payload_ptr.sub(payload_ptr as usize % align);
         (
-            1,
-            block_size - size_of::<*mut u8>()
+            num_segments,
+            segment_size,
+            align,
+            payload_ptr
         )
     }
     else {
-        let stack_size_bytes = (block_size * size_of::<*mut u8>()) / (size_of::<*mut u8>() + segment_size);
-        let num_segments = stack_size_bytes / size_of::<*mut u8>();
+        // One single segment that takes the whole block
+        let payload_ptr = block_base_address.add(size_of::<*mut u8>());
+        let payload_ptr = payload_ptr.add(payload_ptr.align_offset(MAX_SEGMENT_ALIGN));
+        if payload_ptr >= block_end {
+            panic!("Block too small for a segment -> {:#x} {}", block_base_address as usize, block_size);
+        }
+        let segment_size = block_end.offset_from(payload_ptr) as usize;
         (
-            num_segments,
-            segment_size
+            1,
+            segment_size - segment_size % ALIGN,
+            MAX_SEGMENT_ALIGN,
+            payload_ptr
         )
     };
 
-    // Recalc stack size to keep alignment
-    let stack_size_bytes = block_size - num_segments * segment_size;
-    let payload_ptr = block_base_address.add(stack_size_bytes);
-
-    if payload_ptr as usize % ALIGN != 0 {
-        panic!("Bad alignment in payload -> {:#x} {}", payload_ptr as usize, stack_size_bytes);
+    if payload_ptr as usize % align != 0 {
+        panic!("Bad alignment in payload -> {:#x} {}", payload_ptr as usize, align);
     }
 
-    // Convert to pointer of pointers
-    let block_base_address = block_base_address as *mut *mut u8;
-
-    // Create stack with pointers to all segments in the block
-    for segment_index in 0..num_segments {
-        let offset = segment_index * segment_size;
-        *(block_base_address.add(segment_index)) = payload_ptr.add(offset);
-    }
-
-    // Create layout struct
-    MemBlockLayout {
-        stack_ptr: block_base_address,
+    // Create layout struct, with the free list links at the beginning of the block
+    let mut block = MemBlockLayout {
+        links_ptr: block_base_address as *const AtomicUsize,
         payload_ptr,
         segment_size,
+        align,
         num_segments,
-        used_segments: 0,
-        block_size
-    }
+        block_size,
+        ..MemBlockLayout::empty()
+    };
+    block.init_free_list();
+    poison_block(&block);
+    block
 }
\ No newline at end of file
@@ -1,39 +1,60 @@
 use core::{
     alloc::Layout,
-    ops::Drop
+    mem::size_of,
+    ops::{
+        Deref, DerefMut, Drop
+    },
+    ptr, slice
+};
+use super::{
+    arch::ALIGN,
+    alloc_segment, dealloc_segment
 };
-use super::arch::ALIGN;
 
 /// A box that allocates exact segment sizes.
-pub struct KBox {
-    buffer: *mut u8,
-    layout: Layout
+///
+/// The box takes a whole segment, so it may hold more than requested: the extra elements are not wasted, they are part of the box.
+/// `KBox` (of bytes) is a raw buffer, `KBox<T>` is a slice of `T`.
+pub struct KBox<T = u8> {
+    buffer: *mut T,
+    len: usize,
+    layout: Layout,
+    /// Bytes asked to the allocator.
+    requested: usize
 }
 
 impl KBox {
-    /// Allocates at least `size` bytes, but rounds up to the closest segment length.
+    /// Allocates at least `size` bytes, but rounds up to the closest segment length. The buffer is filled with zeros.
     pub fn new(size: usize) -> Result<Self, ()> {
-        let layout = if let Ok(l) = Layout::from_size_align(size, ALIGN) {
-            l
-        }
-        else {
-            return Err(());
-        };
-        //TODO: ask the mem global allocator for the closest segment size
-        let buffer = unsafe { alloc::alloc::alloc(layout) };
-        if buffer.is_null() {
-            Err(())
-        }
-        else {
-            Ok(
-                Self {
-                    buffer,
-                    layout
-                }
-            )
+        Self::new_slice(size)
+    }
+}
+
+impl<T: Default> KBox<T> {
+    /// Allocates a slice of at least `len` elements, rounded up to fill the segment. Elements are set to their default value.
+    pub fn new_slice(len: usize) -> Result<Self, ()> {
+        let layout = Layout::array::<T>(len).map_err(|_| ())?;
+        let align = layout.align().max(ALIGN);
+        let (buffer, size) = alloc_segment(layout.size(), align).ok_or(())?;
+        let buffer = buffer as *mut T;
+        let len = if size_of::<T>() == 0 { len } else { size / size_of::<T>() };
+        for i in 0..len {
+            unsafe {
+                buffer.add(i).write(T::default());
+            }
         }
+        Ok(
+            Self {
+                buffer,
+                len,
+                layout: unsafe { Layout::from_size_align_unchecked(size, align) },
+                requested: layout.size()
+            }
+        )
     }
+}
 
+impl<T> KBox<T> {
     /// Bottom address of the allocated buffer.
     pub fn bottom(&self) -> *const u8 {
         self.buffer as *const u8
@@ -42,20 +63,49 @@ impl KBox {
     /// Top address of the allocated buffer.
     pub fn top(&self) -> *const u8 {
         unsafe {
-            self.buffer.add(self.layout.size())
+            self.bottom().add(self.layout.size())
         }
     }
 
-    /// Allocated buffer size.
+    /// Allocated buffer size, the usable size of the segment.
     pub fn size(&self) -> usize {
         self.layout.size()
-    } 
+    }
+
+    /// Number of elements, all the ones that fit in the segment.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The box has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
 
-impl Drop for KBox {
-    fn drop(&mut self) {
+impl<T> Deref for KBox<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe {
+            slice::from_raw_parts(self.buffer, self.len)
+        }
+    }
+}
+
+impl<T> DerefMut for KBox<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
         unsafe {
-            alloc::alloc::dealloc(self.buffer, self.layout);
+            slice::from_raw_parts_mut(self.buffer, self.len)
         }
     }
 }
+
+impl<T> Drop for KBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.buffer, self.len));
+            dealloc_segment(self.buffer as *mut u8, self.layout.size(), self.layout.align(), self.requested);
+        }
+    }
+}
\ No newline at end of file
@@ -1,9 +1,76 @@
 //! Dynamic memory layout.
 
+use core::{
+    ptr::{
+        null, null_mut
+    },
+    sync::atomic::{
+        AtomicU64, AtomicUsize, Ordering
+    }
+};
+
 use crate::sys::KError;
 
+use super::debug::HEAP_DEBUG;
+
+/// Maximum number of blocks in a memory schema.
+pub const MAX_SCHEMA_BLOCKS: usize = 5;
+
+/// Maximum number of raw memory regions.
+pub const MAX_MEM_REGIONS: usize = 8;
+
 /// Maximum number of allowed memory blocks.
-pub const MAX_NUM_BLOCKS: usize = 5;
+/// A schema block that doesn't fit in the remaining space of a region is split in several blocks, one per region.
+pub const MAX_NUM_BLOCKS: usize = MAX_SCHEMA_BLOCKS + MAX_MEM_REGIONS - 1;
+
+/// Raw memory region.
+#[derive(Copy, Clone)]
+pub struct MemRegion {
+    /// Region start address.
+    pub ptr: *mut u8,
+    /// Region size in bytes.
+    pub size: usize
+}
+
+/// List of raw memory regions.
+#[derive(Copy, Clone)]
+pub struct MemRegions {
+    regions: [MemRegion; MAX_MEM_REGIONS],
+    len: usize
+}
+
+impl MemRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [MemRegion { ptr: null_mut(), size: 0 }; MAX_MEM_REGIONS],
+            len: 0
+        }
+    }
+
+    /// Add a region.
+    /// * Return: could be added or not.
+    pub fn push(&mut self, region: MemRegion) -> bool {
+        if self.len < MAX_MEM_REGIONS {
+            self.regions[self.len] = region;
+            self.len += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Regions as a slice.
+    pub fn as_slice(&self) -> &[MemRegion] {
+        &self.regions[..self.len]
+    }
+}
+
+impl Default for MemRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// Memory blocks set struct.
 #[repr(C)]
@@ -16,39 +83,47 @@ pub struct MemBlockSet {
 
 impl MemBlockSet {
     /// Get reference to block at specified index.
-    pub fn block_at(&mut self, index: usize) -> Option<&mut MemBlockLayout> {
+    pub fn block_at(&self, index: usize) -> Option<&MemBlockLayout> {
         if index < self.len() {
-            Some(&mut self.block_layouts[index])
+            Some(&self.block_layouts[index])
         }
         else {
             None
         }
     }
 
-    /// Find a block to allocate a buffer of the requested size.
-    pub fn find_block(&mut self, buf_size: usize) -> Option<&mut MemBlockLayout> {
-        for i in 0..self.len() {
-            if self.block_layouts[i].segment_size >= buf_size &&
-               self.block_layouts[i].used_segments < self.block_layouts[i].num_segments {
-                   return self.block_at(i);
+    /// Pop a segment from a block that fits a buffer of the requested size and alignment.
+    ///
+    /// The bucket is formed by the blocks with the smallest segment size that fits the buffer and the alignment. If `spill` is true and the bucket is full, try with the next bucket size, and so on.
+    /// * Return: segment address, block that owns it and whether it belongs to a bigger bucket (the allocation spilled).
+    pub fn pop_segment(&self, buf_size: usize, align: usize, spill: bool) -> Option<(*mut u8, &MemBlockLayout, bool)> {
+        let mut bucket_size = None;
+        for block in &self.block_layouts[..self.len()] {
+            if block.segment_size < buf_size || block.align < align {
+                continue;
+            }
+            let bucket_size = *bucket_size.get_or_insert(block.segment_size);
+            let spilled = block.segment_size != bucket_size;
+            if spilled && !spill {
+                break;
+            }
+            if let Some(segment_ptr) = block.pop_address() {
+                return Some((segment_ptr, block, spilled));
             }
         }
         None
     }
 
+    /// Segment size of the bucket that fits a buffer of the requested size and alignment, even if it's full.
+    pub fn bucket_size(&self, buf_size: usize, align: usize) -> Option<usize> {
+        self.block_layouts[..self.len()].iter()
+            .find(|block| block.segment_size >= buf_size && block.align >= align)
+            .map(|block| block.segment_size)
+    }
+
     /// Find the block that owns the provided segment.
-    pub unsafe fn owns_segment(&mut self, segment_ptr: *mut u8) -> Option<&mut MemBlockLayout> {
-        for i in 0..self.len() {
-            let payload_initial_ptr = self.block_layouts[i].payload_ptr;
-            let payload_size = self.block_layouts[i].segment_size * self.block_layouts[i].num_segments;
-            let payload_final_ptr = payload_initial_ptr.add(payload_size);
-
-            let distance = payload_final_ptr.offset_from(segment_ptr);
-            if distance > 0 && distance <= payload_size as isize {
-                return self.block_at(i);
-            }
-        }
-        None
+    pub fn owns_segment(&self, segment_ptr: *mut u8) -> Option<&MemBlockLayout> {
+        self.block_layouts[..self.len()].iter().find(|block| block.segment_index(segment_ptr).is_some())
     }
 
     /// Number of memory blocks.
@@ -57,19 +132,39 @@ impl MemBlockSet {
     }
 }
 
+/// Free list link and head value for "no segment".
+const NIL: u32 = u32::MAX;
+
+/// Link value of an allocated segment, only used with heap debugging.
+const ALLOCATED: usize = usize::MAX;
+
 /// Memory layout struct
+///
+/// Free segments are kept in a lock-free list. The head is a tagged index, the segment index in the lower 32 bits and a tag incremented on every change in the upper 32 bits, to avoid the ABA problem.
+/// The links are stored in an array at the beginning of the block, one per segment, so free segments are never touched.
+/// With heap debugging, the link of an allocated segment is set to a marker, to detect double frees.
 #[repr(C)]
 pub struct MemBlockLayout {
-    /// Pointe to stack of segments
-    pub stack_ptr: *mut *mut u8,
+    /// Pointer to the array of free list links, each one contains the index of the next free segment
+    pub links_ptr: *const AtomicUsize,
+    /// Tagged index of the first free segment
+    pub free_head: AtomicU64,
     /// Pointer to usable memory
     pub payload_ptr: *mut u8,
     /// Segment size in bytes
     pub segment_size: usize,
+    /// Alignment guaranteed for all segments in the block
+    pub align: usize,
     /// Total number of segments in the block
     pub num_segments: usize,
     /// Number of segments currently in use
-    pub used_segments: usize,
+    pub used_segments: AtomicUsize,
+    /// Maximum number of segments in use at the same time
+    pub peak_segments: AtomicUsize,
+    /// Bytes requested by the allocations currently in the block
+    pub requested_bytes: AtomicUsize,
+    /// Number of allocations served by this block because the smaller buckets were full
+    pub spilled_allocs: AtomicUsize,
     /// Block size
     pub block_size: usize
 }
@@ -77,46 +172,113 @@ pub struct MemBlockLayout {
 impl MemBlockLayout {
     pub fn empty() -> Self {
         Self {
-            stack_ptr: 0 as *mut *mut u8,
-            payload_ptr: 0 as *mut u8,
+            links_ptr: null(),
+            free_head: AtomicU64::new(NIL as u64),
+            payload_ptr: null_mut(),
             segment_size: 0,
+            align: 0,
             num_segments: 0,
-            used_segments: 0,
+            used_segments: AtomicUsize::new(0),
+            peak_segments: AtomicUsize::new(0),
+            requested_bytes: AtomicUsize::new(0),
+            spilled_allocs: AtomicUsize::new(0),
             block_size: 0
         }
     }
 
-    /// Pop address from stack.
-    pub unsafe fn pop_address(&mut self) -> Option<*mut u8> {
-        if self.used_segments < self.num_segments {
-            // Calculate current stack top pointer
-            let stack_top = self.stack_ptr.add(self.used_segments);
-            // Get value in the top of stack
-            let ptr = *stack_top;
-            // Set zero
-            *stack_top = 0 as *mut u8;
-            // Increment number of used segments
-            self.used_segments += 1;
-            Some(ptr)
+    /// Init the free list with all segments, in address order.
+    ///
+    /// # Safety
+    ///
+    /// `links_ptr` must point to writable memory for `num_segments` links.
+    pub unsafe fn init_free_list(&mut self) {
+        for index in 0..self.num_segments {
+            let next = if index + 1 < self.num_segments { index + 1 } else { NIL as usize };
+            (self.links_ptr as *mut AtomicUsize).add(index).write(AtomicUsize::new(next));
+        }
+        let first = if self.num_segments > 0 { 0 } else { NIL };
+        self.free_head = AtomicU64::new(first as u64);
+    }
+
+    /// Index of the segment that starts at `ptr`, if it belongs to this block.
+    pub fn segment_index(&self, ptr: *mut u8) -> Option<usize> {
+        if self.num_segments == 0 {
+            return None;
+        }
+        let offset = (ptr as usize).checked_sub(self.payload_ptr as usize)?;
+        let index = offset / self.segment_size;
+        if index < self.num_segments && offset % self.segment_size == 0 {
+            Some(index)
         }
         else {
             None
         }
     }
 
-    /// Push address to stack
-    pub unsafe fn push_address(&mut self, ptr: *mut u8) -> Result<(), KError> {
-        if self.used_segments > 0 {
-            // Decrement number of used segments
-            self.used_segments -= 1;
-            // Calculate current stack top pointer
-            let stack_top = self.stack_ptr.add(self.used_segments);
-            // Set ptr
-            *stack_top = ptr;
-            Ok(())
+    /// Segment that starts at `ptr` belongs to this block and is allocated.
+    ///
+    /// The allocated state is only tracked with heap debugging, otherwise any segment of the block is considered allocated.
+    pub fn is_allocated(&self, ptr: *mut u8) -> bool {
+        match self.segment_index(ptr) {
+            Some(index) => !HEAP_DEBUG || self.link(index).load(Ordering::Relaxed) == ALLOCATED,
+            None => false
         }
-        else {
-            Err(KError::FullSegStack)
+    }
+
+    fn link(&self, index: usize) -> &AtomicUsize {
+        unsafe {
+            &*self.links_ptr.add(index)
         }
     }
+
+    /// Pop address from the free list.
+    pub fn pop_address(&self) -> Option<*mut u8> {
+        let mut head = self.free_head.load(Ordering::Acquire);
+        loop {
+            let index = head as u32;
+            if index == NIL {
+                return None;
+            }
+            // The link may be changed meanwhile if other core pops this segment, but then the tag changes and the exchange fails
+            let next = self.link(index as usize).load(Ordering::Relaxed) as u32;
+            let new_head = Self::tagged(head, next);
+            match self.free_head.compare_exchange_weak(head, new_head, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    if HEAP_DEBUG {
+                        self.link(index as usize).store(ALLOCATED, Ordering::Relaxed);
+                    }
+                    let used = self.used_segments.fetch_add(1, Ordering::Relaxed) + 1;
+                    self.peak_segments.fetch_max(used, Ordering::Relaxed);
+                    return Some(unsafe { self.payload_ptr.add(index as usize * self.segment_size) });
+                },
+                Err(current) => head = current
+            }
+        }
+    }
+
+    /// Push address to the free list.
+    pub fn push_address(&self, ptr: *mut u8) -> Result<(), KError> {
+        let index = self.segment_index(ptr).ok_or(KError::OutBounds)?;
+        if HEAP_DEBUG {
+            self.link(index).compare_exchange(ALLOCATED, NIL as usize, Ordering::Relaxed, Ordering::Relaxed)
+                .map_err(|_| KError::DoubleFree)?;
+        }
+        self.used_segments.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| used.checked_sub(1))
+            .map_err(|_| KError::FullSegStack)?;
+        let mut head = self.free_head.load(Ordering::Relaxed);
+        loop {
+            self.link(index).store(head as u32 as usize, Ordering::Relaxed);
+            let new_head = Self::tagged(head, index as u32);
+            match self.free_head.compare_exchange_weak(head, new_head, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return Ok(()),
+                Err(current) => head = current
+            }
+        }
+    }
+
+    /// New head value pointing to `index`, with the tag of `head` incremented.
+    fn tagged(head: u64, index: u32) -> u64 {
+        let tag = (head >> 32).wrapping_add(1) & 0xFFFF_FFFF;
+        (tag << 32) | index as u64
+    }
 }
\ No newline at end of file
@@ -3,30 +3,72 @@
 pub mod layout;
 
 mod globalloc;
+pub use globalloc::{
+    SpillPolicy, set_spill_policy, spill_policy, alloc_segment, dealloc_segment, usable_size
+};
+#[cfg(feature = "test")]
+pub use globalloc::test_allocator;
 
 pub mod init;
 
+pub mod debug;
+
+pub mod trace;
+
+pub mod profile;
+
 pub mod arch;
 
+pub mod paging;
+
 mod kbox;
 pub use kbox::*;
 
+mod stats;
+pub use stats::*;
+
+/// Memory schema optimized for small allocations.
+pub const SMALL_SCHEMA: &[(usize, u8)] = init::check_schema(&[
+    (256, 80),              // 80% of mem in segments of 256Bytes
+    (1024, 10),             // 10% of mem in segments of 1K
+    (usize::MAX - 1, 5),    // Remaining 10% in two segments
+    (usize::MAX, 5)
+]);
+
+/// Memory schema optimized for big allocations.
+pub const BIG_SCHEMA: &[(usize, u8)] = init::check_schema(&[
+    (4*1024, 10),           // 10% of mem in segments of 4K
+    (128*1024, 80),         // 80% of mem in segments of 128K
+    (usize::MAX - 1, 5),    // Remaining 10% in two segments
+    (usize::MAX, 5)
+]);
+
+/// Memory schema selected at build time, validated at compile time.
+///
+/// - Feature `schema-file`: read from the file in the `THEK_MEM_SCHEMA` environment variable (see [`macros::mem_schema_file`]).
+/// - Feature `schema-big`: [`BIG_SCHEMA`].
+/// - Otherwise: [`SMALL_SCHEMA`].
+#[cfg(feature = "schema-file")]
+pub const SCHEMA: &[(usize, u8)] = init::check_schema(macros::mem_schema_file!());
+#[cfg(all(feature = "schema-big", not(feature = "schema-file")))]
+pub const SCHEMA: &[(usize, u8)] = BIG_SCHEMA;
+#[cfg(not(any(feature = "schema-big", feature = "schema-file")))]
+pub const SCHEMA: &[(usize, u8)] = SMALL_SCHEMA;
+
+#[cfg(all(feature = "schema-big", feature = "schema-file"))]
+compile_error!("Features schema-big and schema-file can't be enabled at the same time");
+
+/// Initialize the memory schema selected at build time (see [`SCHEMA`]).
+pub fn init_schema() {
+    init::setup_mem(SCHEMA);
+}
+
 /// Initialize a default memory schema optimized for small allocations.
 pub fn init_small_schema() {
-    init::setup_mem(&[
-        (256, 80),              // 80% of mem in segments of 256Bytes
-        (1024, 10),             // 10% of mem in segments of 1K
-        (usize::MAX - 1, 5),    // Remaining 10% in two segments
-        (usize::MAX, 5)
-    ]);
+    init::setup_mem(SMALL_SCHEMA);
 }
 
 /// Initialize a default memory schema optimized for big allocations.
 pub fn init_big_schema() {
-    init::setup_mem(&[
-        (4*1024, 10),           // 10% of mem in segments of 4K
-        (128*1024, 80),         // 80% of mem in segments of 128K
-        (usize::MAX - 1, 5),    // Remaining 10% in two segments
-        (usize::MAX, 5)
-    ]);
-}
+    init::setup_mem(BIG_SCHEMA);
+}
\ No newline at end of file
@@ -0,0 +1,15 @@
+//! Arch dependent paging.
+//!
+//! This module must provide, at least, the following public symbols:
+//!
+//! - `unsafe fn enable_nx()`
+//! - `unsafe fn free_window(phys_offset: usize) -> Option<(usize, usize)>`
+//! - `unsafe fn map_page(phys_offset: usize, virt: usize, phys: u64, flags: PageFlags) -> Result<(), KError>`
+//! - `unsafe fn unmap_page(phys_offset: usize, virt: usize) -> Result<u64, KError>`
+//! - `unsafe fn set_page_flags(phys_offset: usize, virt: usize, flags: PageFlags) -> Result<(), KError>`
+//! - `fn translate(phys_offset: usize, virt: usize) -> Option<u64>`
+
+#[cfg(feature = "pc64")]
+mod x86_64;
+#[cfg(feature = "pc64")]
+pub use self::x86_64::*;
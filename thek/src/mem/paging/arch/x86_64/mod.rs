@@ -0,0 +1,139 @@
+//! x86_64 paging, 4 level page tables.
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::{
+        control::Cr3,
+        model_specific::{
+            Efer, EferFlags
+        }
+    },
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate,
+        mapper::{
+            FlagUpdateError, MapToError, UnmapError
+        }
+    }
+};
+
+use crate::sys::KError;
+
+use super::super::{
+    PageFlags, alloc_frame
+};
+
+/// Enable the no-execute bit in page table entries.
+pub unsafe fn enable_nx() {
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+}
+
+/// Find a virtual window not used by any mapping: the highest free entry of the level 4 table, in the upper half.
+/// * Return: start address and size of the window.
+pub unsafe fn free_window(phys_offset: usize) -> Option<(usize, usize)> {
+    let table = level_4_table(phys_offset);
+    let index = (256..512).rev().find(|index| table[*index].is_unused())?;
+    // Sign extend the address, the upper half starts at entry 256
+    let start = VirtAddr::new_truncate((index as u64) << 39);
+    Some((start.as_u64() as usize, WINDOW_SIZE))
+}
+
+/// Map a page to a physical frame.
+pub unsafe fn map_page(phys_offset: usize, virt: usize, phys: u64, flags: PageFlags) -> Result<(), KError> {
+    let page = page(virt)?;
+    let frame = PhysFrame::<Size4KiB>::from_start_address(PhysAddr::new(phys)).map_err(|_| KError::OutBounds)?;
+    // Parent tables must allow everything, permissions are restricted in the last level
+    let mut parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if flags.user {
+        parent_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    mapper(phys_offset)
+        .map_to_with_table_flags(page, frame, table_flags(flags), parent_flags, &mut KernelFrames)
+        .map(|flush| flush.flush())
+        .map_err(|e| match e {
+            MapToError::FrameAllocationFailed => KError::OutOfFrames,
+            MapToError::ParentEntryHugePage => KError::Unsupported,
+            MapToError::PageAlreadyMapped(_) => KError::AlreadyMapped
+        })
+}
+
+/// Unmap a page.
+/// * Return: physical frame it was mapped to.
+pub unsafe fn unmap_page(phys_offset: usize, virt: usize) -> Result<u64, KError> {
+    let page = page(virt)?;
+    mapper(phys_offset)
+        .unmap(page)
+        .map(|(frame, flush)| {
+            flush.flush();
+            frame.start_address().as_u64()
+        })
+        .map_err(|e| match e {
+            UnmapError::ParentEntryHugePage => KError::Unsupported,
+            UnmapError::PageNotMapped => KError::NotMapped,
+            UnmapError::InvalidFrameAddress(_) => KError::Other
+        })
+}
+
+/// Change the flags of a mapped page.
+pub unsafe fn set_page_flags(phys_offset: usize, virt: usize, flags: PageFlags) -> Result<(), KError> {
+    let page = page(virt)?;
+    mapper(phys_offset)
+        .update_flags(page, table_flags(flags))
+        .map(|flush| flush.flush())
+        .map_err(|e| match e {
+            FlagUpdateError::PageNotMapped => KError::NotMapped,
+            FlagUpdateError::ParentEntryHugePage => KError::Unsupported
+        })
+}
+
+/// Physical address a virtual address is mapped to.
+pub fn translate(phys_offset: usize, virt: usize) -> Option<u64> {
+    let virt = VirtAddr::try_new(virt as u64).ok()?;
+    unsafe {
+        mapper(phys_offset).translate_addr(virt).map(|phys| phys.as_u64())
+    }
+}
+
+/// Size of the virtual window, the memory covered by one entry of the level 4 table (512 GB).
+const WINDOW_SIZE: usize = 1 << 39;
+
+fn page(virt: usize) -> Result<Page<Size4KiB>, KError> {
+    let virt = VirtAddr::try_new(virt as u64).map_err(|_| KError::OutBounds)?;
+    Page::from_start_address(virt).map_err(|_| KError::OutBounds)
+}
+
+fn table_flags(flags: PageFlags) -> PageTableFlags {
+    let mut table_flags = PageTableFlags::PRESENT;
+    if flags.writable {
+        table_flags |= PageTableFlags::WRITABLE;
+    }
+    if !flags.executable {
+        table_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if flags.user {
+        table_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    if flags.uncached {
+        table_flags |= PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    }
+    table_flags
+}
+
+/// Active level 4 table, accessed through the physical memory mapping.
+unsafe fn level_4_table(phys_offset: usize) -> &'static mut PageTable {
+    let (frame, _) = Cr3::read();
+    let virt = phys_offset as u64 + frame.start_address().as_u64();
+    &mut *(virt as *mut PageTable)
+}
+
+unsafe fn mapper(phys_offset: usize) -> OffsetPageTable<'static> {
+    OffsetPageTable::new(level_4_table(phys_offset), VirtAddr::new(phys_offset as u64))
+}
+
+/// Frames for new page tables, from the kernel frame allocator.
+struct KernelFrames;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        alloc_frame().map(|phys| PhysFrame::containing_address(PhysAddr::new(phys)))
+    }
+}
\ No newline at end of file
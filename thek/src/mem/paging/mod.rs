@@ -0,0 +1,321 @@
+//! Paging and virtual memory.
+//!
+//! The bootloader maps all the physical memory at an offset, and the heap lives in that mapping. This module controls the page tables to map and unmap pages with specific permissions, and map MMIO regions uncached.
+//!
+//! Physical frames for page tables, DMA buffers, etc. come from the frame allocator, that owns the physical regions added with [`add_frames`] (they must not be given to the heap).
+//! Freed frames are kept in a list, linked through the first bytes of each frame. When they run out, allocations fail and a warning is logged the first time (see [`failed_frame_allocs`]).
+//!
+//! Pages mapped by this module live in a virtual window that doesn't overlap any mapping done by the bootloader (see [`reserve_virt`]).
+//!
+//! # Example
+//!
+//! ```ignore
+//! // Map the local APIC registers
+//! let apic = unsafe { paging::map_mmio(0xFEE0_0000, 0x1000)? };
+//! // Map a fresh read-only page
+//! let frame = paging::alloc_frame().ok_or(KError::OutOfFrames)?;
+//! let page = paging::reserve_virt(PAGE_SIZE).ok_or(KError::Other)?;
+//! unsafe { paging::map_page(page, frame, PageFlags::READ_ONLY)? };
+//! ```
+
+pub mod arch;
+
+use core::{
+    ptr,
+    sync::atomic::{
+        AtomicUsize, Ordering
+    }
+};
+
+use crate::sys::{
+    KError, KMutex
+};
+
+/// Page size in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Maximum number of physical regions owned by the frame allocator.
+pub const MAX_FRAME_REGIONS: usize = 8;
+
+/// Page permissions and attributes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PageFlags {
+    /// Can be written, otherwise it's read-only.
+    pub writable: bool,
+    /// Code can be executed from the page, otherwise it's NX.
+    pub executable: bool,
+    /// Accessible from user mode.
+    pub user: bool,
+    /// Caching disabled, for MMIO.
+    pub uncached: bool
+}
+
+impl PageFlags {
+    /// Kernel data: read/write, not executable.
+    pub const DATA: Self = Self { writable: true, executable: false, user: false, uncached: false };
+    /// Kernel read-only data.
+    pub const READ_ONLY: Self = Self { writable: false, executable: false, user: false, uncached: false };
+    /// Kernel code: read-only, executable.
+    pub const CODE: Self = Self { writable: false, executable: true, user: false, uncached: false };
+    /// Memory mapped IO: read/write, not executable, uncached.
+    pub const MMIO: Self = Self { writable: true, executable: false, user: false, uncached: true };
+
+    /// Same flags, but accessible from user mode.
+    pub const fn user(self) -> Self {
+        Self {
+            user: true,
+            ..self
+        }
+    }
+}
+
+/// Init paging.
+///
+/// # Safety
+///
+/// All the physical memory must be mapped at `phys_mem_offset`. Must be called once, before any other function of this module.
+pub unsafe fn init_paging(phys_mem_offset: usize) {
+    PHYS_OFFSET.store(phys_mem_offset, Ordering::Relaxed);
+    arch::enable_nx();
+    if let Some((start, size)) = arch::free_window(phys_mem_offset) {
+        *VIRT_WINDOW.acquire() = (start, start + size);
+    }
+}
+
+/// Virtual address where a physical address is mapped by the bootloader.
+pub fn phys_to_virt(phys: u64) -> *mut u8 {
+    (PHYS_OFFSET.load(Ordering::Relaxed) as u64 + phys) as *mut u8
+}
+
+/// Physical address a virtual address is mapped to, if any.
+pub fn translate(virt: usize) -> Option<u64> {
+    arch::translate(PHYS_OFFSET.load(Ordering::Relaxed), virt)
+}
+
+/// Map the page that starts at `virt` to the physical frame that starts at `phys`.
+///
+/// # Safety
+///
+/// Mapping a frame in use by something else, or with wrong flags, breaks memory safety.
+pub unsafe fn map_page(virt: usize, phys: u64, flags: PageFlags) -> Result<(), KError> {
+    let _lock = PAGE_TABLES.acquire();
+    arch::map_page(PHYS_OFFSET.load(Ordering::Relaxed), virt, phys, flags)
+}
+
+/// Unmap the page that starts at `virt`.
+/// * Return: physical frame it was mapped to. It's not freed.
+///
+/// # Safety
+///
+/// Any reference to the page becomes invalid.
+pub unsafe fn unmap_page(virt: usize) -> Result<u64, KError> {
+    let _lock = PAGE_TABLES.acquire();
+    arch::unmap_page(PHYS_OFFSET.load(Ordering::Relaxed), virt)
+}
+
+/// Change the permissions of a mapped page.
+///
+/// # Safety
+///
+/// Removing permissions may break code using the page.
+pub unsafe fn set_page_flags(virt: usize, flags: PageFlags) -> Result<(), KError> {
+    let _lock = PAGE_TABLES.acquire();
+    arch::set_page_flags(PHYS_OFFSET.load(Ordering::Relaxed), virt, flags)
+}
+
+/// Reserve a range of virtual addresses, page aligned, to map pages in it.
+///
+/// Addresses are never reused.
+/// * Return: start address of the range, or `None` if the window is exhausted or there is no window.
+pub fn reserve_virt(size: usize) -> Option<usize> {
+    let size = size.checked_next_multiple_of(PAGE_SIZE)?;
+    let mut window = VIRT_WINDOW.acquire();
+    let (next, end) = *window;
+    if size > end - next {
+        return None;
+    }
+    window.0 = next + size;
+    Some(next)
+}
+
+/// Map a MMIO region uncached.
+/// * Return: virtual address of the region (with the same offset into the page as `phys`).
+///
+/// # Safety
+///
+/// The region must be device memory, not RAM in use.
+pub unsafe fn map_mmio(phys: u64, size: usize) -> Result<*mut u8, KError> {
+    let offset = (phys % PAGE_SIZE as u64) as usize;
+    let size = offset + size;
+    let virt = reserve_virt(size).ok_or(KError::Unsupported)?;
+    let first_frame = phys - offset as u64;
+    for page in (0..size).step_by(PAGE_SIZE) {
+        if let Err(e) = map_page(virt + page, first_frame + page as u64, PageFlags::MMIO) {
+            // Undo the pages already mapped
+            for mapped in (0..page).step_by(PAGE_SIZE) {
+                unmap_page(virt + mapped).unwrap_or_default();
+            }
+            return Err(e);
+        }
+    }
+    Ok((virt + offset) as *mut u8)
+}
+
+/// Give a physical memory region to the frame allocator.
+///
+/// The region is trimmed to whole frames.
+/// * Return: could be added or not (too many regions, or smaller than a frame).
+///
+/// # Safety
+///
+/// The region must be usable RAM, mapped by the bootloader and otherwise unused (not given to the heap).
+pub unsafe fn add_frames(phys: u64, size: u64) -> bool {
+    let start = phys.next_multiple_of(PAGE_SIZE as u64);
+    let end = (phys + size) / PAGE_SIZE as u64 * PAGE_SIZE as u64;
+    if start >= end {
+        return false;
+    }
+    FRAMES.acquire().add_region(start, end)
+}
+
+/// Allocate a physical frame, filled with zeros.
+/// * Return: physical address of the frame.
+pub fn alloc_frame() -> Option<u64> {
+    let frame = FRAMES.acquire().pop();
+    let frame = match frame {
+        Some(frame) => frame,
+        None => {
+            if FAILED_FRAME_ALLOCS.fetch_add(1, Ordering::Relaxed) == 0 {
+                log::warn!("Out of physical frames, the frame allocator needs more memory");
+            }
+            return None;
+        }
+    };
+    unsafe {
+        ptr::write_bytes(phys_to_virt(frame), 0, PAGE_SIZE);
+    }
+    Some(frame)
+}
+
+/// Give back a physical frame to the frame allocator.
+///
+/// # Safety
+///
+/// The frame must come from [`alloc_frame`], and must not be used or mapped anymore.
+pub unsafe fn free_frame(phys: u64) {
+    FRAMES.acquire().push(phys);
+}
+
+/// Number of physical frames available.
+pub fn free_frames() -> usize {
+    FRAMES.acquire().available()
+}
+
+/// Number of frame allocations that failed because there were no frames left.
+pub fn failed_frame_allocs() -> usize {
+    FAILED_FRAME_ALLOCS.load(Ordering::Relaxed)
+}
+
+/// Forget all the frames and regions, to test the frame allocator from scratch.
+#[cfg(feature = "test")]
+pub fn reset_frames() {
+    *FRAMES.acquire() = FramePool::new();
+    FAILED_FRAME_ALLOCS.store(0, Ordering::Relaxed);
+}
+
+/// Frame allocator.
+///
+/// New frames are taken from the regions in order, freed ones are pushed to a list.
+struct FramePool {
+    /// Regions: start and end physical addresses.
+    regions: [(u64, u64); MAX_FRAME_REGIONS],
+    num_regions: usize,
+    /// Region where the next new frame is taken from, and its address.
+    region_index: usize,
+    next_frame: u64,
+    /// First freed frame, each one stores the address of the next.
+    free_list: u64,
+    num_free: usize
+}
+
+impl FramePool {
+    const fn new() -> Self {
+        Self {
+            regions: [(0, 0); MAX_FRAME_REGIONS],
+            num_regions: 0,
+            region_index: 0,
+            next_frame: 0,
+            free_list: NO_FRAME,
+            num_free: 0
+        }
+    }
+
+    fn add_region(&mut self, start: u64, end: u64) -> bool {
+        if self.num_regions == MAX_FRAME_REGIONS {
+            return false;
+        }
+        self.regions[self.num_regions] = (start, end);
+        if self.num_regions == 0 {
+            self.next_frame = start;
+        }
+        self.num_regions += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u64> {
+        if self.free_list != NO_FRAME {
+            let frame = self.free_list;
+            self.free_list = unsafe { *(phys_to_virt(frame) as *const u64) };
+            self.num_free -= 1;
+            return Some(frame);
+        }
+        while self.region_index < self.num_regions {
+            let (_, end) = self.regions[self.region_index];
+            if self.next_frame < end {
+                let frame = self.next_frame;
+                self.next_frame += PAGE_SIZE as u64;
+                return Some(frame);
+            }
+            self.region_index += 1;
+            if let Some((start, _)) = self.regions.get(self.region_index) {
+                self.next_frame = *start;
+            }
+        }
+        None
+    }
+
+    fn push(&mut self, frame: u64) {
+        unsafe {
+            *(phys_to_virt(frame) as *mut u64) = self.free_list;
+        }
+        self.free_list = frame;
+        self.num_free += 1;
+    }
+
+    fn available(&self) -> usize {
+        let mut available = self.num_free;
+        if self.region_index < self.num_regions {
+            available += ((self.regions[self.region_index].1 - self.next_frame) / PAGE_SIZE as u64) as usize;
+            for (start, end) in &self.regions[self.region_index + 1..self.num_regions] {
+                available += ((end - start) / PAGE_SIZE as u64) as usize;
+            }
+        }
+        available
+    }
+}
+
+/// End of the free frames list.
+const NO_FRAME: u64 = u64::MAX;
+
+/// Offset where the bootloader maps the physical memory.
+static PHYS_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes page table changes.
+static PAGE_TABLES: KMutex<()> = KMutex::new(());
+
+static FRAMES: KMutex<FramePool> = KMutex::new(FramePool::new());
+
+static FAILED_FRAME_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+/// Virtual window for pages mapped by this module: next free address and end.
+static VIRT_WINDOW: KMutex<(usize, usize)> = KMutex::new((0, 0));
\ No newline at end of file
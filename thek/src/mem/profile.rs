@@ -0,0 +1,263 @@
+//! Allocation profiling, enabled with the `alloc-profile` feature.
+//!
+//! Allocations are counted by size class, the power of two that fits the size (never smaller than `arch::ALIGN`). For each class we keep the number of allocations and the peak of allocations alive at the same time.
+//!
+//! From the peaks we can recommend a memory schema: up to `MAX_SCHEMA_BLOCKS` segment sizes chosen to minimize the memory needed to serve all peaks, and the % of memory each block should get.
+//! The peaks of different classes may not happen at the same time, so the recommendation is on the safe side. Profile a run that is representative of the real workload.
+//!
+//! All functions do nothing when the feature is disabled.
+
+use core::{
+    fmt::{
+        Error, Write
+    },
+    sync::atomic::{
+        AtomicUsize, Ordering
+    }
+};
+
+use crate::controllers::port::PortController;
+
+use super::{
+    arch::ALIGN,
+    layout::MAX_SCHEMA_BLOCKS
+};
+
+/// Allocation profiling enabled.
+pub const ALLOC_PROFILE: bool = cfg!(feature = "alloc-profile");
+
+/// Number of size classes, one for each power of two.
+pub const NUM_SIZE_CLASSES: usize = usize::BITS as usize;
+
+/// Profile of a size class.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SizeClassProfile {
+    /// Biggest allocation size in the class, the segment size that fits all of them.
+    pub segment_size: usize,
+    /// Number of allocations.
+    pub allocs: usize,
+    /// Maximum number of allocations alive at the same time.
+    pub peak_live: usize
+}
+
+/// Snapshot of the allocation profile.
+pub struct AllocProfile {
+    classes: [SizeClassProfile; NUM_SIZE_CLASSES]
+}
+
+impl AllocProfile {
+    /// Size classes with at least one allocation, from smaller to bigger.
+    pub fn classes(&self) -> impl Iterator<Item = &SizeClassProfile> {
+        self.classes.iter().filter(|class| class.allocs > 0)
+    }
+
+    /// Recommend a memory schema for the allocations profiled.
+    ///
+    /// Each block serves a range of consecutive size classes, with the segment size of the biggest one, and the memory needed is the sum of the peaks of the range times the segment size.
+    /// The segment sizes that minimize the total memory needed are chosen, and each block gets the % of memory proportional to what it needs.
+    /// * Return: the schema, or `None` if there are no allocations.
+    pub fn recommend_schema(&self) -> Option<Schema> {
+        let mut classes = [SizeClassProfile::default(); NUM_SIZE_CLASSES];
+        let mut num_classes = 0;
+        for class in self.classes().filter(|class| class.peak_live > 0) {
+            classes[num_classes] = *class;
+            num_classes += 1;
+        }
+        if num_classes == 0 {
+            return None;
+        }
+        let classes = &classes[..num_classes];
+        let num_blocks = num_classes.min(MAX_SCHEMA_BLOCKS);
+
+        // Memory needed to serve classes `first..=last` with one block
+        let block_mem = |first: usize, last: usize| -> u128 {
+            let peak: usize = classes[first..=last].iter().map(|class| class.peak_live).sum();
+            peak as u128 * classes[last].segment_size as u128
+        };
+
+        // best[k][j]: minimum memory to serve classes `0..=j` with `k + 1` blocks, and first class of the last block
+        let mut best = [[(u128::MAX, 0); NUM_SIZE_CLASSES]; MAX_SCHEMA_BLOCKS];
+        for (j, best) in best[0][..num_classes].iter_mut().enumerate() {
+            *best = (block_mem(0, j), 0);
+        }
+        for k in 1..num_blocks {
+            for j in k..num_classes {
+                for first in k..=j {
+                    let mem = best[k - 1][first - 1].0.saturating_add(block_mem(first, j));
+                    if mem < best[k][j].0 {
+                        best[k][j] = (mem, first);
+                    }
+                }
+            }
+        }
+
+        // Walk back the best split, from the last block
+        let mut ranges = [(0, 0); MAX_SCHEMA_BLOCKS];
+        let mut last = num_classes - 1;
+        for k in (0..num_blocks).rev() {
+            let first = best[k][last].1;
+            ranges[k] = (first, last);
+            if first > 0 {
+                last = first - 1;
+            }
+        }
+
+        let mut schema = Schema::default();
+        let mut mem = [0; MAX_SCHEMA_BLOCKS];
+        for (k, (first, last)) in ranges[..num_blocks].iter().copied().enumerate() {
+            mem[k] = block_mem(first, last);
+            schema.blocks[k] = (classes[last].segment_size, 0);
+        }
+        schema.len = num_blocks;
+        for (block, percentage) in schema.blocks.iter_mut().zip(percentages(&mem[..num_blocks])) {
+            block.1 = percentage;
+        }
+        Some(schema)
+    }
+}
+
+/// Memory schema, as accepted by `init::setup_mem`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schema {
+    blocks: [(usize, u8); MAX_SCHEMA_BLOCKS],
+    len: usize
+}
+
+impl Schema {
+    /// Blocks of the schema: segment size and % of memory.
+    pub fn as_slice(&self) -> &[(usize, u8)] {
+        &self.blocks[..self.len]
+    }
+}
+
+/// Account a new allocation.
+pub(super) fn on_alloc(size: usize) {
+    if !ALLOC_PROFILE {
+        return;
+    }
+    let class = &CLASSES[size_class(size)];
+    class.allocs.fetch_add(1, Ordering::Relaxed);
+    let live = class.live.fetch_add(1, Ordering::Relaxed) + 1;
+    class.peak_live.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Account a freed allocation.
+pub(super) fn on_free(size: usize) {
+    if !ALLOC_PROFILE {
+        return;
+    }
+    // Allocations done before profiling was reset are not counted
+    CLASSES[size_class(size)].live.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| live.checked_sub(1)).unwrap_or_default();
+}
+
+/// Forget the profile, the memory schema has been set up again.
+pub(super) fn reset() {
+    for class in CLASSES.iter() {
+        class.allocs.store(0, Ordering::Relaxed);
+        class.live.store(0, Ordering::Relaxed);
+        class.peak_live.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Get a snapshot of the allocation profile.
+pub fn profile() -> AllocProfile {
+    let mut profile = AllocProfile {
+        classes: [SizeClassProfile::default(); NUM_SIZE_CLASSES]
+    };
+    for (index, (snapshot, class)) in profile.classes.iter_mut().zip(CLASSES.iter()).enumerate() {
+        *snapshot = SizeClassProfile {
+            segment_size: class_segment_size(index),
+            allocs: class.allocs.load(Ordering::Relaxed),
+            peak_live: class.peak_live.load(Ordering::Relaxed)
+        };
+    }
+    profile
+}
+
+/// Write the allocation size histogram and the recommended memory schema.
+pub fn write_profile_report(out: &mut dyn Write) -> Result<(), Error> {
+    let profile = profile();
+    writeln!(out, "Allocation profile:")?;
+    for class in profile.classes() {
+        writeln!(out, "  size <= {}: {} allocs, peak {} live", class.segment_size, class.allocs, class.peak_live)?;
+    }
+    match profile.recommend_schema() {
+        Some(schema) => {
+            writeln!(out, "Recommended schema:")?;
+            writeln!(out, "  setup_mem(&{:?});", schema.as_slice())?;
+            // Same schema, in the format of the file read with the `schema-file` feature
+            writeln!(out, "Schema file:")?;
+            for (size, percent) in schema.as_slice() {
+                if *size == usize::MAX {
+                    writeln!(out, "  MAX, {}", percent)?;
+                }
+                else {
+                    writeln!(out, "  {}, {}", size, percent)?;
+                }
+            }
+            Ok(())
+        },
+        None => writeln!(out, "No allocations recorded")
+    }
+}
+
+/// Write the profile report to the serial port `SER1`.
+pub fn profile_report() {
+    write_profile_report(&mut PortController::default()).unwrap_or_default();
+}
+
+/// Split 100% proportionally to the memory needed by each block, every block gets at least 1%.
+fn percentages(mem: &[u128]) -> [u8; MAX_SCHEMA_BLOCKS] {
+    let total: u128 = mem.iter().sum::<u128>().max(1);
+    let mut percentages = [0u8; MAX_SCHEMA_BLOCKS];
+    let mut remainders = [0u128; MAX_SCHEMA_BLOCKS];
+    for (k, mem) in mem.iter().enumerate() {
+        percentages[k] = ((mem * 100 / total) as u8).max(1);
+        remainders[k] = mem * 100 % total;
+    }
+    let len = mem.len();
+    let mut sum: usize = percentages[..len].iter().map(|p| *p as usize).sum();
+    // Give the missing points to the biggest remainders, or take the extra ones from the biggest blocks
+    while sum < 100 {
+        let k = (0..len).max_by_key(|k| remainders[*k]).unwrap_or_default();
+        percentages[k] += 1;
+        remainders[k] = 0;
+        sum += 1;
+    }
+    while sum > 100 {
+        let k = (0..len).max_by_key(|k| percentages[*k]).unwrap_or_default();
+        percentages[k] -= 1;
+        sum -= 1;
+    }
+    percentages
+}
+
+/// Size class of an allocation.
+fn size_class(size: usize) -> usize {
+    let class = size.checked_next_power_of_two().map(|size| size.trailing_zeros()).unwrap_or(usize::BITS - 1);
+    (class as usize).max(ALIGN.trailing_zeros() as usize)
+}
+
+/// Segment size that fits all the allocations of a class.
+fn class_segment_size(class: usize) -> usize {
+    1 << class
+}
+
+/// Allocation counters of a size class.
+struct SizeClass {
+    allocs: AtomicUsize,
+    live: AtomicUsize,
+    peak_live: AtomicUsize
+}
+
+impl SizeClass {
+    const fn new() -> Self {
+        Self {
+            allocs: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            peak_live: AtomicUsize::new(0)
+        }
+    }
+}
+
+static CLASSES: [SizeClass; NUM_SIZE_CLASSES] = [const { SizeClass::new() }; NUM_SIZE_CLASSES];
\ No newline at end of file
@@ -0,0 +1,121 @@
+//! Memory statistics.
+
+use core::sync::atomic::Ordering;
+
+use super::{
+    globalloc::with_block_set,
+    layout::MAX_NUM_BLOCKS
+};
+
+/// Statistics of a memory block.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BlockStats {
+    /// Segment size in bytes.
+    pub segment_size: usize,
+    /// Total number of segments in the block.
+    pub total_segments: usize,
+    /// Number of segments currently in use.
+    pub used_segments: usize,
+    /// Maximum number of segments in use at the same time.
+    pub peak_segments: usize,
+    /// Bytes requested by the allocations currently in the block.
+    pub requested_bytes: usize,
+    /// Bytes reserved by the allocations currently in the block, that is, used segments times segment size.
+    pub reserved_bytes: usize,
+    /// Number of allocations served by this block because the smaller buckets were full.
+    pub spilled_allocs: usize
+}
+
+impl BlockStats {
+    /// Total payload size of the block in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.total_segments * self.segment_size
+    }
+
+    /// Bytes reserved but not requested (internal fragmentation).
+    pub fn wasted_bytes(&self) -> usize {
+        // Counters of a concurrent free may be seen half updated, with the segment already released
+        self.reserved_bytes.saturating_sub(self.requested_bytes)
+    }
+}
+
+/// Statistics of the memory allocator.
+#[derive(Copy, Clone, Debug)]
+pub struct MemStats {
+    blocks: [BlockStats; MAX_NUM_BLOCKS],
+    num_blocks: usize,
+    /// Maximum number of segments in use at the same time, in all blocks.
+    pub peak_segments: usize,
+    /// Number of allocations that failed.
+    pub failed_allocs: usize
+}
+
+impl MemStats {
+    /// Statistics of each block, ordered like the blocks in the memory schema.
+    pub fn blocks(&self) -> &[BlockStats] {
+        &self.blocks[..self.num_blocks]
+    }
+
+    /// Total number of segments.
+    pub fn total_segments(&self) -> usize {
+        self.blocks().iter().map(|b| b.total_segments).sum()
+    }
+
+    /// Number of segments currently in use.
+    pub fn used_segments(&self) -> usize {
+        self.blocks().iter().map(|b| b.used_segments).sum()
+    }
+
+    /// Total payload size in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.blocks().iter().map(|b| b.total_bytes()).sum()
+    }
+
+    /// Bytes requested by the current allocations.
+    pub fn requested_bytes(&self) -> usize {
+        self.blocks().iter().map(|b| b.requested_bytes).sum()
+    }
+
+    /// Bytes reserved by the current allocations.
+    pub fn reserved_bytes(&self) -> usize {
+        self.blocks().iter().map(|b| b.reserved_bytes).sum()
+    }
+
+    /// Bytes reserved but not requested (internal fragmentation).
+    pub fn wasted_bytes(&self) -> usize {
+        self.reserved_bytes().saturating_sub(self.requested_bytes())
+    }
+
+    /// Number of allocations served by a bigger bucket because the one that fits was full.
+    pub fn spilled_allocs(&self) -> usize {
+        self.blocks().iter().map(|b| b.spilled_allocs).sum()
+    }
+}
+
+/// Get a snapshot of the memory allocator statistics.
+///
+/// Counters are read one by one without stopping the allocator, so they may be slightly inconsistent if allocations happen meanwhile.
+pub fn stats() -> MemStats {
+    with_block_set(|block_set, peak_segments, failed_allocs| {
+        let blocks = block_set.map(|block_set| &block_set.block_layouts[..block_set.len()]).unwrap_or_default();
+        let mut stats = MemStats {
+            blocks: [BlockStats::default(); MAX_NUM_BLOCKS],
+            num_blocks: blocks.len(),
+            peak_segments,
+            failed_allocs
+        };
+        for (stats, block) in stats.blocks.iter_mut().zip(blocks) {
+            let used_segments = block.used_segments.load(Ordering::Relaxed);
+            *stats = BlockStats {
+                segment_size: block.segment_size,
+                total_segments: block.num_segments,
+                used_segments,
+                peak_segments: block.peak_segments.load(Ordering::Relaxed),
+                requested_bytes: block.requested_bytes.load(Ordering::Relaxed),
+                reserved_bytes: used_segments * block.segment_size,
+                spilled_allocs: block.spilled_allocs.load(Ordering::Relaxed)
+            };
+        }
+        stats
+    })
+}
\ No newline at end of file
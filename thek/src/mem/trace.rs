@@ -0,0 +1,255 @@
+//! Allocation tracing, enabled with the `alloc-trace` feature.
+//!
+//! Every live allocation is recorded with its size, the segment size of the bucket that serves it, the task that allocated it and the return addresses of its callers (see `arch::backtrace`).
+//! Records are kept in a fixed table of [`MAX_TRACED_ALLOCS`] entries, so tracing never allocates memory. Allocations that don't fit in the table are not traced, only counted.
+//!
+//! A leak report lists the live allocations grouped by call site, of all tasks or of a single one (see [`task_leak_report`]). Reports can be dumped at any moment, tasks don't exit yet so there is no automatic report.
+//! Caller addresses can be resolved with `addr2line -e <kernel binary>`, the first ones usually belong to the allocator itself.
+//!
+//! All functions do nothing when the feature is disabled.
+
+use core::{
+    fmt::{
+        Error, Write
+    },
+    sync::atomic::{
+        AtomicUsize, Ordering
+    }
+};
+
+use crate::{
+    controllers::port::PortController,
+    task::current_task
+};
+
+use super::arch::backtrace;
+
+/// Allocation tracing enabled.
+pub const ALLOC_TRACE: bool = cfg!(feature = "alloc-trace");
+
+/// Maximum number of live allocations traced.
+pub const MAX_TRACED_ALLOCS: usize = 4096;
+
+/// Number of caller addresses recorded for each allocation.
+pub const TRACE_DEPTH: usize = 6;
+
+/// Live allocation record.
+#[derive(Copy, Clone, Debug)]
+pub struct AllocTrace {
+    /// Buffer address.
+    pub ptr: *mut u8,
+    /// Bytes requested.
+    pub size: usize,
+    /// Segment size of the bucket that serves the allocation.
+    pub segment_size: usize,
+    /// Task that allocated the buffer, `None` if allocated before the first task switch.
+    pub task: Option<usize>,
+    /// Return addresses of the callers, from the innermost. Unused entries are zero.
+    pub callers: [usize; TRACE_DEPTH]
+}
+
+/// Record a new allocation.
+pub(super) fn on_alloc(ptr: *mut u8, size: usize, segment_size: usize) {
+    if !ALLOC_TRACE {
+        return;
+    }
+    let mut callers = [0; TRACE_DEPTH];
+    backtrace(&mut callers);
+    let start = slot_index(ptr);
+    for i in 0..SLOTS.len() {
+        let slot = &SLOTS[(start + i) % MAX_TRACED_ALLOCS];
+        let current = slot.ptr.load(Ordering::Relaxed);
+        if (current == EMPTY || current == TOMBSTONE)
+            && slot.ptr.compare_exchange(current, ptr as usize, Ordering::Acquire, Ordering::Relaxed).is_ok()
+        {
+            slot.size.store(size, Ordering::Relaxed);
+            slot.segment_size.store(segment_size, Ordering::Relaxed);
+            slot.task.store(current_task().unwrap_or(NO_TASK), Ordering::Relaxed);
+            for (caller, addr) in slot.callers.iter().zip(callers) {
+                caller.store(addr, Ordering::Relaxed);
+            }
+            return;
+        }
+    }
+    UNTRACED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Remove the record of an allocation. Must be called before the segment is released, it could be allocated again.
+pub(super) fn on_free(ptr: *mut u8) {
+    if let Some(slot) = find_slot(ptr) {
+        slot.ptr.store(TOMBSTONE, Ordering::Release);
+    }
+}
+
+/// Update the size of an allocation resized in place.
+pub(super) fn on_resize(ptr: *mut u8, new_size: usize) {
+    if let Some(slot) = find_slot(ptr) {
+        slot.size.store(new_size, Ordering::Relaxed);
+    }
+}
+
+/// Forget all records, the memory schema has been set up again.
+pub(super) fn reset() {
+    for slot in SLOTS.iter() {
+        slot.ptr.store(EMPTY, Ordering::Relaxed);
+    }
+    UNTRACED.store(0, Ordering::Relaxed);
+}
+
+/// Number of allocations not traced because the table was full.
+pub fn untraced_allocs() -> usize {
+    UNTRACED.load(Ordering::Relaxed)
+}
+
+/// Run `func` for each live allocation traced.
+///
+/// Records are read without stopping the allocator, so allocations that happen meanwhile may be missed or partially read.
+pub fn for_each_alloc(mut func: impl FnMut(&AllocTrace)) {
+    for index in 0..SLOTS.len() {
+        if let Some(trace) = trace_at(index) {
+            func(&trace);
+        }
+    }
+}
+
+/// Write a report of the live allocations that pass the `filter`, grouped by call site and task.
+///
+/// It doesn't allocate memory, so it can be used when the heap is exhausted, but grouping takes quadratic time in the number of allocations.
+pub fn write_leak_report(out: &mut dyn Write, filter: impl Fn(&AllocTrace) -> bool) -> Result<(), Error> {
+    let (mut count, mut bytes, mut sites) = (0, 0, 0);
+    for_each_alloc(|trace| {
+        if filter(trace) {
+            count += 1;
+            bytes += trace.size;
+        }
+    });
+    writeln!(out, "Leak report: {} live allocations, {} bytes", count, bytes)?;
+    for index in 0..SLOTS.len() {
+        let first = match trace_at(index) {
+            Some(trace) if filter(&trace) => trace,
+            _ => continue
+        };
+        let same_site = |trace: &AllocTrace| filter(trace) && trace.callers == first.callers && trace.task == first.task;
+        // Only the first allocation of each site reports the group
+        if (0..index).filter_map(trace_at).any(|trace| same_site(&trace)) {
+            continue;
+        }
+        let (mut count, mut bytes, mut min_size, mut max_size) = (0, 0, usize::MAX, 0);
+        for trace in (index..SLOTS.len()).filter_map(trace_at).filter(same_site) {
+            count += 1;
+            bytes += trace.size;
+            min_size = min_size.min(trace.size);
+            max_size = max_size.max(trace.size);
+        }
+        sites += 1;
+        write!(out, "  {} allocs, {} bytes, sizes {}..{}, ", count, bytes, min_size, max_size)?;
+        match first.task {
+            Some(task) => write!(out, "task {}, at", task)?,
+            None => write!(out, "no task, at")?
+        }
+        for addr in first.callers.iter().take_while(|addr| **addr != 0) {
+            write!(out, " {:#x}", addr)?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "{} call sites, {} allocations not traced", sites, untraced_allocs())
+}
+
+/// Write a report of all live allocations to the serial port `SER1`.
+pub fn leak_report() {
+    write_leak_report(&mut PortController::default(), |_| true).unwrap_or_default();
+}
+
+/// Write a report of the live allocations of a task to the serial port `SER1`.
+pub fn task_leak_report(task: usize) {
+    write_leak_report(&mut PortController::default(), |trace| trace.task == Some(task)).unwrap_or_default();
+}
+
+/// Size of the record table, no space is used when the feature is disabled.
+const TABLE_SIZE: usize = if ALLOC_TRACE { MAX_TRACED_ALLOCS } else { 0 };
+
+/// Free slot that has never been used.
+const EMPTY: usize = 0;
+
+/// Slot of a freed allocation. Segments are aligned, so it's never a buffer address.
+const TOMBSTONE: usize = 1;
+
+/// Task value of allocations done before the first task switch.
+const NO_TASK: usize = usize::MAX;
+
+/// Record slot. The address is claimed atomically, the other fields are owned by the allocation.
+struct TraceSlot {
+    ptr: AtomicUsize,
+    size: AtomicUsize,
+    segment_size: AtomicUsize,
+    task: AtomicUsize,
+    callers: [AtomicUsize; TRACE_DEPTH]
+}
+
+impl TraceSlot {
+    const fn new() -> Self {
+        Self {
+            ptr: AtomicUsize::new(EMPTY),
+            size: AtomicUsize::new(0),
+            segment_size: AtomicUsize::new(0),
+            task: AtomicUsize::new(NO_TASK),
+            callers: [const { AtomicUsize::new(0) }; TRACE_DEPTH]
+        }
+    }
+}
+
+/// Hash table of records, with linear probing.
+///
+/// Freed slots are marked with a tombstone and reused by new allocations. Slots never become empty again, so a search can stop at the first empty slot.
+static SLOTS: [TraceSlot; TABLE_SIZE] = [const { TraceSlot::new() }; TABLE_SIZE];
+
+/// Number of allocations not traced because the table was full.
+static UNTRACED: AtomicUsize = AtomicUsize::new(0);
+
+/// First slot to probe for an address.
+fn slot_index(ptr: *mut u8) -> usize {
+    // Fibonacci hashing, the lower bits of the addresses are always zero
+    let hash = ((ptr as u64) >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+    // Not the table size, that is zero when the feature is disabled (but then no slot is ever probed)
+    hash as usize % MAX_TRACED_ALLOCS
+}
+
+fn find_slot(ptr: *mut u8) -> Option<&'static TraceSlot> {
+    if !ALLOC_TRACE {
+        return None;
+    }
+    let start = slot_index(ptr);
+    for i in 0..SLOTS.len() {
+        let slot = &SLOTS[(start + i) % MAX_TRACED_ALLOCS];
+        match slot.ptr.load(Ordering::Acquire) {
+            EMPTY => return None,
+            addr if addr == ptr as usize => return Some(slot),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn trace_at(index: usize) -> Option<AllocTrace> {
+    let slot = &SLOTS[index];
+    let ptr = slot.ptr.load(Ordering::Acquire);
+    if ptr == EMPTY || ptr == TOMBSTONE {
+        return None;
+    }
+    let mut callers = [0; TRACE_DEPTH];
+    for (addr, caller) in callers.iter_mut().zip(slot.callers.iter()) {
+        *addr = caller.load(Ordering::Relaxed);
+    }
+    Some(
+        AllocTrace {
+            ptr: ptr as *mut u8,
+            size: slot.size.load(Ordering::Relaxed),
+            segment_size: slot.segment_size.load(Ordering::Relaxed),
+            task: match slot.task.load(Ordering::Relaxed) {
+                NO_TASK => None,
+                task => Some(task)
+            },
+            callers
+        }
+    )
+}
\ No newline at end of file
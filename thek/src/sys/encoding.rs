@@ -0,0 +1,143 @@
+//! Character encodings.
+
+use core::sync::atomic::{
+    AtomicU8, Ordering
+};
+
+/// Replacement for code points that can't be represented in an 8 bit encoding.
+pub const REPLACEMENT_BYTE: u8 = b'?';
+
+/// Character encoding.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Encoding {
+    /// 7 bit ASCII.
+    Ascii = 0,
+    /// ISO-8859-1, the first 256 Unicode code points.
+    Latin1,
+    /// IBM PC code page 437, used by VGA text mode fonts.
+    Cp437,
+    /// UTF-8, the `str` encoding.
+    Utf8
+}
+
+impl Encoding {
+    /// Encode a char into a single byte, if it can be represented by the encoding.
+    ///
+    /// For UTF-8 only ASCII chars can be encoded in a single byte.
+    pub fn encode_byte(&self, ch: char) -> Option<u8> {
+        let code = ch as u32;
+        match self {
+            Encoding::Ascii | Encoding::Utf8 => {
+                if code < 0x80 { Some(code as u8) } else { None }
+            },
+            Encoding::Latin1 => {
+                if code <= 0xFF { Some(code as u8) } else { None }
+            },
+            Encoding::Cp437 => {
+                if code < 0x80 {
+                    Some(code as u8)
+                }
+                else if let Some(index) = CP437_HIGH.iter().position(|&c| c == ch) {
+                    Some(0x80 + index as u8)
+                }
+                else if let Some(index) = CP437_LOW.iter().position(|&c| c == ch) {
+                    Some(index as u8)
+                }
+                else if ch == CP437_DEL {
+                    Some(0x7F)
+                }
+                else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Encode a char into `buf`, returning the encoded bytes.
+    ///
+    /// Code points that can't be represented are replaced by [`REPLACEMENT_BYTE`] (or `U+FFFD` in UTF-8, that can represent everything).
+    pub fn encode<'a>(&self, ch: char, buf: &'a mut [u8; 4]) -> &'a [u8] {
+        match self {
+            Encoding::Utf8 => {
+                ch.encode_utf8(buf).as_bytes()
+            },
+            _ => {
+                buf[0] = self.encode_byte(ch).unwrap_or(REPLACEMENT_BYTE);
+                &buf[..1]
+            }
+        }
+    }
+
+    /// Decode a single byte into a char.
+    ///
+    /// For UTF-8, bytes that are not ASCII are decoded as `U+FFFD`.
+    pub fn decode_byte(&self, b: u8) -> char {
+        match self {
+            Encoding::Ascii | Encoding::Utf8 => {
+                if b < 0x80 { b as char } else { char::REPLACEMENT_CHARACTER }
+            },
+            Encoding::Latin1 => {
+                b as char
+            },
+            Encoding::Cp437 => {
+                if b >= 0x80 {
+                    CP437_HIGH[(b - 0x80) as usize]
+                }
+                else if b == 0x7F {
+                    CP437_DEL
+                }
+                else if b > 0 && b < 0x20 {
+                    CP437_LOW[b as usize]
+                }
+                else {
+                    b as char
+                }
+            }
+        }
+    }
+}
+
+impl From<u8> for Encoding {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Encoding::Ascii,
+            1 => Encoding::Latin1,
+            2 => Encoding::Cp437,
+            _ => Encoding::Utf8
+        }
+    }
+}
+
+/// Get the encoding used by default in port devices.
+pub fn port_encoding() -> Encoding {
+    Encoding::from(PORT_ENCODING.load(Ordering::Relaxed))
+}
+
+/// Set the encoding used by default in port devices.
+pub fn set_port_encoding(encoding: Encoding) {
+    PORT_ENCODING.store(encoding as u8, Ordering::Relaxed);
+}
+
+static PORT_ENCODING: AtomicU8 = AtomicU8::new(Encoding::Utf8 as u8);
+
+/// CP437 glyphs for control codes 0x00 to 0x1F. Position 0 is never used, 0x00 is always NUL.
+const CP437_LOW: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼'
+];
+
+/// CP437 glyph for 0x7F.
+const CP437_DEL: char = '⌂';
+
+/// CP437 chars from 0x80 to 0xFF.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}'
+];
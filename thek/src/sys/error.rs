@@ -5,6 +5,18 @@ pub enum KError {
     OutBounds,
     /// Segment stack is full
     FullSegStack,
+    /// Segment is already free
+    DoubleFree,
+    /// Operation not supported by the device
+    Unsupported,
+    /// Page is already mapped
+    AlreadyMapped,
+    /// Page is not mapped
+    NotMapped,
+    /// No physical frames available
+    OutOfFrames,
+    /// All task stack slots are in use
+    NoStackSlot,
     /// Not classified error
     Other
 }
@@ -14,6 +26,12 @@ impl KError {
         match self {
             KError::OutBounds => "Index out of bounds",
             KError::FullSegStack => "Segment stack is full",
+            KError::DoubleFree => "Segment is already free",
+            KError::Unsupported => "Operation not supported",
+            KError::AlreadyMapped => "Page already mapped",
+            KError::NotMapped => "Page not mapped",
+            KError::OutOfFrames => "No physical frames available",
+            KError::NoStackSlot => "No free stack slot",
             KError::Other => "Generic error",
         }
     }
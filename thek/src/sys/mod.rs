@@ -6,3 +6,6 @@ pub use self::mutex::*;
 mod error;
 pub use self::error::*;
 
+mod encoding;
+pub use self::encoding::*;
+
@@ -35,6 +35,16 @@ impl<T> KMutex<T> {
         KLock::new(self)
     }
 
+    /// Try to acquire a lock without waiting.
+    ///
+    /// Returns `None` if the mutex is locked.
+    pub fn try_acquire(&self) -> Option<KLock<'_, T>> {
+        let current = self.current_num.load(Ordering::SeqCst);
+        // Take the next turn only if it's the current one, that is, nobody holds or waits for the lock
+        self.queue_num.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst).ok()?;
+        Some(KLock::new(self))
+    }
+
     /// Release a lock
     fn release(&self) {
         // Only unlock if we are currently locked
@@ -4,4 +4,7 @@ mod scheduler;
 pub use scheduler::*;
 
 mod time;
-pub use time::*;
\ No newline at end of file
+pub use time::*;
+
+mod stack;
+pub use stack::*;
\ No newline at end of file
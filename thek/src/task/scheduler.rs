@@ -3,11 +3,15 @@ use alloc::vec::Vec;
 use crate::{
     cpu::{
         arch::{
-            StackFrame, set_timer_handler
+            StackFrame, set_page_fault_handler, set_timer_handler
         }
     },
-    mem::KBox,
-    sys::KMutex
+    sys::{
+        KMutex, KError
+    }
+};
+use super::{
+    TaskStack, stack_guard_owner
 };
 
 pub struct Task {
@@ -15,13 +19,13 @@ pub struct Task {
     name: [u8; 15],
     name_len: u8,
     pub func: fn(),
-    pub stack: KBox
+    pub stack: TaskStack
 }
 
 impl Task {
-    pub fn new(name_str: &str, stack_size: usize, func: fn()) -> Result<Self, ()> {
+    pub fn new(name_str: &str, stack_size: usize, func: fn()) -> Result<Self, KError> {
         if name_str.bytes().len() > 15 {
-            return Err(());
+            return Err(KError::OutBounds);
         }
         let mut name: [u8; 15] = [0; 15];
         let mut name_len: u8 = 0;
@@ -34,7 +38,7 @@ impl Task {
                 name,
                 name_len,
                 func,
-                stack: KBox::new(stack_size)?,
+                stack: TaskStack::new(name_str, stack_size)?,
             }
         )
     }
@@ -53,6 +57,13 @@ impl Task {
 /// Init tasks module.
 pub fn init_task() {
     set_timer_handler(internal_timer_handler);
+    set_page_fault_handler(internal_page_fault_handler);
+}
+
+fn internal_page_fault_handler(addr: usize) {
+    if let Some(owner) = stack_guard_owner(addr) {
+        panic!("stack overflow in task {}", owner.name_str());
+    }
 }
 
 fn internal_timer_handler(stack_frame: &StackFrame) {
@@ -88,6 +99,14 @@ pub fn disable_scheduling() -> bool {
     TASK_SWITCHING.swap(false, Ordering::SeqCst)
 }
 
+/// Index of the task currently running, `None` before the first task switch.
+pub fn current_task() -> Option<usize> {
+    match TASK_INDEX.load(Ordering::Relaxed) {
+        usize::MAX => None,
+        index => Some(index)
+    }
+}
+
 // Task switching flag.
 static TASK_SWITCHING: AtomicBool = AtomicBool::new(false);
 // Index of current task.
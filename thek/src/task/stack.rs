@@ -0,0 +1,137 @@
+//! Task stacks with guard pages.
+//!
+//! Stacks are not allocated from the heap, they live in a dedicated virtual region divided in slots of `STACK_SLOT_SIZE` bytes. Each stack is mapped at the top of its slot, and the rest of the slot stays unmapped: a stack overflow touches an unmapped page, and the page fault is reported as an overflow of the task that owns the slot.
+
+use crate::{
+    mem::paging::{
+        self, PAGE_SIZE, PageFlags
+    },
+    sys::{
+        KMutex, KError
+    }
+};
+
+/// Maximum number of task stacks.
+pub const MAX_STACKS: usize = 64;
+
+/// Maximum stack size, a slot minus the guard page.
+pub const MAX_STACK_SIZE: usize = STACK_SLOT_SIZE - PAGE_SIZE;
+
+/// Stack of a task, mapped page by page with frames from the frame allocator.
+pub struct TaskStack {
+    slot: usize,
+    size: usize
+}
+
+impl TaskStack {
+    /// Map a stack of at least `size` bytes (rounded up to whole pages) for task `owner`.
+    ///
+    /// Fails with `OutBounds` if the size is 0 or bigger than [`MAX_STACK_SIZE`], `NoStackSlot` if all slots are in use, `OutOfFrames` if the frame allocator is exhausted, or the error of [`paging::map_page`].
+    pub fn new(owner: &str, size: usize) -> Result<Self, KError> {
+        let size = size.checked_next_multiple_of(PAGE_SIZE).ok_or(KError::OutBounds)?;
+        if size == 0 || size > MAX_STACK_SIZE {
+            return Err(KError::OutBounds);
+        }
+        let slot = STACKS.acquire().take(owner)?;
+        // If something fails, dropping the stack unmaps the pages already mapped
+        let stack = Self { slot, size };
+        for page in (stack.bottom() as usize..stack.top() as usize).step_by(PAGE_SIZE) {
+            let frame = paging::alloc_frame().ok_or(KError::OutOfFrames)?;
+            if let Err(err) = unsafe { paging::map_page(page, frame, PageFlags::DATA) } {
+                unsafe { paging::free_frame(frame) };
+                return Err(err);
+            }
+        }
+        Ok(stack)
+    }
+
+    /// Bottom address of the stack, right above the guard page.
+    pub fn bottom(&self) -> *const u8 {
+        (self.top() as usize - self.size) as *const u8
+    }
+
+    /// Top address of the stack.
+    pub fn top(&self) -> *const u8 {
+        (STACKS.acquire().base + (self.slot + 1) * STACK_SLOT_SIZE) as *const u8
+    }
+
+    /// Stack size.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for TaskStack {
+    fn drop(&mut self) {
+        for page in (self.bottom() as usize..self.top() as usize).step_by(PAGE_SIZE) {
+            unsafe {
+                if let Ok(frame) = paging::unmap_page(page) {
+                    paging::free_frame(frame);
+                }
+            }
+        }
+        STACKS.acquire().owners[self.slot] = None;
+    }
+}
+
+/// Name of the task whose stack guard contains `addr`, if any.
+///
+/// Called from the page fault handler, so it doesn't wait for the stack region lock: if it's locked (the fault happened while allocating or freeing a stack), returns `None`.
+pub fn stack_guard_owner(addr: usize) -> Option<StackOwner> {
+    let stacks = STACKS.try_acquire()?;
+    if stacks.base == 0 || addr < stacks.base || addr >= stacks.base + MAX_STACKS * STACK_SLOT_SIZE {
+        return None;
+    }
+    let offset = addr - stacks.base;
+    let owner = stacks.owners[offset / STACK_SLOT_SIZE]?;
+    // Anything in the slot below the mapped stack is guard
+    let mapped = paging::translate(addr - addr % PAGE_SIZE).is_some();
+    (!mapped).then_some(owner)
+}
+
+/// Name of the task that owns a stack.
+#[derive(Copy, Clone)]
+pub struct StackOwner {
+    name: [u8; 15],
+    name_len: u8
+}
+
+impl StackOwner {
+    fn new(name_str: &str) -> Self {
+        let mut name: [u8; 15] = [0; 15];
+        let name_len = name_str.len().min(15);
+        name[..name_len].copy_from_slice(&name_str.as_bytes()[..name_len]);
+        Self {
+            name,
+            name_len: name_len as u8
+        }
+    }
+
+    /// Task name.
+    pub fn name_str(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or_default()
+    }
+}
+
+/// Virtual memory reserved for each stack, including the guard.
+const STACK_SLOT_SIZE: usize = 1024 * 1024;
+
+/// Stack region: start address (0 until the first stack is created) and owner of each slot.
+struct StackRegion {
+    base: usize,
+    owners: [Option<StackOwner>; MAX_STACKS]
+}
+
+impl StackRegion {
+    /// Find a free slot and assign it to `owner`.
+    fn take(&mut self, owner: &str) -> Result<usize, KError> {
+        if self.base == 0 {
+            self.base = paging::reserve_virt(MAX_STACKS * STACK_SLOT_SIZE).ok_or(KError::Other)?;
+        }
+        let slot = self.owners.iter().position(Option::is_none).ok_or(KError::NoStackSlot)?;
+        self.owners[slot] = Some(StackOwner::new(owner));
+        Ok(slot)
+    }
+}
+
+static STACKS: KMutex<StackRegion> = KMutex::new(StackRegion { base: 0, owners: [None; MAX_STACKS] });
\ No newline at end of file
@@ -35,6 +35,11 @@ pub fn sleep(delay_ms: usize) {
     }
 }
 
+/// Number of timer ticks since the system started.
+pub fn ticks() -> u32 {
+    TICKS.load(Ordering::SeqCst)
+}
+
 /// Increment tick counter.
 pub(super) fn tick() {
     TICKS.fetch_add(1, Ordering::SeqCst);
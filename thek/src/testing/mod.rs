@@ -0,0 +1,102 @@
+//! Kernel test runner.
+//!
+//! Runs the `#[test_case]` functions collected by `custom_test_frameworks` inside the kernel, reports the results through the serial port `SER1` and exits QEMU using the `isa-debug-exit` device.
+//!
+//! A crate uses it by setting the runner and calling the generated entry point once the kernel is initialized:
+//!
+//! ```
+//! #![feature(custom_test_frameworks)]
+//! #![test_runner(thek::testing::test_runner)]
+//! #![reexport_test_harness_main = "test_main"]
+//!
+//! #[cfg(test)]
+//! test_main();
+//!
+//! #[test_case]
+//! fn trivial_assertion() {
+//!     assert_eq!(1, 1);
+//! }
+//! ```
+//!
+//! A failed test panics, the panic handler reports it and exits QEMU with [`ExitCode::Failed`]. Running stops at the first failure.
+
+use core::{
+    any::type_name,
+    fmt::{
+        Arguments, Write
+    },
+    panic::PanicInfo,
+    sync::atomic::{
+        AtomicBool, Ordering
+    }
+};
+
+use crate::{
+    cpu,
+    controllers::port::PortController,
+    devices::{
+        self, Device
+    }
+};
+
+/// Code sent to the `isa-debug-exit` device.
+///
+/// QEMU exits with status `(code << 1) | 1`, so `Success` is 33 and `Failed` is 35.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11
+}
+
+/// Something that can be run as a test.
+pub trait Testable {
+    /// Run the test, panic if it fails.
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        test_print(format_args!("{} ... ", type_name::<T>()));
+        self();
+        test_print(format_args!("ok\n"));
+    }
+}
+
+/// Run all tests and exit QEMU.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    RUNNING.store(true, Ordering::SeqCst);
+    test_print(format_args!("\nrunning {} tests\n", tests.len()));
+    for test in tests {
+        test.run();
+    }
+    test_print(format_args!("\ntest result: ok. {} passed\n", tests.len()));
+    exit_qemu(ExitCode::Success);
+}
+
+/// Tests are running.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// Report a failed test and exit QEMU. Called by the panic handler while tests are running.
+pub fn test_panic(info: &PanicInfo) -> ! {
+    cpu::disable_ints();
+    if let Some(Device::Port(port_dev)) = devices::get_port_device("SER1") {
+        // Reset mutex, just in case we panicked while still holding a lock.
+        port_dev.reset();
+    }
+    test_print(format_args!("FAILED\n\nError: {}\n\ntest result: FAILED\n", info));
+    exit_qemu(ExitCode::Failed);
+}
+
+/// Exit QEMU with a test result.
+pub fn exit_qemu(code: ExitCode) -> ! {
+    cpu::exit_vm(code as u32)
+}
+
+fn test_print(args: Arguments) {
+    PortController::default().write_fmt(args).unwrap_or_default();
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
\ No newline at end of file
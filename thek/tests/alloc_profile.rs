@@ -0,0 +1,111 @@
+//! Allocation profiling tests, run on the host with the `test` and `alloc-profile` features (see `run_tests.sh`).
+
+mod common;
+
+use common::*;
+
+use thek::mem::{
+    self,
+    init::check_schema,
+    debug::RED_ZONE_SIZE,
+    layout::MAX_SCHEMA_BLOCKS,
+    profile
+};
+
+fn alloc_many(count: usize, size: usize) -> Vec<*mut u8> {
+    let ptrs: Vec<_> = (0..count).map(|_| alloc(size, 1)).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()), "Could not allocate {} bytes", size);
+    ptrs
+}
+
+fn dealloc_all(ptrs: Vec<*mut u8>, size: usize) {
+    for ptr in ptrs {
+        dealloc(ptr, size, 1);
+    }
+}
+
+#[test]
+fn histogram_counts_peaks() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptrs = alloc_many(10, 100);
+    dealloc_all(ptrs, 100);
+    let ptrs = alloc_many(5, 100);
+    // In place realloc moves the allocation to another class
+    let ptr = realloc(ptrs[0], 100, 1, 200);
+    let profile = profile::profile();
+    let classes: Vec<_> = profile.classes().map(|c| (c.segment_size, c.allocs, c.peak_live)).collect();
+    assert_eq!(classes, [(128, 15, 10), (256, 1, 1)]);
+    dealloc(ptr, 200, 1);
+    dealloc_all(ptrs[1..].to_vec(), 100);
+}
+
+#[test]
+fn segment_allocs_count_requested_size() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    // The allocation takes a 256 bytes segment, but the profile gets what was asked
+    for _ in 0..2 {
+        let (ptr, size) = mem::alloc_segment(100, 1).unwrap();
+        assert_eq!(size, 256 - RED_ZONE_SIZE);
+        unsafe {
+            mem::dealloc_segment(ptr, size, 1, 100);
+        }
+    }
+    let profile = profile::profile();
+    let classes: Vec<_> = profile.classes().map(|c| (c.segment_size, c.allocs, c.peak_live)).collect();
+    assert_eq!(classes, [(128, 2, 1)]);
+}
+
+#[test]
+fn recommends_proportional_schema() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let small = alloc_many(100, 10);
+    let big = alloc_many(10, 1000);
+    dealloc_all(small, 10);
+    dealloc_all(big, 1000);
+    // 100 * 16 bytes and 10 * 1024 bytes
+    let schema = profile::profile().recommend_schema().unwrap();
+    assert_eq!(schema.as_slice(), [(16, 14), (1024, 86)]);
+}
+
+#[test]
+fn merges_classes_into_max_blocks() {
+    let _lock = setup(&[4 * 1024 * 1024], &[(64, 10), (256, 10), (1024, 10), (4096, 60), (usize::MAX, 10)]);
+    let mut ptrs = Vec::new();
+    for shift in 4..=12 {
+        // Leave room for the red zone, so every size fits the segments of its class
+        let size = ((1 << shift) - RED_ZONE_SIZE).max(1);
+        ptrs.push((alloc_many(10, size), size));
+    }
+    for (ptrs, size) in ptrs {
+        dealloc_all(ptrs, size);
+    }
+    let schema = profile::profile().recommend_schema().unwrap();
+    let blocks = schema.as_slice();
+    assert_eq!(blocks.len(), MAX_SCHEMA_BLOCKS);
+    assert_eq!(blocks.last().unwrap().0, 4096);
+    // Big classes need most of the memory, so they keep their own block
+    assert_eq!(blocks[MAX_SCHEMA_BLOCKS - 2].0, 2048);
+    check_schema(blocks);
+}
+
+#[test]
+fn no_allocations_no_schema() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    assert!(profile::profile().recommend_schema().is_none());
+    let mut report = String::new();
+    profile::write_profile_report(&mut report).unwrap();
+    assert_eq!(report, "Allocation profile:\nNo allocations recorded\n");
+}
+
+#[test]
+fn report_has_both_schema_formats() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptrs = alloc_many(4, 64);
+    dealloc_all(ptrs, 64);
+    let mut report = String::new();
+    profile::write_profile_report(&mut report).unwrap();
+    assert_eq!(
+        report,
+        "Allocation profile:\n  size <= 64: 4 allocs, peak 4 live\nRecommended schema:\n  setup_mem(&[(64, 100)]);\nSchema file:\n  64, 100\n"
+    );
+}
\ No newline at end of file
@@ -0,0 +1,100 @@
+//! Allocation tracing tests, run on the host with the `test` and `alloc-trace` features (see `run_tests.sh`).
+
+mod common;
+
+use common::*;
+
+use thek::mem::{
+    self,
+    trace::{
+        self, AllocTrace, MAX_TRACED_ALLOCS
+    }
+};
+
+fn live() -> Vec<AllocTrace> {
+    let mut traces = Vec::new();
+    trace::for_each_alloc(|t| traces.push(*t));
+    traces
+}
+
+#[test]
+fn records_live_allocations() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let a = alloc(10, 1);
+    let b = alloc(500, 1);
+    let c = alloc(100, 1);
+    dealloc(b, 500, 1);
+    let mut traces = live();
+    traces.sort_by_key(|t| t.size);
+    assert_eq!(traces.len(), 2);
+    assert_eq!((traces[0].ptr, traces[0].size, traces[0].segment_size), (a, 10, 64));
+    assert_eq!((traces[1].ptr, traces[1].size, traces[1].segment_size), (c, 100, 256));
+    assert!(traces.iter().all(|t| t.task.is_none()));
+    dealloc(a, 10, 1);
+    dealloc(c, 100, 1);
+    assert!(live().is_empty());
+}
+
+#[test]
+fn follows_realloc() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    let ptr = realloc(ptr, 100, 1, 200);
+    let traces = live();
+    assert_eq!(traces.len(), 1);
+    assert_eq!((traces[0].ptr, traces[0].size), (ptr, 200));
+    // Moved to a bigger bucket
+    let ptr = realloc(ptr, 200, 1, 1000);
+    let traces = live();
+    assert_eq!(traces.len(), 1);
+    assert_eq!((traces[0].ptr, traces[0].size, traces[0].segment_size), (ptr, 1000, 1024));
+}
+
+#[test]
+fn segment_allocs_record_requested_size() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let (ptr, size) = mem::alloc_segment(100, 1).unwrap();
+    let traces = live();
+    assert_eq!(traces.len(), 1);
+    assert_eq!((traces[0].ptr, traces[0].size, traces[0].segment_size), (ptr, 100, 256));
+    unsafe {
+        mem::dealloc_segment(ptr, size, 1, 100);
+    }
+    assert!(live().is_empty());
+}
+
+#[test]
+fn counts_untraced_when_full() {
+    let _lock = setup(&[4 * 1024 * 1024], &[(64, 90), (usize::MAX, 10)]);
+    let ptrs: Vec<_> = (0..MAX_TRACED_ALLOCS + 10).map(|_| alloc(16, 1)).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+    assert_eq!(live().len(), MAX_TRACED_ALLOCS);
+    assert_eq!(trace::untraced_allocs(), 10);
+    // Freed slots are reused, and untraced allocations are freed without complaining
+    for ptr in ptrs {
+        dealloc(ptr, 16, 1);
+    }
+    assert!(live().is_empty());
+    let ptr = alloc(16, 1);
+    assert_eq!(live().len(), 1);
+    dealloc(ptr, 16, 1);
+}
+
+#[test]
+fn leak_report_groups_call_sites() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptrs: Vec<_> = [10, 20, 30].iter().map(|size| alloc(*size, 1)).collect();
+    let mut report = String::new();
+    trace::write_leak_report(&mut report, |_| true).unwrap();
+    // No callers on the host, so all allocations come from the same site
+    assert!(report.starts_with("Leak report: 3 live allocations, 60 bytes\n"), "{}", report);
+    assert!(report.contains("  3 allocs, 60 bytes, sizes 10..30, no task, at\n"), "{}", report);
+    assert!(report.ends_with("1 call sites, 0 allocations not traced\n"), "{}", report);
+
+    let mut report = String::new();
+    trace::write_leak_report(&mut report, |t| t.task == Some(1)).unwrap();
+    assert!(report.starts_with("Leak report: 0 live allocations, 0 bytes\n"), "{}", report);
+    for (ptr, size) in ptrs.into_iter().zip([10, 20, 30]) {
+        dealloc(ptr, size, 1);
+    }
+}
\ No newline at end of file
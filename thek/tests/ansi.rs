@@ -0,0 +1,122 @@
+//! ANSI parser tests, run on the host with the `test` feature (see `run_tests.sh`).
+
+use thek::devices::text::ansi::{
+    AnsiParser, AnsiAction, AnsiCommand, AnsiColor, SgrAttr
+};
+
+/// Feed a string to a new parser.
+/// * Return: printed chars and commands, in order.
+fn feed(s: &str) -> (String, Vec<AnsiCommand>) {
+    let mut parser = AnsiParser::new();
+    let mut printed = String::new();
+    let mut commands = Vec::new();
+    for ch in s.chars() {
+        match parser.feed(ch) {
+            AnsiAction::Print(ch) => printed.push(ch),
+            AnsiAction::Command(cmd) => commands.push(cmd),
+            AnsiAction::None => {}
+        }
+    }
+    (printed, commands)
+}
+
+/// Attributes of a single SGR sequence.
+fn sgr(s: &str) -> Vec<SgrAttr> {
+    match feed(s).1.as_slice() {
+        [AnsiCommand::Sgr(params)] => params.sgr_iter().collect(),
+        _ => panic!("Not a single SGR sequence: {:?}", s)
+    }
+}
+
+#[test]
+fn prints_plain_text() {
+    let (printed, commands) = feed("abc\nd");
+    assert_eq!(printed, "abc\nd");
+    assert!(commands.is_empty());
+}
+
+#[test]
+fn parses_csi_params() {
+    let (printed, commands) = feed("a\x1b[12;34Hb\x1b[5A\x1b[2J");
+    assert_eq!(printed, "ab");
+    assert!(matches!(
+        commands.as_slice(),
+        [AnsiCommand::CursorPosition(33, 11), AnsiCommand::CursorUp(5), AnsiCommand::EraseDisplay(_)]
+    ));
+    assert!(matches!(feed("\x1b[3;20r").1.as_slice(), [AnsiCommand::ScrollRegion(2, Some(19))]));
+}
+
+#[test]
+fn applies_defaults() {
+    // Missing, empty or zero parameters take the default value
+    assert!(matches!(feed("\x1b[H").1.as_slice(), [AnsiCommand::CursorPosition(0, 0)]));
+    assert!(matches!(feed("\x1b[;5H").1.as_slice(), [AnsiCommand::CursorPosition(4, 0)]));
+    assert!(matches!(feed("\x1b[7H").1.as_slice(), [AnsiCommand::CursorPosition(0, 6)]));
+    assert!(matches!(feed("\x1b[B").1.as_slice(), [AnsiCommand::CursorDown(1)]));
+    assert!(matches!(feed("\x1b[0C").1.as_slice(), [AnsiCommand::CursorForward(1)]));
+    assert!(matches!(feed("\x1b[r").1.as_slice(), [AnsiCommand::ScrollRegion(0, None)]));
+}
+
+#[test]
+fn parses_escape_sequences() {
+    assert!(matches!(feed("\x1b7\x1b8").1.as_slice(), [AnsiCommand::SaveCursor, AnsiCommand::RestoreCursor]));
+    assert!(matches!(feed("\x1b[s\x1b[u").1.as_slice(), [AnsiCommand::SaveCursor, AnsiCommand::RestoreCursor]));
+    // Private and unknown sequences are consumed, not printed
+    let (printed, commands) = feed("\x1b[?25lx\x1b[5zy\x1bZ");
+    assert_eq!(printed, "xy");
+    assert!(matches!(
+        commands.as_slice(),
+        [AnsiCommand::Unsupported, AnsiCommand::Unsupported, AnsiCommand::Unsupported]
+    ));
+}
+
+#[test]
+fn aborts_invalid_sequences() {
+    // A control char aborts the sequence, what follows is printed
+    let (printed, commands) = feed("\x1b[12\x07x\x1b[1;\u{e9}y");
+    assert_eq!(printed, "xy");
+    assert!(matches!(commands.as_slice(), [AnsiCommand::Unsupported, AnsiCommand::Unsupported]));
+}
+
+#[test]
+fn parses_sequences_split_between_writes() {
+    let mut parser = AnsiParser::new();
+    assert!(matches!(parser.feed('\x1b'), AnsiAction::None));
+    assert!(matches!(parser.feed('['), AnsiAction::None));
+    assert!(matches!(parser.feed('4'), AnsiAction::None));
+    assert!(matches!(parser.feed('D'), AnsiAction::Command(AnsiCommand::CursorBack(4))));
+    assert!(matches!(parser.feed('D'), AnsiAction::Print('D')));
+}
+
+#[test]
+fn parses_sgr_attributes() {
+    // No parameters means reset
+    assert!(matches!(sgr("\x1b[m").as_slice(), [SgrAttr::Reset]));
+    assert!(matches!(
+        sgr("\x1b[0;1;22;7;27;39;49m").as_slice(),
+        [
+            SgrAttr::Reset, SgrAttr::Bold, SgrAttr::Normal, SgrAttr::Inverse, SgrAttr::NotInverse,
+            SgrAttr::DefaultTextColor, SgrAttr::DefaultBgColor
+        ]
+    ));
+    assert!(matches!(
+        sgr("\x1b[31;44;92;107m").as_slice(),
+        [
+            SgrAttr::TextColor(AnsiColor::Red), SgrAttr::BgColor(AnsiColor::Blue),
+            SgrAttr::TextColor(AnsiColor::BrightGreen), SgrAttr::BgColor(AnsiColor::BrightWhite)
+        ]
+    ));
+    assert!(matches!(sgr("\x1b[5m").as_slice(), [SgrAttr::Unsupported]));
+}
+
+#[test]
+fn parses_256_color_sgr() {
+    assert!(matches!(
+        sgr("\x1b[38;5;196;48;5;17;1m").as_slice(),
+        [
+            SgrAttr::TextColor(AnsiColor::Color256(196)), SgrAttr::BgColor(AnsiColor::Color256(17)), SgrAttr::Bold
+        ]
+    ));
+    // True color is skipped with its parameters
+    assert!(matches!(sgr("\x1b[38;2;10;20;30;4;1m").as_slice(), [SgrAttr::Unsupported, SgrAttr::Unsupported, SgrAttr::Bold]));
+}
\ No newline at end of file
@@ -0,0 +1,86 @@
+//! Helpers shared by the host tests.
+
+// Each test file uses a different subset of the helpers
+#![allow(dead_code)]
+
+use std::{
+    alloc::{
+        GlobalAlloc, Layout
+    },
+    sync::{
+        Mutex, MutexGuard
+    }
+};
+
+use thek::mem::{
+    self, SpillPolicy,
+    init::setup_mem,
+    layout::{
+        MemBlockSet, MemBlockLayout
+    },
+    arch::{
+        add_host_mem, reset_raw_mem, raw_mem
+    }
+};
+
+/// The allocator state is global, tests can't run in parallel.
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub const SCHEMA: &[(usize, u8)] = &[(64, 25), (256, 25), (1024, 25), (usize::MAX, 25)];
+
+/// Set up a new memory schema over host regions of the given sizes.
+pub fn setup(regions: &[usize], schema: &[(usize, u8)]) -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    reset_raw_mem();
+    for size in regions {
+        add_host_mem(*size).expect("Too many regions");
+    }
+    mem::set_spill_policy(SpillPolicy::Spill);
+    setup_mem(schema);
+    guard
+}
+
+pub fn block_set() -> &'static MemBlockSet {
+    unsafe {
+        &*(raw_mem().0 as *const MemBlockSet)
+    }
+}
+
+pub fn blocks() -> &'static [MemBlockLayout] {
+    let block_set = block_set();
+    &block_set.block_layouts[..block_set.len()]
+}
+
+pub fn owner(ptr: *mut u8) -> &'static MemBlockLayout {
+    block_set().owns_segment(ptr).expect("Segment not owned by any block")
+}
+
+pub fn alloc(size: usize, align: usize) -> *mut u8 {
+    unsafe {
+        mem::test_allocator().alloc(Layout::from_size_align(size, align).unwrap())
+    }
+}
+
+pub fn dealloc(ptr: *mut u8, size: usize, align: usize) {
+    unsafe {
+        mem::test_allocator().dealloc(ptr, Layout::from_size_align(size, align).unwrap())
+    }
+}
+
+pub fn realloc(ptr: *mut u8, size: usize, align: usize, new_size: usize) -> *mut u8 {
+    unsafe {
+        mem::test_allocator().realloc(ptr, Layout::from_size_align(size, align).unwrap(), new_size)
+    }
+}
+
+pub fn fill(ptr: *mut u8, size: usize, value: u8) {
+    unsafe {
+        ptr.write_bytes(value, size);
+    }
+}
+
+pub fn check_filled(ptr: *mut u8, size: usize, value: u8) -> bool {
+    unsafe {
+        std::slice::from_raw_parts(ptr, size).iter().all(|b| *b == value)
+    }
+}
\ No newline at end of file
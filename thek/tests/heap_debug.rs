@@ -0,0 +1,87 @@
+//! Heap debugging tests, run on the host with the `test` and `heap-debug` features (see `run_tests.sh`).
+
+mod common;
+
+use common::*;
+
+use thek::mem::debug::{
+    FREE_PATTERN, RED_ZONE_PATTERN, RED_ZONE_SIZE
+};
+
+#[test]
+fn red_zone_after_allocation() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    // 64 bytes plus the red zone don't fit in the smallest segment
+    let ptr = alloc(64, 1);
+    assert_eq!(owner(ptr).segment_size, 256);
+    assert!(check_filled(unsafe { ptr.add(64) }, RED_ZONE_SIZE, RED_ZONE_PATTERN));
+    dealloc(ptr, 64, 1);
+}
+
+#[test]
+fn free_segment_is_poisoned() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    fill(ptr, 100, 0xAB);
+    dealloc(ptr, 100, 1);
+    assert!(check_filled(ptr, owner(ptr).segment_size, FREE_PATTERN));
+}
+
+#[test]
+fn realloc_moves_red_zone() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    let new_ptr = realloc(ptr, 100, 1, 200);
+    assert_eq!(ptr, new_ptr);
+    assert!(check_filled(unsafe { ptr.add(200) }, RED_ZONE_SIZE, RED_ZONE_PATTERN));
+    // Writing the whole new size doesn't touch the red zone
+    fill(ptr, 200, 0x11);
+    dealloc(ptr, 200, 1);
+}
+
+#[test]
+#[should_panic(expected = "Double free")]
+fn detects_double_free() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    dealloc(ptr, 100, 1);
+    dealloc(ptr, 100, 1);
+}
+
+#[test]
+#[should_panic(expected = "Heap buffer overflow")]
+fn detects_overflow() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    fill(ptr, 101, 0x22);
+    dealloc(ptr, 100, 1);
+}
+
+#[test]
+#[should_panic(expected = "written after free")]
+fn detects_write_after_free() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    dealloc(ptr, 100, 1);
+    fill(ptr, 1, 0x33);
+    // The free list is LIFO, so the same segment is allocated again
+    alloc(100, 1);
+}
+
+#[test]
+#[should_panic(expected = "Invalid heap pointer")]
+fn detects_invalid_free() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    dealloc(unsafe { ptr.add(16) }, 84, 1);
+}
+
+#[test]
+fn segment_alloc_keeps_red_zone() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let mut buffer = thek::mem::KBox::new(100).unwrap();
+    assert_eq!(buffer.size(), 256 - RED_ZONE_SIZE);
+    assert!(check_filled(buffer.top() as *mut u8, RED_ZONE_SIZE, RED_ZONE_PATTERN));
+    // Writing the whole buffer doesn't touch the red zone
+    buffer.fill(0x22);
+}
\ No newline at end of file
@@ -0,0 +1,416 @@
+//! Memory allocator tests, run on the host with the `test` feature (see `run_tests.sh`).
+//!
+//! They also run with the `heap-debug` feature (see `heap_debug.rs`). Red zones change the segment that serves each allocation, so then the segment size assertions are skipped, and usable sizes are [`RED_ZONE_SIZE`] smaller.
+
+mod common;
+
+use common::*;
+
+use std::{
+    collections::HashSet,
+    sync::atomic::Ordering
+};
+
+use thek::mem::{
+    self, SpillPolicy,
+    init::check_schema,
+    debug::{
+        HEAP_DEBUG, RED_ZONE_SIZE
+    },
+    layout::{
+        MemBlockSet, MAX_SCHEMA_BLOCKS
+    },
+    arch::raw_mem_regions
+};
+
+/// Allocations are served by the smallest segment that fits them. Not true with red zones, that take some bytes of the segment.
+const EXACT_SEGMENTS: bool = !HEAP_DEBUG;
+
+/// Allocate segments of `size` until the block that serves them is full.
+fn exhaust(size: usize) -> Vec<*mut u8> {
+    mem::set_spill_policy(SpillPolicy::Strict);
+    let mut ptrs = Vec::new();
+    loop {
+        let ptr = alloc(size, 1);
+        if ptr.is_null() {
+            break;
+        }
+        ptrs.push(ptr);
+    }
+    mem::set_spill_policy(SpillPolicy::Spill);
+    ptrs
+}
+
+#[test]
+fn blocks_dont_overlap() {
+    let _lock = setup(&[1024 * 1024, 4 * 1024 * 1024], SCHEMA);
+    let regions = raw_mem_regions();
+    let mut ranges = Vec::new();
+    let mut last_segment_size = 0;
+    for block in blocks() {
+        let start = block.links_ptr as usize;
+        let end = start + block.block_size;
+        let links_end = start + block.num_segments * size_of::<usize>();
+        let payload = block.payload_ptr as usize;
+        let payload_end = payload + block.num_segments * block.segment_size;
+        assert!(block.num_segments > 0);
+        assert!(links_end <= payload && payload_end <= end, "Block parts out of the block");
+        assert_eq!(payload % block.align, 0, "Payload not aligned");
+        assert!(block.segment_size >= last_segment_size || block.num_segments == 1, "Segment sizes not sorted");
+        last_segment_size = block.segment_size;
+        assert!(
+            regions.as_slice().iter().any(|r| start >= r.ptr as usize && end <= r.ptr as usize + r.size),
+            "Block out of the regions"
+        );
+        ranges.push((start, end));
+    }
+    // Block set struct is at the beginning of the first region
+    let block_set_ptr = block_set() as *const MemBlockSet as usize;
+    ranges.push((block_set_ptr, block_set_ptr + size_of::<MemBlockSet>()));
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        assert!(pair[0].1 <= pair[1].0, "Blocks overlap");
+    }
+}
+
+#[test]
+fn blocks_split_across_regions() {
+    let _lock = setup(&[64 * 1024, 1024 * 1024], &[(256, 90), (usize::MAX, 10)]);
+    let small_blocks = blocks().iter().filter(|b| b.segment_size == 256).count();
+    assert_eq!(small_blocks, 2);
+    let stats = mem::stats();
+    let total = stats.total_bytes();
+    // Some memory is lost in headers, links and alignment, but not much
+    assert!(total > (64 + 1024) * 1024 * 9 / 10, "Only {} bytes available", total);
+}
+
+#[test]
+fn selects_smallest_segment() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    for (size, segment_size) in [(1, 64), (10, 64), (64, 64), (65, 256), (256, 256), (1000, 1024), (1024, 1024)] {
+        let ptr = alloc(size, 1);
+        assert!(!ptr.is_null());
+        if EXACT_SEGMENTS {
+            assert_eq!(owner(ptr).segment_size, segment_size, "Bad segment for size {}", size);
+        }
+        dealloc(ptr, size, 1);
+    }
+    let ptr = alloc(5000, 1);
+    assert_eq!(owner(ptr).num_segments, 1);
+    dealloc(ptr, 5000, 1);
+}
+
+#[test]
+fn used_mem_after_each_alloc_and_dealloc() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let sizes = [10, 100, 500, 1000, 20, 64];
+    let mut ptrs = Vec::new();
+    let mut requested = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        ptrs.push(alloc(*size, 1));
+        requested += size;
+        let stats = mem::stats();
+        assert_eq!(stats.used_segments(), i + 1);
+        assert_eq!(stats.requested_bytes(), requested);
+        assert!(stats.reserved_bytes() >= requested);
+    }
+    assert_eq!(mem::stats().peak_segments, sizes.len());
+    for (i, (ptr, size)) in ptrs.iter().zip(sizes).enumerate() {
+        dealloc(*ptr, size, 1);
+        requested -= size;
+        let stats = mem::stats();
+        assert_eq!(stats.used_segments(), sizes.len() - i - 1);
+        assert_eq!(stats.requested_bytes(), requested);
+    }
+    assert_eq!(mem::stats().wasted_bytes(), 0);
+    assert_eq!(mem::stats().peak_segments, sizes.len());
+}
+
+#[test]
+fn spill_and_strict() {
+    let _lock = setup(&[256 * 1024], SCHEMA);
+    let small = exhaust(32);
+    assert!(!small.is_empty());
+    assert!(small.iter().all(|ptr| owner(*ptr).segment_size == 64));
+    let failed = mem::stats().failed_allocs;
+
+    mem::set_spill_policy(SpillPolicy::Strict);
+    assert!(alloc(32, 1).is_null());
+    assert_eq!(mem::stats().failed_allocs, failed + 1);
+
+    mem::set_spill_policy(SpillPolicy::Spill);
+    let ptr = alloc(32, 1);
+    assert_eq!(owner(ptr).segment_size, 256);
+    assert_eq!(mem::stats().spilled_allocs(), 1);
+}
+
+#[test]
+fn honors_alignment() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let mut align = 1;
+    while align <= 4096 {
+        let ptr = alloc(8, align);
+        assert!(!ptr.is_null(), "Could not allocate with alignment {}", align);
+        assert_eq!(ptr as usize % align, 0, "Bad alignment {}", align);
+        assert!(owner(ptr).align >= align);
+        dealloc(ptr, 8, align);
+        align *= 2;
+    }
+    // Bigger than any segment alignment
+    assert!(alloc(8, 8192).is_null());
+}
+
+#[test]
+#[should_panic(expected = "ordered from smaller segment size")]
+fn schema_rejects_unsorted_sizes() {
+    check_schema(&[(1024, 50), (256, 50)]);
+}
+
+#[test]
+#[should_panic(expected = "must be 100")]
+fn schema_rejects_wrong_sum() {
+    check_schema(&[(256, 50), (1024, 40)]);
+}
+
+#[test]
+#[should_panic(expected = "MAX_SCHEMA_BLOCKS")]
+fn schema_rejects_too_many_blocks() {
+    let schema: Vec<(usize, u8)> = (1..=MAX_SCHEMA_BLOCKS + 1).map(|i| (i * 64, 0)).collect();
+    check_schema(&schema);
+}
+
+#[test]
+fn realloc_grows_in_place() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    fill(ptr, 100, 0xAB);
+    let new_ptr = realloc(ptr, 100, 1, 200);
+    assert_eq!(ptr, new_ptr);
+    assert!(check_filled(new_ptr, 100, 0xAB));
+    assert_eq!(mem::stats().requested_bytes(), 200);
+    dealloc(new_ptr, 200, 1);
+    assert_eq!(mem::stats().requested_bytes(), 0);
+}
+
+#[test]
+fn realloc_shrinks_in_place() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(200, 1);
+    // 100 bytes would still go to the 256 bytes bucket
+    let new_ptr = realloc(ptr, 200, 1, 100);
+    assert_eq!(ptr, new_ptr);
+    assert_eq!(mem::stats().requested_bytes(), 100);
+    dealloc(new_ptr, 100, 1);
+    assert_eq!(mem::stats().requested_bytes(), 0);
+}
+
+#[test]
+fn realloc_moves_to_smaller_bucket() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(1000, 1);
+    fill(ptr, 1000, 0x5A);
+    let new_ptr = realloc(ptr, 1000, 1, 50);
+    assert_ne!(ptr, new_ptr);
+    if EXACT_SEGMENTS {
+        assert_eq!(owner(new_ptr).segment_size, 64);
+    }
+    assert!(check_filled(new_ptr, 50, 0x5A));
+    let stats = mem::stats();
+    assert_eq!(stats.used_segments(), 1);
+    assert_eq!(stats.requested_bytes(), 50);
+}
+
+#[test]
+fn realloc_stays_when_smaller_bucket_full() {
+    let _lock = setup(&[256 * 1024], SCHEMA);
+    let ptr = alloc(1000, 1);
+    let small = exhaust(32);
+    // Fits the full bucket, with or without red zone
+    let new_ptr = realloc(ptr, 1000, 1, 40);
+    assert_eq!(ptr, new_ptr);
+    assert_eq!(mem::stats().used_segments(), small.len() + 1);
+    assert_eq!(mem::stats().requested_bytes(), small.len() * 32 + 40);
+}
+
+#[test]
+fn realloc_grows_moving() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 16);
+    fill(ptr, 100, 0xC3);
+    let new_ptr = realloc(ptr, 100, 16, 1000);
+    assert_ne!(ptr, new_ptr);
+    assert_eq!(owner(new_ptr).segment_size, 1024);
+    assert_eq!(new_ptr as usize % 16, 0);
+    assert!(check_filled(new_ptr, 100, 0xC3));
+    let stats = mem::stats();
+    assert_eq!(stats.used_segments(), 1);
+    assert_eq!(stats.requested_bytes(), 1000);
+}
+
+#[test]
+fn realloc_grow_failure_keeps_buffer() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let ptr = alloc(100, 1);
+    fill(ptr, 100, 0x77);
+    assert!(realloc(ptr, 100, 1, 16 * 1024 * 1024).is_null());
+    assert!(check_filled(ptr, 100, 0x77));
+    let stats = mem::stats();
+    assert_eq!(stats.used_segments(), 1);
+    assert_eq!(stats.requested_bytes(), 100);
+    assert_eq!(stats.failed_allocs, 1);
+    dealloc(ptr, 100, 1);
+}
+
+#[test]
+fn segment_alloc_takes_whole_segment() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    assert_eq!(mem::usable_size(100, 1), Some(256 - RED_ZONE_SIZE));
+    let (ptr, size) = mem::alloc_segment(100, 1).unwrap();
+    assert_eq!(size, 256 - RED_ZONE_SIZE);
+    assert_eq!(owner(ptr).segment_size, 256);
+    // All the usable segment is requested, only the red zone is wasted
+    let stats = mem::stats();
+    assert_eq!(stats.requested_bytes(), 256 - RED_ZONE_SIZE);
+    assert_eq!(stats.wasted_bytes(), RED_ZONE_SIZE);
+    unsafe {
+        mem::dealloc_segment(ptr, size, 1, 100);
+    }
+    assert_eq!(mem::stats().used_segments(), 0);
+    assert_eq!(mem::stats().requested_bytes(), 0);
+}
+
+#[test]
+fn segment_alloc_reports_spilled_size() {
+    let _lock = setup(&[256 * 1024], SCHEMA);
+    let small = exhaust(32);
+    assert_eq!(mem::usable_size(32, 1), Some(64 - RED_ZONE_SIZE));
+    let (ptr, size) = mem::alloc_segment(32, 1).unwrap();
+    assert_eq!(size, 256 - RED_ZONE_SIZE);
+    unsafe {
+        mem::dealloc_segment(ptr, size, 1, 32);
+    }
+    for ptr in small {
+        dealloc(ptr, 32, 1);
+    }
+}
+
+#[test]
+fn kbox_fills_segment() {
+    let _lock = setup(&[1024 * 1024], SCHEMA);
+    let buffer = mem::KBox::new(700).unwrap();
+    assert_eq!(buffer.size(), 1024 - RED_ZONE_SIZE);
+    assert_eq!(buffer.len(), 1024 - RED_ZONE_SIZE);
+    assert_eq!(buffer.top() as usize - buffer.bottom() as usize, 1024 - RED_ZONE_SIZE);
+    assert!(buffer.iter().all(|b| *b == 0));
+
+    let mut words = mem::KBox::<u32>::new_slice(10).unwrap();
+    assert_eq!(words.len(), (64 - RED_ZONE_SIZE) / 4);
+    let last = words.len() - 1;
+    words[last] = 7;
+    assert_eq!(words.iter().sum::<u32>(), 7);
+    assert_eq!(mem::stats().requested_bytes(), 1024 + 64 - 2 * RED_ZONE_SIZE);
+    drop(buffer);
+    drop(words);
+    assert_eq!(mem::stats().used_segments(), 0);
+}
+
+/// Xorshift random number generator, good enough for fuzzing.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+struct Allocation {
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    value: u8
+}
+
+fn check_invariants(live: &[Allocation]) {
+    let mut segments = HashSet::new();
+    let mut used = vec![0; blocks().len()];
+    let mut requested = vec![0; blocks().len()];
+    for a in live {
+        assert_eq!(a.ptr as usize % a.align, 0, "Bad alignment");
+        let block = owner(a.ptr);
+        assert!(block.segment_size >= a.size, "Segment too small");
+        assert!(segments.insert(a.ptr), "Segment allocated twice");
+        assert!(check_filled(a.ptr, a.size, a.value), "Allocation content overwritten");
+        let index = blocks().iter().position(|b| std::ptr::eq(b, block)).unwrap();
+        used[index] += 1;
+        requested[index] += a.size;
+    }
+    for (i, block) in blocks().iter().enumerate() {
+        assert_eq!(block.used_segments.load(Ordering::Relaxed), used[i], "Bad used segments in block {}", i);
+        assert_eq!(block.requested_bytes.load(Ordering::Relaxed), requested[i], "Bad requested bytes in block {}", i);
+        assert!(block.peak_segments.load(Ordering::Relaxed) >= used[i]);
+    }
+}
+
+#[test]
+fn fuzz_alloc_free() {
+    let _lock = setup(&[512 * 1024, 2 * 1024 * 1024], SCHEMA);
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let mut live: Vec<Allocation> = Vec::new();
+    for step in 0..20000 {
+        match rng.below(10) {
+            // Alloc
+            0..=4 => {
+                let size = match rng.below(20) {
+                    0 => 1 + rng.below(64 * 1024),
+                    _ => 1 + rng.below(1100)
+                };
+                let align = [1, 8, 16, 64, 256][rng.below(5)];
+                let ptr = alloc(size, align);
+                if !ptr.is_null() {
+                    let value = rng.next() as u8;
+                    fill(ptr, size, value);
+                    live.push(Allocation { ptr, size, align, value });
+                }
+            },
+            // Free
+            5..=7 => {
+                if !live.is_empty() {
+                    let a = live.swap_remove(rng.below(live.len()));
+                    dealloc(a.ptr, a.size, a.align);
+                }
+            },
+            // Realloc
+            _ => {
+                if !live.is_empty() {
+                    let index = rng.below(live.len());
+                    let new_size = 1 + rng.below(2000);
+                    let a = &mut live[index];
+                    let ptr = realloc(a.ptr, a.size, a.align, new_size);
+                    if !ptr.is_null() {
+                        assert!(check_filled(ptr, a.size.min(new_size), a.value), "Realloc lost content");
+                        fill(ptr, new_size, a.value);
+                        a.ptr = ptr;
+                        a.size = new_size;
+                    }
+                }
+            }
+        }
+        if step % 100 == 0 {
+            check_invariants(&live);
+        }
+    }
+    check_invariants(&live);
+    for a in live.drain(..) {
+        dealloc(a.ptr, a.size, a.align);
+    }
+    let stats = mem::stats();
+    assert_eq!(stats.used_segments(), 0);
+    assert_eq!(stats.requested_bytes(), 0);
+}
\ No newline at end of file
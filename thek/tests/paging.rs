@@ -0,0 +1,108 @@
+//! Frame allocator tests, run on the host with the `test` feature (see `run_tests.sh`).
+//!
+//! Paging is not initialized, so physical addresses are host addresses: frames are host buffers.
+
+use std::sync::{
+    Mutex, MutexGuard
+};
+
+use thek::mem::paging::{
+    self, PAGE_SIZE, MAX_FRAME_REGIONS
+};
+
+/// The frame allocator is global, tests can't run in parallel.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Lock the frame allocator and reset it.
+fn setup() -> MutexGuard<'static, ()> {
+    let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    paging::reset_frames();
+    lock
+}
+
+/// Leak a host buffer of `count` frames.
+/// * Return: address of the first frame.
+fn host_frames(count: usize) -> u64 {
+    let buffer = vec![0u8; (count + 1) * PAGE_SIZE].leak();
+    let offset = buffer.as_ptr().align_offset(PAGE_SIZE);
+    buffer[offset..].as_ptr() as u64
+}
+
+fn alloc_all() -> Vec<u64> {
+    std::iter::from_fn(paging::alloc_frame).collect()
+}
+
+#[test]
+fn takes_frames_in_order() {
+    let _lock = setup();
+    let first = host_frames(2);
+    let second = host_frames(3);
+    unsafe {
+        assert!(paging::add_frames(first, 2 * PAGE_SIZE as u64));
+        assert!(paging::add_frames(second, 3 * PAGE_SIZE as u64));
+    }
+    assert_eq!(paging::free_frames(), 5);
+    let frames = alloc_all();
+    let page = PAGE_SIZE as u64;
+    assert_eq!(frames, [first, first + page, second, second + page, second + 2 * page]);
+    assert_eq!(paging::free_frames(), 0);
+}
+
+#[test]
+fn reuses_freed_frames_zeroed() {
+    let _lock = setup();
+    let start = host_frames(2);
+    unsafe {
+        paging::add_frames(start, 2 * PAGE_SIZE as u64);
+    }
+    let frames = alloc_all();
+    unsafe {
+        for frame in frames.iter() {
+            std::ptr::write_bytes(*frame as *mut u8, 0xAB, PAGE_SIZE);
+            paging::free_frame(*frame);
+        }
+    }
+    assert_eq!(paging::free_frames(), 2);
+    // Last freed, first reused
+    let frame = paging::alloc_frame().unwrap();
+    assert_eq!(frame, frames[1]);
+    let bytes = unsafe { std::slice::from_raw_parts(frame as *const u8, PAGE_SIZE) };
+    assert!(bytes.iter().all(|b| *b == 0));
+    assert_eq!(paging::alloc_frame(), Some(frames[0]));
+    assert_eq!(paging::alloc_frame(), None);
+}
+
+#[test]
+fn trims_regions_to_whole_frames() {
+    let _lock = setup();
+    let start = host_frames(4);
+    unsafe {
+        // Only the 2 frames fully inside the region are used
+        assert!(paging::add_frames(start + 100, 3 * PAGE_SIZE as u64));
+        assert!(!paging::add_frames(start + 100, PAGE_SIZE as u64));
+    }
+    assert_eq!(alloc_all(), [start + PAGE_SIZE as u64, start + 2 * PAGE_SIZE as u64]);
+}
+
+#[test]
+fn rejects_too_many_regions() {
+    let _lock = setup();
+    for _ in 0..MAX_FRAME_REGIONS {
+        assert!(unsafe { paging::add_frames(host_frames(1), PAGE_SIZE as u64) });
+    }
+    assert!(!unsafe { paging::add_frames(host_frames(1), PAGE_SIZE as u64) });
+    assert_eq!(paging::free_frames(), MAX_FRAME_REGIONS);
+}
+
+#[test]
+fn counts_exhaustion() {
+    let _lock = setup();
+    unsafe {
+        paging::add_frames(host_frames(1), PAGE_SIZE as u64);
+    }
+    assert!(paging::alloc_frame().is_some());
+    assert_eq!(paging::failed_frame_allocs(), 0);
+    assert_eq!(paging::alloc_frame(), None);
+    assert_eq!(paging::alloc_frame(), None);
+    assert_eq!(paging::failed_frame_allocs(), 2);
+}
\ No newline at end of file